@@ -0,0 +1,109 @@
+//! A local record of the blobs fstn has uploaded, by content.
+//!
+//! Kernels, runtimes and application images are large and often identical
+//! across gates. `blobs.toml` in the fstn config directory remembers the
+//! SHA-256 of every blob uploaded with [`crate::Fs::mkblob`], or as a gate's
+//! local image, so that a gate given a local image whose contents were
+//! uploaded before refers to the existing blob rather than uploading it
+//! again:
+//!
+//! ```toml
+//! [[blob]]
+//! server = "https://faasten.princeton.systems"
+//! user = "alice"
+//! path = "~:images:vmlinux"
+//! sha256 = "6923dd1bc0460082c5d55a831908c24a282860b7f1cd6c2b79cf1bc8857c639c"
+//! size = 6
+//! ```
+//!
+//! Blobs can be removed or replaced behind fstn's back, so an entry is only
+//! a hint: callers check that the blob still holds the recorded contents
+//! before using it, and [`BlobCache::forget`] the entry if it does not.
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::credentials::{config_dir, replace_locked};
+use crate::path::FsPath;
+use crate::Result;
+
+/// One uploaded blob.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedBlob {
+    pub server: String,
+    /// The user who uploaded the blob, whose home `~` in `path` is.
+    pub user: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub path: FsPath,
+    /// The SHA-256 of the contents, in hex.
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Blobs {
+    #[serde(default)]
+    blob: Vec<CachedBlob>,
+}
+
+/// A `blobs.toml` file.
+#[derive(Debug, Clone)]
+pub struct BlobCache {
+    path: PathBuf,
+}
+
+impl BlobCache {
+    pub fn new(path: impl Into<PathBuf>) -> BlobCache {
+        BlobCache { path: path.into() }
+    }
+
+    /// The `blobs.toml` file in [`config_dir`].
+    pub fn in_config_dir() -> BlobCache {
+        BlobCache::new(config_dir().join("blobs.toml"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every recorded blob, oldest first.
+    pub fn list(&self) -> Result<Vec<CachedBlob>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(toml::from_slice::<Blobs>(&bytes)?.blob),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Vec<CachedBlob>)) -> Result<()> {
+        replace_locked(&self.path, || {
+            let mut blobs = self.list()?;
+            change(&mut blobs);
+            Ok((toml::to_string(&Blobs { blob: blobs })?, ()))
+        })
+    }
+
+    /// The blobs `user` uploaded to `server` with the contents `sha256`,
+    /// newest first.
+    pub fn find(&self, server: &str, user: &str, sha256: &str) -> Result<Vec<FsPath>> {
+        let mut blobs = self.list()?;
+        blobs.retain(|b| b.server == server && b.user == user && b.sha256.eq_ignore_ascii_case(sha256));
+        Ok(blobs.into_iter().rev().map(|b| b.path).collect())
+    }
+
+    /// Records `blob`, replacing whatever was recorded at its path.
+    pub fn add(&self, blob: CachedBlob) -> Result<()> {
+        self.update(|blobs| {
+            blobs.retain(|b| (&b.server, &b.user, &b.path) != (&blob.server, &blob.user, &blob.path));
+            blobs.push(blob);
+        })
+    }
+
+    /// Drops the record of the blob at `path`, if any.
+    pub fn forget(&self, server: &str, user: &str, path: &FsPath) -> Result<()> {
+        self.update(|blobs| blobs.retain(|b| (b.server.as_str(), b.user.as_str(), &b.path) != (server, user, path)))
+    }
+}
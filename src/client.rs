@@ -0,0 +1,1427 @@
+//! A blocking client for the Faasten gateway.
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::multipart::{Form, Part};
+use reqwest::blocking::{Body, Response};
+use reqwest::Url;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cache::{BlobCache, CachedBlob};
+use crate::fsutil::{
+    DirEntry, EntryKind, FsFailure, FsReply, FsRequest, FsResponse, GateArgs, MkSvc, MkgateArgs, Stat, UpgateArgs,
+};
+use crate::label::{Component, Label};
+use crate::output::{Progress, ProgressReader};
+use crate::path::{FsPath, PathComponent, PathError};
+use crate::spool::spool;
+use crate::token::TokenInfo;
+use crate::{credentials, output, FstnError, Result};
+
+pub const DEFAULT_SERVER: &str = "https://faasten.princeton.systems";
+pub const DEFAULT_USER: &str = "default";
+
+/// How many bytes of a file [`Fs::write_from`] and [`Fs::read_to`] move per
+/// request by default.
+pub const DEFAULT_CHUNK_SIZE: u64 = 4 << 20;
+
+/// A connection to a Faasten gateway on behalf of a single user.
+#[derive(Debug, Clone)]
+pub struct Client {
+    server: String,
+    user: String,
+    token: Option<String>,
+    http: reqwest::blocking::Client,
+}
+
+impl Client {
+    /// Creates a client without a token. Only unauthenticated calls such as
+    /// [`Client::ping`] succeed until a token is set.
+    pub fn new(server: impl Into<String>, user: impl Into<String>) -> Result<Client> {
+        Ok(Client {
+            server: server.into(),
+            user: user.into(),
+            token: None,
+            http: reqwest::blocking::ClientBuilder::new().timeout(None).build()?,
+        })
+    }
+
+    /// Creates a client using the token saved for `user` on `server`, if any.
+    pub fn from_credentials(server: impl Into<String>, user: impl Into<String>) -> Result<Client> {
+        let mut client = Client::new(server, user)?;
+        client.token = match credentials::load(&client.server, &client.user) {
+            Ok(token) => Some(token),
+            Err(FstnError::NoCredentials { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(client)
+    }
+
+    /// Limits how long requests and connection attempts may take; `None`
+    /// means no limit.
+    pub fn with_timeouts(mut self, timeout: Option<Duration>, connect_timeout: Option<Duration>) -> Result<Client> {
+        let mut builder = reqwest::blocking::ClientBuilder::new().timeout(timeout);
+        if let Some(connect_timeout) = connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        self.http = builder.build()?;
+        Ok(self)
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Client {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// The bearer token, or an error asking the user to log in. Warns, once,
+    /// if the token says it has expired, since the gateway will only answer
+    /// 401.
+    pub fn token(&self) -> Result<&str> {
+        let token = self.token.as_deref().ok_or_else(|| FstnError::NoCredentials {
+            server: self.server.clone(),
+            user: self.user.clone(),
+        })?;
+        if let Some(ago) = TokenInfo::decode(token).and_then(|t| t.expires_in()).filter(|left| *left <= 0) {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                output::warn(&format!(
+                    "the token for {} on {} expired {} ago; run `fstn login` for a new one",
+                    self.user,
+                    self.server,
+                    output::age_string(ago.unsigned_abs())
+                ))
+            });
+        }
+        Ok(token)
+    }
+
+    /// The URL users visit to obtain a token by hand.
+    pub fn login_url(&self) -> String {
+        format!("{}/login/cas", self.server)
+    }
+
+    /// The `host[:port]` that `fstn://` paths use to name this gateway.
+    fn authority(&self) -> Option<String> {
+        let url = Url::parse(&self.server).ok()?;
+        let host = url.host_str()?;
+        Some(match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        })
+    }
+
+    /// Refuses a `fstn://` path that names some other gateway.
+    fn check_server(&self, path: &FsPath) -> Result<()> {
+        match path.server() {
+            Some(server) if Some(server) != self.authority().as_deref() => Err(FstnError::InvalidPath(format!(
+                "{} is on {}, but this client talks to {}; pass --server",
+                path, server, self.server
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        Url::parse(format!("{}{}", self.server, path).as_str())
+            .map_err(|e| FstnError::Config(format!("invalid server URL {}: {}", self.server, e)))
+    }
+
+    fn gate_url(&self, function: &str) -> Result<Url> {
+        let mut url = self.url("/faasten/invoke")?;
+        url.path_segments_mut()
+            .map_err(|_| FstnError::Config(format!("invalid server URL {}", self.server)))?
+            .push(function);
+        Ok(url)
+    }
+
+    fn post_invoke(&self, function: &str, payload: Body) -> Result<Response> {
+        Ok(self
+            .http
+            .post(self.gate_url(function)?)
+            .bearer_auth(self.token()?)
+            .header("content-type", "application/json")
+            .body(payload)
+            .send()?)
+    }
+
+    fn post_invoke_multipart(&self, function: &str, form: Form) -> Result<Response> {
+        Ok(self
+            .http
+            .post(self.gate_url(function)?)
+            .bearer_auth(self.token()?)
+            .multipart(form)
+            .send()?)
+    }
+
+    /// Invokes the gate at `function` with a JSON `payload`.
+    pub fn invoke(&self, function: &str, payload: impl Into<Body>) -> Result<Response> {
+        check(self.post_invoke(function, payload.into())?)
+    }
+
+    /// Invokes the gate at `function` with a multipart form, used when local
+    /// files are uploaded alongside the payload.
+    pub fn invoke_multipart(&self, function: &str, form: Form) -> Result<Response> {
+        check(self.post_invoke_multipart(function, form)?)
+    }
+
+    /// This client acting as `user`, with the token saved for them.
+    pub fn as_user(&self, user: &str) -> Result<Client> {
+        Ok(Client {
+            user: user.to_string(),
+            token: Some(credentials::load(&self.server, user)?),
+            ..self.clone()
+        })
+    }
+
+    /// Requests a token for `privilege` derived from the current token,
+    /// valid for `ttl` if given.
+    pub fn delegate(
+        &self,
+        privilege: &Component,
+        bootstrap: bool,
+        clearance: Option<&Label>,
+        ttl: Option<Duration>,
+    ) -> Result<String> {
+        let mut args = serde_json::json!({
+            "component": privilege,
+            "bootstrap": bootstrap,
+            "clearance": clearance,
+        });
+        if let Some(ttl) = ttl {
+            args["ttl"] = ttl.as_secs().into();
+        }
+        let response = self
+            .http
+            .post(self.url("/faasten/delegate")?)
+            .bearer_auth(self.token()?)
+            .header("content-type", "application/json")
+            .json(&args)
+            .send()?;
+        Ok(check(response)?.text()?)
+    }
+
+    /// Fetches the identity behind the current token.
+    pub fn whoami(&self) -> Result<Response> {
+        let response = self
+            .http
+            .get(self.url("/me")?)
+            .bearer_auth(self.token()?)
+            .header("content-type", "application/json")
+            .send()?;
+        check(response)
+    }
+
+    /// The identity behind the current token.
+    pub fn identity(&self) -> Result<Identity> {
+        let body = self.whoami()?.bytes()?;
+        let me: Me = serde_json::from_slice(&body).map_err(|e| FstnError::Protocol(format!("/me: {}", e)))?;
+        let home = FsPath::home_of(&me.login)?;
+        Ok(Identity {
+            login: me.login,
+            privilege: me.privilege,
+            clearance: me.clearance,
+            fsutil: home.child("fsutil"),
+            home,
+        })
+    }
+
+    /// Round-trip time to the gateway.
+    pub fn ping(&self) -> Result<Duration> {
+        let now = Instant::now();
+        check(self.http.get(self.url("/faasten/ping")?).send()?)?;
+        Ok(now.elapsed())
+    }
+
+    /// Round-trip time to the scheduler, via the gateway.
+    pub fn ping_scheduler(&self) -> Result<Duration> {
+        let now = Instant::now();
+        check(self.http.get(self.url("/faasten/ping/scheduler")?).send()?)?;
+        Ok(now.elapsed())
+    }
+
+    /// File system operations through the caller's `fsutil` gate, or through
+    /// the `fsutil` gate in `masquerade`'s home directory.
+    pub fn fs(&self, masquerade: Option<&str>) -> Result<Fs<'_>> {
+        let home = match masquerade {
+            Some(user) => FsPath::home_of(user)?,
+            None => FsPath::home(),
+        };
+        Ok(Fs { client: self, gate: home.child("fsutil"), blobs: None })
+    }
+}
+
+/// An entry found by [`Fs::walk`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ListedEntry {
+    pub path: FsPath,
+    /// How many directories below the listed one the entry is.
+    pub depth: usize,
+    #[serde(flatten)]
+    pub entry: DirEntry,
+    /// Whether the entry is a directory that could not be listed, so that
+    /// what is below it is unknown.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unlisted: bool,
+}
+
+/// A facet of a faceted directory, as found by [`Fs::facets`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Facet {
+    pub path: FsPath,
+    pub label: Label,
+    /// Whether the caller's clearance lets them see into the facet, if the
+    /// gateway says what their clearance is.
+    pub visible: Option<bool>,
+}
+
+/// The reply of `/me`.
+#[derive(Debug, Deserialize)]
+struct Me {
+    login: String,
+    #[serde(default)]
+    privilege: Option<Component>,
+    #[serde(default)]
+    clearance: Option<Label>,
+}
+
+/// Who a token acts for, and where their files are.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Identity {
+    pub login: String,
+    pub privilege: Option<Component>,
+    pub clearance: Option<Label>,
+    /// The user's home directory, the facet of `home` labeled with their
+    /// login.
+    pub home: FsPath,
+    /// The user's `fsutil` gate, which `fstn fs` commands are sent to.
+    pub fsutil: FsPath,
+}
+
+/// A function image given either as a path in the Faasten file system or,
+/// when prefixed with `@`, as a local file to upload as a blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    Remote(FsPath),
+    Local(PathBuf),
+}
+
+impl FromStr for ImageSource {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.strip_prefix('@') {
+            Some(local) => ImageSource::Local(local.into()),
+            None => ImageSource::Remote(s.parse()?),
+        })
+    }
+}
+
+/// What a gate runs: either a function built from images, or another gate.
+#[derive(Debug, Clone, Default)]
+pub struct GateFunction {
+    pub memory: Option<u64>,
+    pub app_image: Option<ImageSource>,
+    pub kernel: Option<ImageSource>,
+    pub runtime: Option<ImageSource>,
+    pub gate: Option<FsPath>,
+}
+
+/// Turns a non-success reply into an error carrying its status and body.
+fn check(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        Err(FstnError::from_status(status, response.text().unwrap_or_default()))
+    }
+}
+
+/// A part streaming the file at `path`, with a progress bar.
+fn blob_part(path: &Path, file_name: String) -> Result<Part> {
+    let file = std::fs::File::open(path)?;
+    let size = file.metadata()?.len();
+    let reader = ProgressReader::new(file, Progress::new(file_name.clone(), size));
+    Ok(Part::reader_with_length(reader, size)
+        .mime_str("application/octet-stream")?
+        .file_name(file_name))
+}
+
+/// How many times a piece of an upload is retried after the connection
+/// drops before giving up.
+const UPLOAD_RETRIES: u32 = 3;
+
+/// Operations on the Faasten file system, performed by an `fsutil` gate.
+#[derive(Debug, Clone)]
+pub struct Fs<'a> {
+    client: &'a Client,
+    gate: FsPath,
+    blobs: Option<BlobCache>,
+}
+
+impl<'a> Fs<'a> {
+    /// Records uploaded blobs in `cache`, and refers gates to them instead
+    /// of uploading local images with the same contents again.
+    pub fn with_blob_cache(mut self, cache: BlobCache) -> Fs<'a> {
+        self.blobs = Some(cache);
+        self
+    }
+
+    /// The path of the `fsutil` gate requests are sent to.
+    pub fn gate(&self) -> &FsPath {
+        &self.gate
+    }
+
+    /// The gateway the file system is on.
+    pub fn server(&self) -> &str {
+        self.client.server()
+    }
+
+    /// Sends `request` to the `fsutil` gate and decodes the reply.
+    pub fn request(&self, request: &FsRequest) -> Result<FsResponse> {
+        self.send(request)
+            .and_then(|reply| Ok(request.decode(reply)?))
+            .map_err(|e| self.hidden_facet(request, e))
+    }
+
+    /// Like [`Fs::request`], but uploads the blobs in `form` along with it.
+    pub fn request_multipart(&self, request: &FsRequest, form: Form) -> Result<FsResponse> {
+        self.check_servers(request)?;
+        let form = form.text("payload", serde_json::to_string(request)?);
+        let response = self.client.post_invoke_multipart(&self.gate.to_string(), form)?;
+        Self::reply(request, response)
+            .and_then(|reply| Ok(request.decode(reply)?))
+            .map_err(|e| self.hidden_facet(request, e))
+    }
+
+    /// Explains a failed `request` by the first facet on its paths that the
+    /// caller's clearance does not let them see, if there is one; gates
+    /// report such facets as failures with no reason, or not at all.
+    fn hidden_facet(&self, request: &FsRequest, error: FstnError) -> FstnError {
+        if !matches!(error, FstnError::Fs(_) | FstnError::NotFound(_)) {
+            return error;
+        }
+        let mut facets = Vec::new();
+        for path in request.paths() {
+            let mut prefix = FsPath::root();
+            for component in path.components() {
+                prefix = prefix.join(component.clone());
+                if let PathComponent::Facet(label) = component {
+                    facets.push((prefix.clone(), label));
+                }
+            }
+        }
+        if facets.is_empty() {
+            return error;
+        }
+        let Ok(Identity { clearance: Some(clearance), .. }) = self.client.identity() else {
+            return error;
+        };
+        match facets.into_iter().find(|(_, label)| !label.flows_to(&clearance)) {
+            Some((facet, label)) => FstnError::LabelViolation(format!(
+                "{} is labeled {}, which your clearance {} cannot see",
+                facet, label, clearance
+            )),
+            None => error,
+        }
+    }
+
+    /// Sends `request` to the `fsutil` gate and returns the undecoded reply.
+    fn send(&self, request: &FsRequest) -> Result<FsReply> {
+        self.check_servers(request)?;
+        let response = self.client.post_invoke(&self.gate.to_string(), serde_json::to_vec(request)?.into())?;
+        Self::reply(request, response)
+    }
+
+    fn check_servers(&self, request: &FsRequest) -> Result<()> {
+        request.paths().into_iter().try_for_each(|path| self.client.check_server(path))
+    }
+
+    fn reply(request: &FsRequest, response: Response) -> Result<FsReply> {
+        let status = response.status();
+        let body = response.bytes()?;
+        match serde_json::from_slice::<FsReply>(&body) {
+            Ok(reply) => Ok(reply),
+            Err(_) if !status.is_success() => {
+                Err(FstnError::from_status(status, String::from_utf8_lossy(&body).into_owned()))
+            }
+            Err(e) => Err(FstnError::Protocol(format!("{}: {}", request.op(), e))),
+        }
+    }
+
+    /// Leaves remote images as paths and replaces local ones with blobs
+    /// holding their contents, uploading them into `base` with `label` where
+    /// there are none yet. Local images go into `form` instead if there is
+    /// no `label` for the blob or the gate does not support `upload`.
+    fn images(
+        &self,
+        function: GateFunction,
+        base: &FsPath,
+        label: Option<&Label>,
+        mut form: Form,
+    ) -> Result<(GateArgs, Form)> {
+        let mut args = GateArgs {
+            memory: function.memory,
+            gate: function.gate,
+            ..Default::default()
+        };
+        for (name, image, arg) in [
+            ("app_image", function.app_image, &mut args.app_image),
+            ("kernel", function.kernel, &mut args.kernel),
+            ("runtime", function.runtime, &mut args.runtime),
+        ] {
+            match image {
+                Some(ImageSource::Local(local)) => match self.image_blob(&local, name, base, label)? {
+                    Some(path) => *arg = Some(path),
+                    None => form = form.part("blob", blob_part(&local, name.to_string())?),
+                },
+                Some(ImageSource::Remote(path)) => *arg = Some(path),
+                None => {}
+            }
+        }
+        Ok((args, form))
+    }
+
+    /// Round-trip time through the `fsutil` gate.
+    pub fn ping(&self) -> Result<Duration> {
+        let start = Instant::now();
+        self.request(&FsRequest::Ping {})?;
+        Ok(start.elapsed())
+    }
+
+    pub fn ls(&self, path: &FsPath) -> Result<Vec<DirEntry>> {
+        match self.request(&FsRequest::Ls { path: path.clone() }).map_err(not_found(path))? {
+            FsResponse::Listing(entries) => Ok(entries),
+            other => Err(unexpected("ls", other)),
+        }
+    }
+
+    /// Lists `path` and, down to `depth` levels in all (without limit if
+    /// `None`), the directories and faceted directories below it, in
+    /// pre-order with siblings arranged by `order`. Subdirectories that
+    /// cannot be listed are skipped with a warning and marked
+    /// [`ListedEntry::unlisted`].
+    pub fn walk(
+        &self,
+        path: &FsPath,
+        depth: Option<usize>,
+        order: impl Fn(&DirEntry, &DirEntry) -> std::cmp::Ordering,
+    ) -> Result<Vec<ListedEntry>> {
+        let mut entries = self.ls(path)?;
+        entries.sort_by(&order);
+        let faceted = is_facet_listing(&entries);
+        // Entries still to visit, with their directory, whether it is
+        // faceted, and their depth; the next one to visit is on top.
+        let mut stack: Vec<_> = entries.into_iter().rev().map(|e| (path.clone(), e, faceted, 0)).collect();
+        let mut listed = Vec::new();
+        while let Some((base, entry, faceted, level)) = stack.pop() {
+            let child = child_path(&base, &entry, faceted);
+            let kind = entry.kind;
+            listed.push(ListedEntry { path: child.clone(), depth: level, entry, unlisted: false });
+            if !kind.is_some_and(EntryKind::is_directory) || depth.is_some_and(|d| level + 1 >= d) {
+                continue;
+            }
+            match self.ls(&child) {
+                Ok(mut entries) => {
+                    entries.sort_by(&order);
+                    let faceted = kind == Some(EntryKind::Faceted);
+                    stack.extend(entries.into_iter().rev().map(|e| (child.clone(), e, faceted, level + 1)));
+                }
+                Err(e) => {
+                    output::warn(&format!("cannot list {}: {}", child, e));
+                    if let Some(last) = listed.last_mut() {
+                        last.unlisted = true;
+                    }
+                }
+            }
+        }
+        Ok(listed)
+    }
+
+    /// The entry at `path`, as listed in its directory. `~` and the root are
+    /// directories with no label of their own to report, and a facet is a
+    /// directory labeled with its label.
+    pub fn lookup(&self, path: &FsPath) -> Result<DirEntry> {
+        let directory = |label| DirEntry { name: String::new(), kind: Some(EntryKind::Directory), label };
+        let name = match path.last() {
+            None | Some(PathComponent::Home) => return Ok(directory(None)),
+            Some(PathComponent::Facet(label)) => return Ok(directory(Some(label.clone()))),
+            Some(PathComponent::Name(name) | PathComponent::Gate(name) | PathComponent::Blob(name)) => name,
+        };
+        let parent = path.parent().unwrap_or_default();
+        self.ls(&parent)?
+            .into_iter()
+            .find(|e| &e.name == name)
+            .ok_or_else(|| FstnError::NotFound(path.to_string()))
+    }
+
+    /// The facets of the faceted directory at `path`, in order of their
+    /// labels, and whether the caller may see into each.
+    pub fn facets(&self, path: &FsPath) -> Result<Vec<Facet>> {
+        let kind = self.lookup(path)?.kind;
+        if kind.is_some_and(|k| k != EntryKind::Faceted) {
+            let kind = kind.map_or("entry", EntryKind::name);
+            return Err(FstnError::InvalidPath(format!("{} is a {}, not a faceted directory", path, kind)));
+        }
+        let clearance = self.client.identity()?.clearance;
+        let mut facets: Vec<_> = self
+            .ls(path)?
+            .iter()
+            .filter_map(|entry| {
+                let path = child_path(path, entry, true);
+                let Some(PathComponent::Facet(label)) = path.last() else { return None };
+                let label = label.clone();
+                let visible = clearance.as_ref().map(|clearance| label.flows_to(clearance));
+                Some(Facet { path, label, visible })
+            })
+            .collect();
+        facets.sort_by(|a, b| a.label.cmp(&b.label));
+        Ok(facets)
+    }
+
+    /// `path` with the facet labeled `label` entered in each faceted
+    /// directory along it, including at its end, that it does not already
+    /// name a facet of.
+    pub fn select_facet(&self, path: &FsPath, label: &Label) -> Result<FsPath> {
+        // The path is rebuilt without its server, which is this one.
+        self.client.check_server(path)?;
+        let mut selected = FsPath::root();
+        let mut components = path.components().iter().peekable();
+        while let Some(component) = components.next() {
+            selected = selected.join(component.clone());
+            let named = matches!(components.peek(), Some(PathComponent::Facet(_)));
+            let entered = matches!(component, PathComponent::Name(_));
+            if entered && !named && self.lookup(&selected)?.kind == Some(EntryKind::Faceted) {
+                selected = selected.join(PathComponent::Facet(label.clone()));
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Creates the directory `path` and any missing directories above it,
+    /// all labeled `label`, returning the ones created. Facets, `~` and the
+    /// root are taken to exist.
+    pub fn mkdir_all(&self, path: &FsPath, label: &Label) -> Result<Vec<FsPath>> {
+        let mut created = Vec::new();
+        let mut parent = FsPath::root();
+        for component in path.components() {
+            let current = parent.clone().join(component.clone());
+            match component {
+                PathComponent::Home | PathComponent::Facet(_) => {}
+                PathComponent::Name(name) => {
+                    let exists = match created.is_empty() {
+                        true => self.ls(&parent)?.into_iter().find(|e| &e.name == name),
+                        false => None,
+                    };
+                    match exists.map(|e| e.kind) {
+                        None => {
+                            self.mkdir(&parent, name, label)?;
+                            created.push(current.clone());
+                        }
+                        Some(Some(kind)) if kind.is_directory() => {}
+                        Some(kind) => {
+                            let kind = kind.map_or("entry of unknown kind", EntryKind::name);
+                            return Err(FstnError::InvalidPath(format!("{} is a {}, not a directory", current, kind)));
+                        }
+                    }
+                }
+                PathComponent::Gate(_) | PathComponent::Blob(_) => {
+                    return Err(FstnError::InvalidPath(format!("{} cannot be a directory", current)));
+                }
+            }
+            parent = current;
+        }
+        Ok(created)
+    }
+
+    /// Unlinks `path` and, if it is a directory, everything below it, deepest
+    /// entries first, returning what was removed. `confirm` sees what would
+    /// be removed first, and nothing is if it says no. Facets themselves
+    /// cannot be unlinked, only what is in them, and neither can `~`, the
+    /// root or the `fsutil` gate in use.
+    pub fn remove_all(&self, path: &FsPath, confirm: impl FnOnce(&[FsPath]) -> bool) -> Result<Vec<FsPath>> {
+        if matches!(path.last(), None | Some(PathComponent::Home)) {
+            return Err(FstnError::InvalidPath(format!("refusing to remove {}", path)));
+        }
+        let entry = self.lookup(path)?;
+        let mut doomed = vec![path.clone()];
+        if entry.kind.is_some_and(EntryKind::is_directory) {
+            doomed.extend(self.walk(path, None, |a, b| a.name.cmp(&b.name))?.into_iter().map(|e| e.path));
+        }
+        doomed.retain(|p| linked_name(p).is_some());
+        if let Some(gate) = doomed.iter().find(|p| **p == self.gate) {
+            return Err(FstnError::InvalidPath(format!("refusing to remove the fsutil gate {}", gate)));
+        }
+        doomed.reverse();
+        if doomed.is_empty() || !confirm(&doomed) {
+            return Ok(Vec::new());
+        }
+        let mut removed = Vec::new();
+        for doomed in doomed {
+            if let Some((parent, name)) = linked_name(&doomed) {
+                self.unlink(&parent, name).map_err(not_found(&doomed))?;
+                removed.push(doomed);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Copies the entry at `src` to `base:name`, and with `recursive`
+    /// everything below it, returning the entries created. Entries keep
+    /// their labels unless `label` is given, which may not be less secret
+    /// than theirs. Existing files and blobs are overwritten and existing
+    /// directories filled in, so an interrupted copy can be run again.
+    /// Gates cannot rename entries, so an existing blob is unlinked before
+    /// its replacement is made: if the copy stops in between, that blob is
+    /// missing until it is run again. Gates and services cannot be copied
+    /// and are skipped with a warning. Nothing is copied from a tree with a
+    /// directory that cannot be listed, which the copy would leave out.
+    pub fn copy(
+        &self,
+        src: &FsPath,
+        base: &FsPath,
+        name: &str,
+        label: Option<&Label>,
+        recursive: bool,
+    ) -> Result<Vec<FsPath>> {
+        let entry = self.lookup(src)?;
+        let dst = base.child(name);
+        if dst.components().starts_with(src.components()) {
+            return Err(FstnError::InvalidPath(format!("cannot copy {} into itself", src)));
+        }
+        let directory = entry.kind.is_some_and(EntryKind::is_directory);
+        if directory && !recursive {
+            return Err(FstnError::InvalidPath(format!("{} is a directory; use -r to copy it", src)));
+        }
+        let below = match directory {
+            true => complete(src, "copied", self.walk(src, None, |a, b| a.name.cmp(&b.name))?)?,
+            false => Vec::new(),
+        };
+        let mut copy = Copy { fs: self, label, listings: HashMap::new(), created: Vec::new() };
+        if !copy.entry(src, &entry, base, name)? || !directory {
+            return Ok(copy.created);
+        }
+        let depth = src.components().len();
+        let mut skipped: Vec<FsPath> = Vec::new();
+        for ListedEntry { path, entry, .. } in below {
+            if skipped.iter().any(|s| path.components().starts_with(s.components())) {
+                continue;
+            }
+            let target = path.components()[depth..].iter().fold(dst.clone(), |p, c| p.join(c.clone()));
+            // Facets need no creating: they exist once the faceted directory does.
+            if let Some((parent, name)) = linked_name(&target) {
+                if !copy.entry(&path, &entry, &parent, name)? {
+                    skipped.push(path);
+                }
+            }
+        }
+        Ok(copy.created)
+    }
+
+    /// Copies the entry at `src` to `base:name` and then unlinks `src`,
+    /// returning the entries created, or `None` if there was nothing to
+    /// move. Entries keep their labels. A directory holding gates or
+    /// services cannot be moved, since they cannot be copied, nor one with a
+    /// directory below it that cannot be listed; `src` is only unlinked once
+    /// everything in it has been copied. If `src` is already gone but
+    /// `base:name` exists, the move is taken to have been done.
+    pub fn rename(&self, src: &FsPath, base: &FsPath, name: &str) -> Result<Option<Vec<FsPath>>> {
+        let (parent, src_name) = linked_name(src)
+            .ok_or_else(|| FstnError::InvalidPath(format!("{} cannot be moved", src)))?;
+        let entry = match self.lookup(src) {
+            Err(FstnError::NotFound(_)) if self.lookup(&base.child(name)).is_ok() => return Ok(None),
+            entry => entry?,
+        };
+        let mut entries = vec![(src.clone(), entry.kind)];
+        if entry.kind.is_some_and(EntryKind::is_directory) {
+            let below = complete(src, "moved", self.walk(src, None, |a, b| a.name.cmp(&b.name))?)?;
+            entries.extend(below.into_iter().map(|e| (e.path, e.entry.kind)));
+        }
+        if let Some((path, kind)) = entries.into_iter().find(|(_, kind)| !copyable(*kind)) {
+            let kind = kind.map_or("entry of unknown kind", EntryKind::name);
+            let message = format!("cannot move {}: {} is a {}, which cannot be copied", src, path, kind);
+            return Err(FstnError::InvalidPath(message));
+        }
+        let created = self.copy(src, base, name, None, true)?;
+        self.unlink(&parent, src_name).map_err(not_found(src))?;
+        Ok(Some(created))
+    }
+
+    /// Makes `base:name` a gate that runs the gate at `target`, or points an
+    /// existing gate there, returning the gate if it was created. The new
+    /// gate has the target's label unless `label` is given.
+    pub fn link(
+        &self,
+        target: &FsPath,
+        base: &FsPath,
+        name: &str,
+        label: Option<&Label>,
+        privilege: &Component,
+        clearance: &Label,
+    ) -> Result<Vec<FsPath>> {
+        let entry = self.lookup(target)?;
+        if entry.kind != Some(EntryKind::Gate) {
+            let kind = entry.kind.map_or("entry of unknown kind", EntryKind::name);
+            return Err(FstnError::InvalidPath(format!("{} is a {}; only gates can be linked", target, kind)));
+        }
+        let path = base.child(name);
+        let function = GateFunction { gate: Some(target.clone()), ..Default::default() };
+        match self.ls(base)?.into_iter().find(|e| e.name == name).map(|e| e.kind) {
+            None => {
+                let label = label.or(entry.label.as_ref()).cloned().unwrap_or_default();
+                self.mkgate(base, name, &label, privilege, clearance, function)?;
+                Ok(vec![path])
+            }
+            Some(Some(EntryKind::Gate)) => {
+                self.upgate(&path, Some(privilege), Some(clearance), function)?;
+                Ok(Vec::new())
+            }
+            Some(kind) => {
+                let kind = kind.map_or("entry of unknown kind", EntryKind::name);
+                Err(FstnError::InvalidPath(format!("{} is a {}, not a gate", path, kind)))
+            }
+        }
+    }
+
+    pub fn unlink(&self, base: &FsPath, name: &str) -> Result<FsResponse> {
+        self.request(&FsRequest::Unlink { base: base.clone(), name: name.to_string() })
+    }
+
+    pub fn mkdir(&self, base: &FsPath, name: &str, label: &Label) -> Result<FsResponse> {
+        self.request(&FsRequest::Mkdir {
+            base: base.clone(),
+            name: name.to_string(),
+            label: label.clone(),
+        })
+    }
+
+    pub fn mkfile(&self, base: &FsPath, name: &str, label: &Label) -> Result<FsResponse> {
+        self.request(&FsRequest::Mkfile {
+            base: base.clone(),
+            name: name.to_string(),
+            label: label.clone(),
+        })
+    }
+
+    pub fn write(&self, path: &FsPath, data: Vec<u8>) -> Result<FsResponse> {
+        self.request(&FsRequest::Write { path: path.clone(), data, offset: None })
+    }
+
+    /// Writes everything `reader` yields to the file at `path`, sending at
+    /// most `chunk_size` bytes per request, and returns the reply to the
+    /// last request. Input that fits in one chunk is written with a plain
+    /// `write`; longer input is written a chunk at a time at increasing
+    /// offsets if the gate supports them, and otherwise with a single plain
+    /// `write` after all.
+    pub fn write_from(&self, path: &FsPath, reader: &mut dyn Read, chunk_size: u64) -> Result<FsResponse> {
+        let mut read_chunk = || -> Result<Vec<u8>> {
+            let mut data = Vec::new();
+            (&mut *reader).take(chunk_size).read_to_end(&mut data)?;
+            Ok(data)
+        };
+        // One chunk is read ahead, so that the last is known to be last.
+        let mut data = read_chunk()?;
+        let mut next = read_chunk()?;
+        if next.is_empty() {
+            return self.write(path, data);
+        }
+        if !self.writes_at_offsets(path)? {
+            data.append(&mut next);
+            reader.read_to_end(&mut data)?;
+            return self.write(path, data);
+        }
+        let mut offset = 0;
+        loop {
+            let len = data.len() as u64;
+            let response = self.request(&FsRequest::Write { path: path.clone(), data, offset: Some(offset) })?;
+            offset += len;
+            let size = match &response {
+                FsResponse::Done(Some(value)) => value["size"].as_u64(),
+                _ => None,
+            };
+            if size != Some(offset) {
+                let size = size.map_or("no size".to_string(), |size| format!("{} bytes", size));
+                return Err(FstnError::Protocol(format!(
+                    "write: {} reported {} for {} after {} bytes were written",
+                    self.gate, size, path, offset
+                )));
+            }
+            if next.is_empty() {
+                return Ok(response);
+            }
+            data = std::mem::replace(&mut next, read_chunk()?);
+        }
+    }
+
+    /// Whether the gate supports writing at an offset, found by writing
+    /// nothing at offset 0 to the file at `path`. That empties the file on
+    /// any gate, so it is only done before `path` is written in full.
+    fn writes_at_offsets(&self, path: &FsPath) -> Result<bool> {
+        let request = FsRequest::Write { path: path.clone(), data: Vec::new(), offset: Some(0) };
+        Ok(match self.request(&request)? {
+            FsResponse::Done(Some(value)) => value["size"].as_u64() == Some(0),
+            _ => false,
+        })
+    }
+
+    /// The contents of the file at `path`.
+    pub fn read(&self, path: &FsPath) -> Result<Vec<u8>> {
+        let request = FsRequest::Read { path: path.clone(), offset: None, length: None };
+        match self.request(&request).map_err(not_found(path))? {
+            FsResponse::Contents(contents) => Ok(contents),
+            other => Err(unexpected("read", other)),
+        }
+    }
+
+    /// Copies the contents of the file at `path` into `writer`, asking for at
+    /// most `chunk_size` bytes per request and decoding each chunk as it is
+    /// copied, and returns the number of bytes copied. Gates that ignore the
+    /// requested range send the whole file in one go.
+    pub fn read_to(&self, path: &FsPath, writer: &mut dyn Write, chunk_size: u64) -> Result<u64> {
+        let mut offset = 0;
+        loop {
+            let request = FsRequest::Read { path: path.clone(), offset: Some(offset), length: Some(chunk_size) };
+            let chunk = self
+                .send(&request)
+                .and_then(|reply| Ok(request.decode_chunk(reply)?))
+                .map_err(|e| self.hidden_facet(&request, e))
+                .map_err(not_found(path))?;
+            let mut encoded = chunk.data.as_bytes();
+            let mut decoder = base64::read::DecoderReader::new(&mut encoded, base64::STANDARD);
+            let len = std::io::copy(&mut decoder, writer).map_err(|e| match e.get_ref() {
+                Some(inner) if inner.is::<base64::DecodeError>() => {
+                    FstnError::Protocol(format!("read: malformed contents: {}", inner))
+                }
+                _ => e.into(),
+            })?;
+            match chunk.size {
+                None if offset == 0 => return Ok(len),
+                None => return Err(FstnError::Protocol(format!("read: {} stopped honoring the range", self.gate))),
+                Some(size) if offset + len >= size => return Ok(offset + len),
+                Some(_) if len == 0 => {
+                    return Err(FstnError::Protocol(format!("read: {} sent an empty chunk at {}", self.gate, offset)))
+                }
+                Some(_) => offset += len,
+            }
+        }
+    }
+
+    pub fn mkgate(
+        &self,
+        base: &FsPath,
+        name: &str,
+        label: &Label,
+        privilege: &Component,
+        clearance: &Label,
+        function: GateFunction,
+    ) -> Result<FsResponse> {
+        let (function, form) = self.images(function, base, Some(label), Form::new())?;
+        let request = FsRequest::Mkgate(MkgateArgs {
+            label: label.clone(),
+            privilege: privilege.clone(),
+            clearance: clearance.clone(),
+            base: base.clone(),
+            name: name.to_string(),
+            function,
+        });
+        self.request_multipart(&request, form)
+    }
+
+    pub fn upgate(
+        &self,
+        path: &FsPath,
+        privilege: Option<&Component>,
+        clearance: Option<&Label>,
+        function: GateFunction,
+    ) -> Result<FsResponse> {
+        // Images are uploaded next to the gate, with its label.
+        let local = [&function.app_image, &function.kernel, &function.runtime]
+            .into_iter()
+            .any(|image| matches!(image, Some(ImageSource::Local(_))));
+        let label = match local {
+            true => self.lookup(path)?.label,
+            false => None,
+        };
+        let base = path.parent().unwrap_or_default();
+        let (function, form) = self.images(function, &base, label.as_ref(), Form::new())?;
+        let request = FsRequest::Upgate(UpgateArgs {
+            privilege: privilege.cloned(),
+            clearance: clearance.cloned(),
+            path: path.clone(),
+            function,
+        });
+        self.request_multipart(&request, form)
+    }
+
+    /// Uploads each of `files` as a blob in the directory `base`, then
+    /// checks each blob against the SHA-256 of its file where the gate
+    /// reports one. Files larger than `chunk_size` are uploaded in pieces
+    /// of that size if the gate supports it, continuing any earlier upload
+    /// of the same contents that was cut short; the rest go in a single
+    /// request. Returns the gate's reply to that request, if it was sent.
+    pub fn mkblob(&self, base: &FsPath, label: &Label, files: &[PathBuf], chunk_size: u64) -> Result<FsResponse> {
+        let mut form = Form::new();
+        let mut whole = false;
+        let mut uploaded = Vec::new();
+        for file in files {
+            let file_name = file
+                .file_name()
+                .and_then(|f| f.to_str())
+                .map(|f| f.to_string())
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{}: not a file name", file.display()),
+                    )
+                })?;
+            let (size, sha256) = file_hash(file)?;
+            let blob = base.child(&file_name);
+            if size <= chunk_size || !self.upload(base, &file_name, label, file, &sha256, size, chunk_size)? {
+                form = form.part("blob", blob_part(file, file_name)?);
+                whole = true;
+            }
+            uploaded.push((blob, size, sha256));
+        }
+        let request = FsRequest::Mkblob { label: label.clone(), base: base.clone() };
+        let response = match whole {
+            true => self.request_multipart(&request, form)?,
+            false => FsResponse::Done(None),
+        };
+        for (blob, size, sha256) in uploaded {
+            self.verify(&blob, &sha256)?;
+            self.remember(blob, size, sha256)?;
+        }
+        Ok(response)
+    }
+
+    /// Records in the blob cache, if there is one, that `blob` holds `size`
+    /// bytes hashing to `sha256`.
+    fn remember(&self, blob: FsPath, size: u64, sha256: String) -> Result<()> {
+        match &self.blobs {
+            Some(cache) => {
+                let (server, user) = (self.client.server.clone(), self.client.user.clone());
+                cache.add(CachedBlob { server, user, path: blob, sha256, size })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// A blob holding the contents of the local `image` file `local`: one the
+    /// blob cache knows of, or else one in `base` named after the file and
+    /// the start of its SHA-256, uploaded with `label` if it is not there
+    /// yet. `None` if the blob would have to be uploaded but cannot be.
+    fn image_blob(&self, local: &Path, image: &str, base: &FsPath, label: Option<&Label>) -> Result<Option<FsPath>> {
+        let (size, sha256) = file_hash(local)?;
+        if let Some(blob) = self.uploaded(&sha256)? {
+            return Ok(Some(blob));
+        }
+        let Some(label) = label else { return Ok(None) };
+        let stem = local.file_name().and_then(|f| f.to_str()).unwrap_or(image);
+        let name = Some(format!("{}-{}", stem, &sha256[..12]))
+            .filter(|name| crate::path::name(name).is_ok())
+            .unwrap_or_else(|| format!("{}-{}", image, &sha256[..12]));
+        let blob = base.child(&name);
+        if !self.holds_blob(&blob, &sha256)? {
+            if !self.upload(base, &name, label, local, &sha256, size, DEFAULT_CHUNK_SIZE)? {
+                return Ok(None);
+            }
+            self.verify(&blob, &sha256)?;
+        }
+        self.remember(blob.clone(), size, sha256)?;
+        Ok(Some(blob))
+    }
+
+    /// Uploads the local file `local`, of `size` bytes hashing to `sha256`,
+    /// as the blob `name` in `base`, `chunk_size` bytes per request, from
+    /// wherever the gate's record of an earlier upload of it ends. Returns
+    /// `false`, having sent nothing, if the gate does not support `upload`.
+    #[allow(clippy::too_many_arguments)]
+    fn upload(
+        &self,
+        base: &FsPath,
+        name: &str,
+        label: &Label,
+        local: &Path,
+        sha256: &str,
+        size: u64,
+        chunk_size: u64,
+    ) -> Result<bool> {
+        let mut file = std::fs::File::open(local)?;
+        let mut progress = Progress::new(name, size);
+        // The first request only asks how much the gate already has.
+        let mut piece: Option<(u64, Vec<u8>)> = None;
+        let mut probed = false;
+        let mut retries = 0;
+        loop {
+            let sent = piece.as_ref().map(|(offset, _)| *offset);
+            let request = FsRequest::Upload {
+                label: label.clone(),
+                base: base.clone(),
+                name: name.to_string(),
+                sha256: sha256.to_string(),
+                size,
+                offset: sent.unwrap_or(0),
+            };
+            let form = match piece.take() {
+                Some((_, data)) => {
+                    let part = Part::bytes(data).mime_str("application/octet-stream")?.file_name(name.to_string());
+                    Form::new().part("blob", part)
+                }
+                None => Form::new(),
+            };
+            let received = match self.request_multipart(&request, form) {
+                Ok(FsResponse::Received(received)) => received,
+                Ok(other) => return Err(unexpected("upload", other)),
+                Err(FstnError::Fs(_)) if !probed => return Ok(false),
+                Err(FstnError::Network(_)) if retries < UPLOAD_RETRIES => {
+                    retries += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            probed = true;
+            retries = 0;
+            if received > size || sent.is_some_and(|offset| received == offset) {
+                return Err(FstnError::Protocol(format!(
+                    "upload: {} holds {} of {} bytes of {} and took no more",
+                    self.gate,
+                    received,
+                    size,
+                    base.child(name)
+                )));
+            }
+            progress.set(received);
+            if received == size {
+                return Ok(true);
+            }
+            file.seek(SeekFrom::Start(received))?;
+            let mut data = Vec::new();
+            (&mut file).take(chunk_size).read_to_end(&mut data)?;
+            if data.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{} changed during the upload", local.display()),
+                )
+                .into());
+            }
+            piece = Some((received, data));
+        }
+    }
+
+    /// A blob already uploaded with the contents `sha256`, according to the
+    /// blob cache, that still holds them.
+    fn uploaded(&self, sha256: &str) -> Result<Option<FsPath>> {
+        let Some(cache) = &self.blobs else { return Ok(None) };
+        let (server, user) = (self.client.server(), self.client.user());
+        for path in cache.find(server, user, sha256)? {
+            if self.holds_blob(&path, sha256)? {
+                return Ok(Some(path));
+            }
+            cache.forget(server, user, &path)?;
+        }
+        Ok(None)
+    }
+
+    /// Whether `path` is a blob whose contents hash to `sha256`. Gates
+    /// without `stat` are trusted to have left an existing blob unchanged.
+    fn holds_blob(&self, path: &FsPath, sha256: &str) -> Result<bool> {
+        let is_blob = |kind| kind == Some(EntryKind::Blob);
+        match self.reported_stat(path)? {
+            Some(stat) => Ok(is_blob(stat.kind) && stat.sha256.is_none_or(|s| s.eq_ignore_ascii_case(sha256))),
+            None => match self.lookup(path) {
+                Ok(entry) => Ok(is_blob(entry.kind)),
+                Err(FstnError::NotFound(_) | FstnError::Fs(_)) => Ok(false),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    /// Uploads `contents` as the blob `name` in the directory `base`.
+    pub fn mkblob_bytes(&self, base: &FsPath, label: &Label, name: &str, contents: Vec<u8>) -> Result<FsResponse> {
+        let part = Part::bytes(contents).mime_str("application/octet-stream")?.file_name(name.to_string());
+        let request = FsRequest::Mkblob { label: label.clone(), base: base.clone() };
+        self.request_multipart(&request, Form::new().part("blob", part))
+    }
+
+    pub fn cat(&self, path: &FsPath) -> Result<Vec<u8>> {
+        match self.request(&FsRequest::Cat { path: path.clone() }).map_err(not_found(path))? {
+            FsResponse::Contents(contents) => Ok(contents),
+            other => Err(unexpected("cat", other)),
+        }
+    }
+
+    /// What the entry at `path` is. If the gate fails `stat`, as gates that
+    /// predate it do, the kind and label come from listing the parent, and
+    /// the size and hash of a file or blob from reading it, while a gate's
+    /// settings are left unknown.
+    pub fn stat(&self, path: &FsPath) -> Result<Stat> {
+        if let Some(stat) = self.reported_stat(path)? {
+            return Ok(stat);
+        }
+        let entry = self.lookup(path)?;
+        let mut stat = Stat { kind: entry.kind, label: entry.label, ..Default::default() };
+        let mut hashing = Hashing::new(std::io::sink());
+        match entry.kind {
+            Some(EntryKind::File) => {
+                self.read_to(path, &mut hashing, DEFAULT_CHUNK_SIZE)?;
+            }
+            Some(EntryKind::Blob) => hashing.write_all(&self.cat(path)?)?,
+            Some(EntryKind::Gate) => {
+                output::warn(&format!("{} does not support stat, so the settings of {} are unknown", self.gate, path));
+                return Ok(stat);
+            }
+            _ => return Ok(stat),
+        }
+        let (_, size, sha256) = hashing.finish();
+        stat.size = Some(size);
+        stat.sha256 = Some(sha256);
+        Ok(stat)
+    }
+
+    /// What the gate's `stat` op reports about `path`, or `None` if the gate
+    /// fails it.
+    pub fn reported_stat(&self, path: &FsPath) -> Result<Option<Stat>> {
+        match self.request(&FsRequest::Stat { path: path.clone() }) {
+            Ok(FsResponse::Stat(stat)) => Ok(Some(*stat)),
+            Ok(other) => Err(unexpected("stat", other)),
+            Err(FstnError::Fs(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks `sha256` against the SHA-256 the gate reports for the contents
+    /// of the file or blob at `path`, returning whether it reported one.
+    pub fn verify(&self, path: &FsPath, sha256: &str) -> Result<bool> {
+        match self.reported_stat(path)?.and_then(|stat| stat.sha256) {
+            None => Ok(false),
+            Some(reported) if reported.eq_ignore_ascii_case(sha256) => Ok(true),
+            Some(reported) => Err(FstnError::Integrity(format!(
+                "the gateway reports SHA-256 {} for {}, but the contents here hash to {}",
+                reported, path, sha256
+            ))),
+        }
+    }
+
+    pub fn mkfaceted(&self, base: &FsPath, name: &str) -> Result<FsResponse> {
+        self.request(&FsRequest::Mkfaceted { base: base.clone(), name: name.to_string() })
+    }
+
+    pub fn mksvc(&self, base: &FsPath, name: &str, label: Option<&Label>, mut svc: MkSvc) -> Result<FsResponse> {
+        svc.base = Some(base.clone());
+        svc.name = Some(name.to_string());
+        svc.label = label.cloned();
+        self.request(&FsRequest::Mksvc(svc))
+    }
+
+    /// Synchronously invokes the gate at `path` with `payload`, returning its
+    /// output.
+    pub fn invoke(&self, path: &FsPath, payload: Vec<u8>, params: HashMap<String, String>) -> Result<Vec<u8>> {
+        let request = FsRequest::Invoke { path: path.clone(), sync: true, payload, params };
+        match self.request(&request)? {
+            FsResponse::Contents(contents) => Ok(contents),
+            other => Err(unexpected("invoke", other)),
+        }
+    }
+}
+
+/// The path of `entry` within the directory at `base`. The entries of a
+/// faceted directory are its facets, listed either as `<label>` or as a bare
+/// label.
+fn child_path(base: &FsPath, entry: &DirEntry, faceted: bool) -> FsPath {
+    let name = &entry.name;
+    let facet = match faceted {
+        true => name.strip_prefix('<').and_then(|n| n.strip_suffix('>')).unwrap_or(name).parse().ok(),
+        false => None,
+    };
+    base.clone().join(facet.map_or_else(|| PathComponent::Name(name.clone()), PathComponent::Facet))
+}
+
+/// The size and SHA-256 of the local file at `path`.
+pub(crate) fn file_hash(path: &Path) -> Result<(u64, String)> {
+    let mut hashing = Hashing::new(std::io::sink());
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hashing)?;
+    let (_, size, sha256) = hashing.finish();
+    Ok((size, sha256))
+}
+
+/// Counts and hashes what is written through it to `inner`.
+pub(crate) struct Hashing<W> {
+    inner: W,
+    sha256: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Hashing<W> {
+    pub(crate) fn new(inner: W) -> Hashing<W> {
+        Hashing { inner, sha256: Sha256::new(), size: 0 }
+    }
+
+    /// The writer, the number of bytes written and their SHA-256 in hex.
+    pub(crate) fn finish(self) -> (W, u64, String) {
+        (self.inner, self.size, format!("{:x}", self.sha256.finalize()))
+    }
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.sha256.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An [`Fs::copy`] in progress.
+struct Copy<'f, 'a> {
+    fs: &'f Fs<'a>,
+    label: Option<&'f Label>,
+    /// The entries of the destination directories seen so far.
+    listings: HashMap<FsPath, HashMap<String, DirEntry>>,
+    created: Vec<FsPath>,
+}
+
+impl Copy<'_, '_> {
+    /// The contents of the file at `src` in a [`spool`], ready to be read
+    /// from the start. Blobs need none: `cat` returns them whole anyway.
+    fn spool(&self, src: &FsPath) -> Result<std::fs::File> {
+        let mut file = spool()?;
+        self.fs.read_to(src, &mut file, DEFAULT_CHUNK_SIZE)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(file)
+    }
+
+    /// Copies the entry at `src`, listed as `entry`, to `base:name`,
+    /// returning whether it was copied.
+    fn entry(&mut self, src: &FsPath, entry: &DirEntry, base: &FsPath, name: &str) -> Result<bool> {
+        let kind = match entry.kind {
+            Some(kind) if copyable(Some(kind)) => kind,
+            kind => {
+                let kind = kind.map_or("entry of unknown kind", EntryKind::name);
+                output::warn(&format!("skipping {}: a {} cannot be copied", src, kind));
+                return Ok(false);
+            }
+        };
+        let label = match (kind, &entry.label) {
+            (EntryKind::Faceted, _) => Label::default(),
+            (_, Some(label)) => match self.label {
+                Some(new) if !new.secrecy.implies(&label.secrecy) => {
+                    return Err(FstnError::LabelViolation(format!(
+                        "copying {} ({}) as {} would downgrade its secrecy",
+                        src, label, new
+                    )));
+                }
+                new => new.unwrap_or(label).clone(),
+            },
+            (_, None) => return Err(FstnError::Protocol(format!("ls: no label reported for {}", src))),
+        };
+        let dst = base.child(name);
+        if !self.listings.contains_key(base) {
+            let listing = self.fs.ls(base)?.into_iter().map(|e| (e.name.clone(), e)).collect();
+            self.listings.insert(base.clone(), listing);
+        }
+        let existing = self.listings[base].get(name).map(|e| e.kind);
+        match (kind, existing) {
+            (EntryKind::Directory, None) => {
+                self.fs.mkdir(base, name, &label)?;
+                self.listings.insert(dst.clone(), HashMap::new());
+            }
+            (EntryKind::Faceted, None) => {
+                self.fs.mkfaceted(base, name)?;
+            }
+            (EntryKind::File, None) => {
+                let mut contents = self.spool(src)?;
+                self.fs.mkfile(base, name, &label)?;
+                self.fs.write_from(&dst, &mut contents, DEFAULT_CHUNK_SIZE)?;
+            }
+            (EntryKind::Blob, None) => {
+                self.fs.mkblob_bytes(base, &label, name, self.fs.cat(src)?)?;
+            }
+            (EntryKind::Directory | EntryKind::Faceted, Some(Some(existing))) if existing == kind => return Ok(true),
+            (EntryKind::File, Some(Some(EntryKind::File))) => {
+                self.fs.write_from(&dst, &mut self.spool(src)?, DEFAULT_CHUNK_SIZE)?;
+                return Ok(true);
+            }
+            (EntryKind::Blob, Some(Some(EntryKind::Blob))) => {
+                // Fetched first, so that only the replacement can fail once
+                // the old blob is gone; see `Fs::copy`.
+                let contents = self.fs.cat(src)?;
+                self.fs.unlink(base, name)?;
+                self.fs.mkblob_bytes(base, &label, name, contents)?;
+                return Ok(true);
+            }
+            (EntryKind::Gate | EntryKind::Service, None) => unreachable!("gates and services are skipped above"),
+            (_, Some(existing)) => {
+                let existing = existing.map_or("entry of unknown kind", EntryKind::name);
+                return Err(FstnError::InvalidPath(format!("{} is a {}, not a {}", dst, existing, kind)));
+            }
+        }
+        self.created.push(dst);
+        Ok(true)
+    }
+}
+
+/// `listed`, the entries below `src`, unless some directory among them
+/// could not be listed, in which case `src` cannot be `done` in full.
+fn complete(src: &FsPath, done: &str, listed: Vec<ListedEntry>) -> Result<Vec<ListedEntry>> {
+    match listed.iter().find(|e| e.unlisted) {
+        Some(unlisted) => Err(FstnError::Fs(FsFailure {
+            op: "ls",
+            error: Some(serde_json::Value::String(format!(
+                "{} could not be listed, so {} cannot be {}",
+                unlisted.path, src, done
+            ))),
+        })),
+        None => Ok(listed),
+    }
+}
+
+/// Whether [`Fs::copy`] can copy an entry of `kind`.
+fn copyable(kind: Option<EntryKind>) -> bool {
+    matches!(kind, Some(EntryKind::Directory | EntryKind::Faceted | EntryKind::File | EntryKind::Blob))
+}
+
+/// The directory `path` is linked into and the name it is linked as, unless
+/// it is a facet, `~` or the root.
+fn linked_name(path: &FsPath) -> Option<(FsPath, &str)> {
+    match path.last()? {
+        PathComponent::Name(name) | PathComponent::Gate(name) | PathComponent::Blob(name) => {
+            Some((path.parent()?, name))
+        }
+        PathComponent::Home | PathComponent::Facet(_) => None,
+    }
+}
+
+/// Whether a listing is of a faceted directory, judging by its names.
+fn is_facet_listing(entries: &[DirEntry]) -> bool {
+    !entries.is_empty()
+        && entries.iter().all(|e| matches!(e.name.parse(), Ok(PathComponent::Facet(_))))
+}
+
+fn unexpected(op: &str, response: FsResponse) -> FstnError {
+    FstnError::Protocol(format!("{}: unexpected reply {:?}", op, response))
+}
+
+/// Reports a failed lookup with no further explanation as a missing entry.
+fn not_found(path: &FsPath) -> impl FnOnce(FstnError) -> FstnError + '_ {
+    move |e| match e {
+        FstnError::Fs(FsFailure { error: None, .. }) => FstnError::NotFound(path.to_string()),
+        e => e,
+    }
+}
@@ -0,0 +1,234 @@
+//! Named profiles in `config.toml` in the fstn config directory.
+//!
+//! A profile bundles the settings for one gateway, so switching between, say,
+//! a production and a local gateway is a matter of `--profile local`:
+//!
+//! ```toml
+//! profile = "production"
+//!
+//! [profiles.production]
+//! server = "https://faasten.princeton.systems"
+//! user = "alice"
+//! label = "alice,alice"
+//!
+//! [profiles.local]
+//! server = "http://localhost:8080"
+//! timeout = 30
+//! ```
+//!
+//! `profile` names the profile used when none is given on the command line.
+//! The `default` profile may be used without being defined.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::credentials::{config_dir, Store};
+use crate::label::Label;
+use crate::{FstnError, Result};
+
+/// The profile used when none is selected.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The settings of one profile. Unset settings fall back to the command-line
+/// defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// The label given to new entries when `--label` is omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<Label>,
+    /// The request timeout, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// The connection timeout, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// The user whose `fsutil` gate `fstn fs` uses when `--masquerade` is
+    /// omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub masquerade: Option<String>,
+    /// Where `fstn login` and `fstn delegate --save` keep tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_store: Option<Store>,
+}
+
+/// A setting of a [`Profile`], as named by `fstn config get/set`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProfileKey {
+    Server,
+    User,
+    Label,
+    Timeout,
+    ConnectTimeout,
+    Masquerade,
+    CredentialStore,
+}
+
+impl ProfileKey {
+    pub const ALL: [ProfileKey; 7] = [
+        ProfileKey::Server,
+        ProfileKey::User,
+        ProfileKey::Label,
+        ProfileKey::Timeout,
+        ProfileKey::ConnectTimeout,
+        ProfileKey::Masquerade,
+        ProfileKey::CredentialStore,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileKey::Server => "server",
+            ProfileKey::User => "user",
+            ProfileKey::Label => "label",
+            ProfileKey::Timeout => "timeout",
+            ProfileKey::ConnectTimeout => "connect-timeout",
+            ProfileKey::Masquerade => "masquerade",
+            ProfileKey::CredentialStore => "credential-store",
+        }
+    }
+
+    /// The column header for this setting in `fstn config list`.
+    pub fn header(self) -> &'static str {
+        match self {
+            ProfileKey::Server => "SERVER",
+            ProfileKey::User => "USER",
+            ProfileKey::Label => "LABEL",
+            ProfileKey::Timeout => "TIMEOUT",
+            ProfileKey::ConnectTimeout => "CONNECT-TIMEOUT",
+            ProfileKey::Masquerade => "MASQUERADE",
+            ProfileKey::CredentialStore => "STORE",
+        }
+    }
+}
+
+impl Profile {
+    pub fn get(&self, key: ProfileKey) -> Option<String> {
+        match key {
+            ProfileKey::Server => self.server.clone(),
+            ProfileKey::User => self.user.clone(),
+            ProfileKey::Label => self.label.as_ref().map(ToString::to_string),
+            ProfileKey::Timeout => self.timeout.map(|t| t.to_string()),
+            ProfileKey::ConnectTimeout => self.connect_timeout.map(|t| t.to_string()),
+            ProfileKey::Masquerade => self.masquerade.clone(),
+            ProfileKey::CredentialStore => self.credential_store.map(|s| match s {
+                Store::File => "file".to_string(),
+                Store::Keyring => "keyring".to_string(),
+            }),
+        }
+    }
+
+    /// Sets `key` to `value`, or clears it if `value` is empty.
+    pub fn set(&mut self, key: ProfileKey, value: &str) -> Result<()> {
+        let value = Some(value).filter(|v| !v.is_empty());
+        let seconds = |v: &str| {
+            v.parse::<u64>()
+                .map_err(|_| FstnError::Config(format!("{}: `{}` is not a number of seconds", key.name(), v)))
+        };
+        match key {
+            ProfileKey::Server => {
+                if let Some(server) = value {
+                    reqwest::Url::parse(server)
+                        .map_err(|e| FstnError::Config(format!("server: `{}`: {}", server, e)))?;
+                }
+                self.server = value.map(String::from);
+            }
+            ProfileKey::User => self.user = value.map(String::from),
+            ProfileKey::Label => self.label = value.map(str::parse).transpose()?,
+            ProfileKey::Timeout => self.timeout = value.map(seconds).transpose()?,
+            ProfileKey::ConnectTimeout => self.connect_timeout = value.map(seconds).transpose()?,
+            ProfileKey::Masquerade => self.masquerade = value.map(String::from),
+            ProfileKey::CredentialStore => {
+                self.credential_store = value
+                    .map(|v| {
+                        <Store as clap::ValueEnum>::from_str(v, false).map_err(|_| {
+                            FstnError::Config(format!("credential-store: `{}` is not `file` or `keyring`", v))
+                        })
+                    })
+                    .transpose()?
+            }
+        }
+        Ok(())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout.map(Duration::from_secs)
+    }
+}
+
+/// The contents of `config.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    /// The profile used when none is selected on the command line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// The path of `config.toml`.
+    pub fn path() -> PathBuf {
+        config_dir().join("config.toml")
+    }
+
+    /// Reads `config.toml`, which need not exist.
+    pub fn load() -> Result<Config> {
+        match std::fs::read(Config::path()) {
+            Ok(bytes) => Ok(toml::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(config_dir())?;
+        std::fs::write(Config::path(), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The name of the profile to use, given the one selected on the command
+    /// line, if any.
+    pub fn selected(&self, name: Option<String>) -> String {
+        name.or_else(|| self.profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+    }
+
+    /// The settings of the profile `name`.
+    pub fn get(&self, name: &str) -> Result<Profile> {
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile.clone()),
+            None if name == DEFAULT_PROFILE => Ok(Profile::default()),
+            None => Err(self.no_profile(name)),
+        }
+    }
+
+    /// Makes `name` the profile used by default.
+    pub fn use_profile(&mut self, name: &str) -> Result<()> {
+        if !self.profiles.contains_key(name) && name != DEFAULT_PROFILE {
+            return Err(self.no_profile(name));
+        }
+        self.profile = Some(name.to_string());
+        Ok(())
+    }
+
+    fn no_profile(&self, name: &str) -> FstnError {
+        let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+        FstnError::Config(format!(
+            "no profile named `{}` (known profiles: {})",
+            name,
+            if known.is_empty() { "none".into() } else { known.join(", ") }
+        ))
+    }
+}
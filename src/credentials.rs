@@ -0,0 +1,578 @@
+//! Reading and writing the `credentials` file in the fstn config directory.
+//!
+//! Tokens are stored in a TOML table keyed by server URL and then by user
+//! name, along with when they were saved and, for tokens saved by
+//! `fstn delegate --save`, the user they were delegated from:
+//!
+//! ```toml
+//! [global.users]
+//! "https://faasten.princeton.systems" = "alice"
+//!
+//! ["https://faasten.princeton.systems".alice]
+//! token = "..."
+//! saved = 1700000000
+//!
+//! ["https://faasten.princeton.systems"."alice/photos"]
+//! token = "..."
+//! saved = 1700000100
+//! delegated-from = "alice"
+//! ```
+//!
+//! `global.users` records the user `fstn auth use` made the default for each
+//! server. Bare `user = "token"` entries, either under a server or in the
+//! legacy flat layout at the top level, are still accepted. The flat layout
+//! did not record which server a token was for, so the first time the file
+//! is changed flat entries are moved to `global.legacy`, which is used for
+//! any server without an entry of its own, and copied under the legacy
+//! default server (`global.server`, a top-level `server` key, or
+//! [`DEFAULT_SERVER`]). A top-level `server` key becomes `global.server`.
+//! Removing a user's credential for any server removes their legacy entry.
+//!
+//! The file is created readable only by its owner, and a warning is printed
+//! when it is found readable by anyone else. Every change is made while
+//! holding a lock on `credentials.lock` and written to a temporary file that
+//! is renamed into place, so concurrent `fstn` processes neither clobber each
+//! other's entries nor leave a half-written file behind.
+//!
+//! With the [`Store::Keyring`] store the token itself is kept in the Secret
+//! Service and the file only records that it is there, as `keyring = true`.
+//! This needs fstn built with the `keyring` feature.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fs2::FileExt;
+use serde_derive::{Deserialize, Serialize};
+use toml::value::Table;
+use toml::Value;
+
+use crate::client::DEFAULT_SERVER;
+use crate::{output, FstnError, Result};
+
+/// The fstn configuration directory, `$XDG_CONFIG_HOME/fstn` on Linux.
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or("~/.config".into())
+        .join("fstn")
+}
+
+/// The top-level table holding settings rather than servers.
+const GLOBAL: &str = "global";
+
+/// The table in [`GLOBAL`] holding migrated flat entries.
+const LEGACY: &str = "legacy";
+
+/// The server [`CredentialsFile::list`] gives legacy flat entries, which
+/// stand in for any server without an entry of its own.
+pub const ANY_SERVER: &str = "*";
+
+/// Where newly saved tokens are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Store {
+    /// In the credentials file.
+    #[default]
+    File,
+    /// In the Secret Service, e.g. GNOME Keyring or KWallet.
+    Keyring,
+}
+
+/// One entry of the credentials file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Credential {
+    /// The token, unless it is kept in the keyring.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyring: bool,
+    /// When the token was saved, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved: Option<u64>,
+    /// The user this token was delegated from by `fstn delegate --save`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delegated_from: Option<String>,
+}
+
+impl Credential {
+    /// Reads an entry, which may be a bare token.
+    fn from_value(value: &Value) -> Option<Credential> {
+        match value {
+            Value::String(token) => Some(Credential { token: Some(token.clone()), ..Default::default() }),
+            Value::Table(_) => value.clone().try_into().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn store(&self) -> Store {
+        if self.keyring {
+            Store::Keyring
+        } else {
+            Store::File
+        }
+    }
+
+    /// How long ago the token was saved, if known.
+    pub fn age(&self) -> Option<u64> {
+        self.saved.map(|saved| now().saturating_sub(saved))
+    }
+}
+
+/// A saved credential, as listed by [`CredentialsFile::list`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SavedCredential {
+    pub server: String,
+    pub user: String,
+    #[serde(flatten)]
+    pub credential: Credential,
+    /// Whether this is the server's default user.
+    pub default: bool,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// A credentials file.
+#[derive(Debug, Clone)]
+pub struct CredentialsFile {
+    path: PathBuf,
+}
+
+impl CredentialsFile {
+    pub fn new(path: impl Into<PathBuf>) -> CredentialsFile {
+        CredentialsFile { path: path.into() }
+    }
+
+    /// The `credentials` file in [`config_dir`].
+    pub fn in_config_dir() -> CredentialsFile {
+        CredentialsFile::new(config_dir().join("credentials"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The contents of the file, which need not exist.
+    pub fn read(&self) -> Result<Table> {
+        let bytes = match std::fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+            Err(e) => return Err(e.into()),
+        };
+        self.check_permissions();
+        Ok(toml::from_slice(&bytes)?)
+    }
+
+    /// Warns, once per process, if anyone but the owner may read the file.
+    fn check_permissions(&self) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            let Ok(metadata) = std::fs::metadata(&self.path) else {
+                return;
+            };
+            let mode = metadata.permissions().mode() & 0o777;
+            if mode & 0o077 != 0 {
+                WARNED.call_once(|| {
+                    output::warn(&format!(
+                        "{} is accessible by other users (mode {:o}); run `chmod 600 {}`",
+                        self.path.display(),
+                        mode,
+                        self.path.display()
+                    ))
+                });
+            }
+        }
+    }
+
+    /// Applies `change` to the contents of the file and replaces it
+    /// atomically, holding the lock throughout. Legacy flat entries are
+    /// migrated first.
+    pub fn update<T>(&self, change: impl FnOnce(&mut Table) -> Result<T>) -> Result<T> {
+        replace_locked(&self.path, || {
+            let mut table = self.read()?;
+            migrate(&mut table);
+            let result = change(&mut table)?;
+            Ok((toml::to_string(&table)?, result))
+        })
+    }
+
+    /// Moves legacy flat entries under their server, returning the users
+    /// moved.
+    pub fn migrate(&self) -> Result<Vec<String>> {
+        let table = self.read()?;
+        let users = legacy_users(&table);
+        if !users.is_empty() || table.contains_key("server") {
+            self.update(|_| Ok(()))?;
+        }
+        Ok(users)
+    }
+
+    /// Looks up the token saved for `user` on `server`, or else the legacy
+    /// flat entry for `user`.
+    pub fn load(&self, server: &str, user: &str) -> Result<String> {
+        let mut table = self.read()?;
+        migrate(&mut table);
+        let entry = table
+            .get(server)
+            .and_then(|v| v.get(user))
+            .or_else(|| table.get(GLOBAL)?.get(LEGACY)?.get(user))
+            .and_then(Credential::from_value);
+        match entry {
+            Some(Credential { keyring: true, .. }) => keyring::load(server, user),
+            Some(Credential { token: Some(token), .. }) => Ok(token),
+            _ => Err(FstnError::NoCredentials {
+                server: server.to_string(),
+                user: user.to_string(),
+            }),
+        }
+    }
+
+    /// Saves `token` for `user` on `server` in `store`, keeping all other
+    /// entries. `delegated_from` names the user it was delegated from, if
+    /// any.
+    pub fn save(
+        &self,
+        store: Store,
+        server: &str,
+        user: &str,
+        token: &str,
+        delegated_from: Option<&str>,
+    ) -> Result<()> {
+        let mut credential = Credential {
+            saved: Some(now()),
+            delegated_from: delegated_from.map(String::from),
+            ..Default::default()
+        };
+        match store {
+            Store::File => credential.token = Some(token.to_string()),
+            Store::Keyring => {
+                keyring::save(server, user, token)?;
+                credential.keyring = true;
+            }
+        }
+        let entry = Value::try_from(credential)?;
+        self.update(|credentials| {
+            server_table(credentials, server)?.insert(user.to_string(), entry);
+            Ok(())
+        })
+    }
+
+    /// Every saved credential, by server and then user, followed by the
+    /// legacy flat entries under [`ANY_SERVER`].
+    pub fn list(&self) -> Result<Vec<SavedCredential>> {
+        let mut table = self.read()?;
+        migrate(&mut table);
+        let mut saved = Vec::new();
+        for (server, users) in table.iter().filter(|(k, _)| *k != GLOBAL) {
+            let default = default_user(&table, server);
+            for (user, entry) in users.as_table().into_iter().flatten() {
+                if let Some(credential) = Credential::from_value(entry) {
+                    saved.push(SavedCredential {
+                        server: server.clone(),
+                        user: user.clone(),
+                        credential,
+                        default: default.as_deref() == Some(user),
+                    });
+                }
+            }
+        }
+        let legacy = table.get(GLOBAL).and_then(|g| g.get(LEGACY)).and_then(Value::as_table);
+        for (user, entry) in legacy.into_iter().flatten() {
+            if let Some(credential) = Credential::from_value(entry) {
+                let (server, user) = (ANY_SERVER.to_string(), user.clone());
+                saved.push(SavedCredential { server, user, credential, default: false });
+            }
+        }
+        Ok(saved)
+    }
+
+    /// Removes the credentials of `users` on `server`, including any kept in
+    /// the keyring, returning the users that had one. Their legacy flat
+    /// entries are removed too, so that they no longer stand in for one,
+    /// and count as one.
+    pub fn remove(&self, server: &str, users: &[String]) -> Result<Vec<String>> {
+        let removed = self.update(|table| {
+            let mut removed = Vec::new();
+            if let Some(Value::Table(entries)) = table.get_mut(server) {
+                for user in users {
+                    if let Some(entry) = entries.remove(user) {
+                        removed.push((user.clone(), Credential::from_value(&entry).unwrap_or_default()));
+                    }
+                }
+                if entries.is_empty() {
+                    table.remove(server);
+                }
+            }
+            if let Some(Value::Table(legacy)) = table.get_mut(GLOBAL).and_then(|g| g.get_mut(LEGACY)) {
+                for user in users {
+                    if let Some(entry) = legacy.remove(user) {
+                        if !removed.iter().any(|(u, _)| u == user) {
+                            removed.push((user.clone(), Credential::from_value(&entry).unwrap_or_default()));
+                        }
+                    }
+                }
+            }
+            if let Some(Value::Table(defaults)) = table.get_mut(GLOBAL).and_then(|g| g.get_mut("users")) {
+                if defaults.get(server).and_then(Value::as_str).is_some_and(|u| users.iter().any(|r| r == u)) {
+                    defaults.remove(server);
+                }
+            }
+            Ok(removed)
+        })?;
+        for (user, credential) in &removed {
+            if credential.keyring {
+                keyring::delete(server, user)?;
+            }
+        }
+        Ok(removed.into_iter().map(|(user, _)| user).collect())
+    }
+
+    /// Removes delegated credentials whose parent credential is gone and,
+    /// given `older_than`, credentials saved longer ago than that. Returns
+    /// what was removed.
+    pub fn prune(&self, older_than: Option<Duration>) -> Result<Vec<SavedCredential>> {
+        let mut kept = self.list()?;
+        let mut pruned = Vec::new();
+        let stale = |c: &SavedCredential| {
+            older_than.is_some_and(|limit| c.credential.age().is_some_and(|age| age > limit.as_secs()))
+        };
+        loop {
+            let orphan = |c: &SavedCredential, all: &[SavedCredential]| {
+                c.credential.delegated_from.as_ref().is_some_and(|parent| {
+                    !all.iter().any(|p| (p.server == c.server || p.server == ANY_SERVER) && &p.user == parent)
+                })
+            };
+            let (gone, rest): (Vec<_>, Vec<_>) = kept.iter().cloned().partition(|c| stale(c) || orphan(c, &kept));
+            if gone.is_empty() {
+                break;
+            }
+            pruned.extend(gone);
+            kept = rest;
+        }
+        let mut servers: Vec<&str> = pruned.iter().map(|c| c.server.as_str()).collect();
+        servers.sort_unstable();
+        servers.dedup();
+        for server in servers {
+            let users: Vec<String> = pruned.iter().filter(|c| c.server == server).map(|c| c.user.clone()).collect();
+            self.remove(server, &users)?;
+        }
+        Ok(pruned)
+    }
+
+    /// The user `fstn auth use` made the default for `server`.
+    pub fn default_user(&self, server: &str) -> Result<Option<String>> {
+        Ok(default_user(&self.read()?, server))
+    }
+
+    /// Makes `user`, who must have a saved credential, the default user for
+    /// `server`.
+    pub fn set_default_user(&self, server: &str, user: &str) -> Result<()> {
+        self.update(|table| {
+            if table.get(server).and_then(|s| s.get(user)).is_none() {
+                return Err(FstnError::NoCredentials { server: server.to_string(), user: user.to_string() });
+            }
+            let global = table
+                .entry(GLOBAL.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            let users = global
+                .as_table_mut()
+                .ok_or_else(|| FstnError::Config("credentials: `global` is not a table".into()))?
+                .entry("users".to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            users
+                .as_table_mut()
+                .ok_or_else(|| FstnError::Config("credentials: `global.users` is not a table".into()))?
+                .insert(server.to_string(), Value::String(user.to_string()));
+            Ok(())
+        })
+    }
+}
+
+fn server_table<'a>(table: &'a mut Table, server: &str) -> Result<&'a mut Table> {
+    table
+        .entry(server.to_string())
+        .or_insert_with(|| Value::Table(Table::new()))
+        .as_table_mut()
+        .ok_or_else(|| FstnError::Config(format!("credentials: `{}` is not a table", server)))
+}
+
+fn default_user(table: &Table, server: &str) -> Option<String> {
+    table
+        .get(GLOBAL)
+        .and_then(|g| g.get("users"))
+        .and_then(|u| u.get(server))
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// The server that legacy flat entries belong to.
+fn legacy_server(table: &Table) -> String {
+    table
+        .get(GLOBAL)
+        .and_then(|v| v.get("server"))
+        .or_else(|| table.get("server"))
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_SERVER)
+        .to_string()
+}
+
+/// The users with a flat `user = "token"` entry at the top level.
+fn legacy_users(table: &Table) -> Vec<String> {
+    table
+        .iter()
+        .filter(|(key, value)| *key != "server" && value.is_str())
+        .map(|(key, _)| key.clone())
+        .collect()
+}
+
+/// Moves flat entries to `global.legacy` and copies them under the legacy
+/// server, keeping existing entries in both, and moves a top-level `server`
+/// key to `global.server`.
+fn migrate(table: &mut Table) {
+    let server = legacy_server(table);
+    if let Some(legacy) = table.remove("server") {
+        let global = table.entry(GLOBAL.to_string()).or_insert_with(|| Value::Table(Table::new()));
+        if let Some(global) = global.as_table_mut() {
+            global.entry("server".to_string()).or_insert(legacy);
+        }
+    }
+    for user in legacy_users(table) {
+        let token = table.remove(&user).unwrap();
+        if let Ok(entries) = server_table(table, &server) {
+            entries.entry(user.clone()).or_insert(token.clone());
+        }
+        let global = table.entry(GLOBAL.to_string()).or_insert_with(|| Value::Table(Table::new()));
+        if let Some(legacy) = global
+            .as_table_mut()
+            .map(|g| g.entry(LEGACY.to_string()).or_insert_with(|| Value::Table(Table::new())))
+            .and_then(Value::as_table_mut)
+        {
+            legacy.entry(user).or_insert(token);
+        }
+    }
+}
+
+/// Runs `change` while holding an exclusive lock on `path`'s `.lock` file,
+/// then replaces `path` with the contents it returns through a temporary
+/// file, so readers see either the old or the new file in full.
+pub(crate) fn replace_locked<T>(path: &Path, change: impl FnOnce() -> Result<(String, T)>) -> Result<T> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let lock = private_file(&path.with_extension("lock"), false)?;
+    lock.lock_exclusive()?;
+
+    let (contents, result) = change()?;
+    let file_name = path.file_name().and_then(|f| f.to_str()).unwrap_or("fstn");
+    let temporary = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+    let _ = std::fs::remove_file(&temporary);
+    let mut file = private_file(&temporary, true)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, path)?;
+
+    lock.unlock()?;
+    Ok(result)
+}
+
+/// Opens `path` for writing, creating it readable only by its owner.
+fn private_file(path: &Path, truncate: bool) -> std::io::Result<std::fs::File> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(truncate);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// The server named by `global.server` (or a top-level `server` key) in the
+/// credentials file, if any.
+pub fn default_server() -> Option<String> {
+    let creds = CredentialsFile::in_config_dir().read().ok()?;
+    creds
+        .get(GLOBAL)
+        .and_then(|v| v.get("server"))
+        .or_else(|| creds.get("server"))
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// The user `fstn auth use` made the default for `server`, if any.
+pub fn default_user_for(server: &str) -> Option<String> {
+    CredentialsFile::in_config_dir().default_user(server).ok().flatten()
+}
+
+/// Looks up the token saved for `user` on `server`.
+pub fn load(server: &str, user: &str) -> Result<String> {
+    CredentialsFile::in_config_dir().load(server, user)
+}
+
+/// Saves `token` for `user` on `server` in `store`, keeping all other
+/// entries.
+pub fn save(store: Store, server: &str, user: &str, token: &str, delegated_from: Option<&str>) -> Result<()> {
+    CredentialsFile::in_config_dir().save(store, server, user, token, delegated_from)
+}
+
+#[cfg(feature = "keyring")]
+mod keyring {
+    use crate::{FstnError, Result};
+
+    fn entry(server: &str, user: &str) -> Result<::keyring::Entry> {
+        ::keyring::Entry::new(&format!("fstn {}", server), user).map_err(error)
+    }
+
+    fn error(e: ::keyring::Error) -> FstnError {
+        FstnError::Config(format!("keyring: {}", e))
+    }
+
+    pub fn load(server: &str, user: &str) -> Result<String> {
+        match entry(server, user)?.get_password() {
+            Ok(token) => Ok(token),
+            Err(::keyring::Error::NoEntry) => Err(FstnError::NoCredentials {
+                server: server.to_string(),
+                user: user.to_string(),
+            }),
+            Err(e) => Err(error(e)),
+        }
+    }
+
+    pub fn save(server: &str, user: &str, token: &str) -> Result<()> {
+        entry(server, user)?.set_password(token).map_err(error)
+    }
+
+    pub fn delete(server: &str, user: &str) -> Result<()> {
+        match entry(server, user)?.delete_password() {
+            Ok(()) | Err(::keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(error(e)),
+        }
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+mod keyring {
+    use crate::{FstnError, Result};
+
+    fn unsupported() -> FstnError {
+        FstnError::Config("the keyring credential store needs fstn built with the `keyring` feature".into())
+    }
+
+    pub fn load(_server: &str, _user: &str) -> Result<String> {
+        Err(unsupported())
+    }
+
+    pub fn save(_server: &str, _user: &str, _token: &str) -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn delete(_server: &str, _user: &str) -> Result<()> {
+        Err(unsupported())
+    }
+}
@@ -0,0 +1,150 @@
+//! A local record of the tokens `fstn delegate` has derived.
+//!
+//! The gateway cannot revoke a delegated token, so the only way to retire one
+//! is to discard every copy of it. `delegations.toml` in the fstn config
+//! directory remembers, for each delegation, which credential it was derived
+//! from and under which user it was saved, so that [`DelegationRecord::discard`]
+//! can drop a token together with everything derived from it:
+//!
+//! ```toml
+//! [[delegation]]
+//! id = 1
+//! server = "https://faasten.princeton.systems"
+//! from = "alice"
+//! privilege = "alice/photos"
+//! bootstrap = false
+//! issued = 1700000000
+//! expires = 1700003600
+//! saved-as = "alice/photos"
+//!
+//! [[delegation]]
+//! id = 2
+//! parent = 1
+//! server = "https://faasten.princeton.systems"
+//! from = "alice/photos"
+//! privilege = "alice/photos/2024"
+//! bootstrap = false
+//! issued = 1700000100
+//! ```
+//!
+//! Tokens themselves are never written here; saved ones live in the
+//! credentials file.
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::credentials::{config_dir, replace_locked, CredentialsFile};
+use crate::Result;
+
+/// One delegated token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Delegation {
+    pub id: u64,
+    /// The delegation the token was derived from, if it was derived from a
+    /// delegated token rather than a login token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<u64>,
+    pub server: String,
+    /// The user whose credential the token was derived from.
+    pub from: String,
+    pub privilege: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clearance: Option<String>,
+    #[serde(default)]
+    pub bootstrap: bool,
+    /// When the token was issued, in seconds since the Unix epoch.
+    pub issued: u64,
+    /// When the token expires, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    /// The user the token was saved as with `--save`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved_as: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Delegations {
+    #[serde(default)]
+    delegation: Vec<Delegation>,
+}
+
+/// A `delegations.toml` file.
+#[derive(Debug, Clone)]
+pub struct DelegationRecord {
+    path: PathBuf,
+}
+
+impl DelegationRecord {
+    pub fn new(path: impl Into<PathBuf>) -> DelegationRecord {
+        DelegationRecord { path: path.into() }
+    }
+
+    /// The `delegations.toml` file in [`config_dir`].
+    pub fn in_config_dir() -> DelegationRecord {
+        DelegationRecord::new(config_dir().join("delegations.toml"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every recorded delegation, oldest first.
+    pub fn list(&self) -> Result<Vec<Delegation>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(toml::from_slice::<Delegations>(&bytes)?.delegation),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut Vec<Delegation>) -> T) -> Result<T> {
+        replace_locked(&self.path, || {
+            let mut delegations = self.list()?;
+            let result = change(&mut delegations);
+            Ok((toml::to_string(&Delegations { delegation: delegations })?, result))
+        })
+    }
+
+    /// Records `delegation` under a fresh id, linking it to the delegation
+    /// its `from` user's token was saved from, if any. Returns the recorded
+    /// delegation.
+    pub fn add(&self, mut delegation: Delegation) -> Result<Delegation> {
+        self.update(|delegations| {
+            delegation.id = delegations.iter().map(|d| d.id).max().unwrap_or(0) + 1;
+            delegation.parent = delegations
+                .iter()
+                .rev()
+                .find(|d| d.server == delegation.server && d.saved_as.as_ref() == Some(&delegation.from))
+                .map(|d| d.id);
+            delegations.push(delegation.clone());
+            delegation
+        })
+    }
+
+    /// Forgets the delegation `id` and everything derived from it, and
+    /// removes the credentials they were saved as. Returns what was
+    /// discarded, which is empty if `id` is unknown.
+    pub fn discard(&self, id: u64, credentials: &CredentialsFile) -> Result<Vec<Delegation>> {
+        let discarded = self.update(|delegations| {
+            let mut doomed = vec![id];
+            let mut i = 0;
+            while i < doomed.len() {
+                let parent = doomed[i];
+                doomed.extend(delegations.iter().filter(|d| d.parent == Some(parent)).map(|d| d.id));
+                i += 1;
+            }
+            let (discarded, kept): (Vec<_>, Vec<_>) =
+                delegations.drain(..).partition(|d| doomed.contains(&d.id));
+            *delegations = kept;
+            discarded
+        })?;
+        for delegation in &discarded {
+            if let Some(user) = &delegation.saved_as {
+                credentials.remove(&delegation.server, std::slice::from_ref(user))?;
+            }
+        }
+        Ok(discarded)
+    }
+}
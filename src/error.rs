@@ -0,0 +1,197 @@
+//! The error type returned by every fallible operation in this crate.
+//!
+//! Each variant maps to a distinct, stable process exit code so scripts can
+//! tell failures apart without parsing messages:
+//!
+//! | code | variant                        |
+//! |------|--------------------------------|
+//! | 0    | success                        |
+//! | 2    | command-line usage error       |
+//! | 3    | [`FstnError::NoCredentials`]   |
+//! | 4    | [`FstnError::Http`]            |
+//! | 5    | [`FstnError::Fs`]              |
+//! | 6    | [`FstnError::LabelViolation`]  |
+//! | 7    | [`FstnError::NotFound`]        |
+//! | 8    | [`FstnError::Io`]              |
+//! | 9    | [`FstnError::ImageBuild`]      |
+//! | 10   | [`FstnError::Network`]         |
+//! | 11   | [`FstnError::Protocol`]        |
+//! | 12   | [`FstnError::Config`]          |
+//! | 13   | [`FstnError::InvalidLabel`]    |
+//! | 14   | [`FstnError::InvalidPath`]     |
+//! | 15   | [`FstnError::Integrity`]       |
+//! | 16   | [`FstnError::Cancelled`]       |
+
+use std::fmt;
+
+use crate::fsutil::FsFailure;
+use crate::label::LabelError;
+use crate::path::PathError;
+
+#[derive(Debug)]
+pub enum FstnError {
+    /// No token is saved for the selected server and user.
+    NoCredentials { server: String, user: String },
+    /// The gateway answered with a non-success HTTP status.
+    Http { status: u16, body: String },
+    /// The `fsutil` gate reported that an op failed.
+    Fs(FsFailure),
+    /// A request would violate information flow control, either as refused
+    /// by the gateway or as detected before it was sent.
+    LabelViolation(String),
+    /// The named file system entry does not exist or cannot be read.
+    NotFound(String),
+    /// Reading or writing a local file failed.
+    Io(std::io::Error),
+    /// Packing a function image failed.
+    ImageBuild(String),
+    /// The gateway could not be reached.
+    Network(reqwest::Error),
+    /// The gateway's reply could not be understood.
+    Protocol(String),
+    /// The local configuration or credentials are malformed.
+    Config(String),
+    /// A label, component or principal could not be parsed.
+    InvalidLabel(String),
+    /// A file system path could not be parsed, names another server or
+    /// names an entry the command cannot be used on as given.
+    InvalidPath(String),
+    /// Contents did not match the hash the gateway reported for them.
+    Integrity(String),
+    /// The user declined to go ahead when asked.
+    Cancelled(String),
+}
+
+impl FstnError {
+    /// The process exit code for this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            FstnError::NoCredentials { .. } => 3,
+            FstnError::Http { .. } => 4,
+            FstnError::Fs(_) => 5,
+            FstnError::LabelViolation(_) => 6,
+            FstnError::NotFound(_) => 7,
+            FstnError::Io(_) => 8,
+            FstnError::ImageBuild(_) => 9,
+            FstnError::Network(_) => 10,
+            FstnError::Protocol(_) => 11,
+            FstnError::Config(_) => 12,
+            FstnError::InvalidLabel(_) => 13,
+            FstnError::InvalidPath(_) => 14,
+            FstnError::Integrity(_) => 15,
+            FstnError::Cancelled(_) => 16,
+        }
+    }
+
+    /// A short machine-readable name for this class of error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FstnError::NoCredentials { .. } => "no-credentials",
+            FstnError::Http { .. } => "http",
+            FstnError::Fs(_) => "fs",
+            FstnError::LabelViolation(_) => "label-violation",
+            FstnError::NotFound(_) => "not-found",
+            FstnError::Io(_) => "io",
+            FstnError::ImageBuild(_) => "image-build",
+            FstnError::Network(_) => "network",
+            FstnError::Protocol(_) => "protocol",
+            FstnError::Config(_) => "config",
+            FstnError::InvalidLabel(_) => "invalid-label",
+            FstnError::InvalidPath(_) => "invalid-path",
+            FstnError::Integrity(_) => "integrity",
+            FstnError::Cancelled(_) => "cancelled",
+        }
+    }
+
+    /// Classifies a non-success HTTP reply.
+    pub fn from_status(status: reqwest::StatusCode, body: String) -> FstnError {
+        if status == reqwest::StatusCode::FORBIDDEN {
+            FstnError::LabelViolation(body)
+        } else {
+            FstnError::Http { status: status.as_u16(), body }
+        }
+    }
+}
+
+impl fmt::Display for FstnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FstnError::NoCredentials { server, user } => {
+                write!(f, "you must first login (no token for {} on {})", user, server)
+            }
+            FstnError::Http { status, body } if body.is_empty() => write!(f, "HTTP {}", status),
+            FstnError::Http { status, body } => write!(f, "HTTP {}: {}", status, body),
+            FstnError::Fs(failure) => write!(f, "{}", failure),
+            FstnError::LabelViolation(msg) => write!(f, "label violation: {}", msg),
+            FstnError::NotFound(path) => write!(f, "{}: not found", path),
+            FstnError::Io(e) => write!(f, "{}", e),
+            FstnError::ImageBuild(msg) => write!(f, "image build failed: {}", msg),
+            FstnError::Network(e) => write!(f, "{}", e),
+            FstnError::Protocol(msg) => write!(f, "malformed reply: {}", msg),
+            FstnError::Config(msg) => write!(f, "bad configuration: {}", msg),
+            FstnError::InvalidLabel(msg) => write!(f, "invalid label: {}", msg),
+            FstnError::InvalidPath(msg) => write!(f, "invalid path: {}", msg),
+            FstnError::Integrity(msg) => write!(f, "integrity check failed: {}", msg),
+            FstnError::Cancelled(msg) => write!(f, "cancelled: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FstnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FstnError::Fs(e) => Some(e),
+            FstnError::Io(e) => Some(e),
+            FstnError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FstnError {
+    fn from(e: std::io::Error) -> Self {
+        FstnError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for FstnError {
+    fn from(e: reqwest::Error) -> Self {
+        FstnError::Network(e)
+    }
+}
+
+impl From<FsFailure> for FstnError {
+    fn from(e: FsFailure) -> Self {
+        FstnError::Fs(e)
+    }
+}
+
+impl From<serde_json::Error> for FstnError {
+    fn from(e: serde_json::Error) -> Self {
+        FstnError::Protocol(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for FstnError {
+    fn from(e: toml::de::Error) -> Self {
+        FstnError::Config(e.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for FstnError {
+    fn from(e: toml::ser::Error) -> Self {
+        FstnError::Config(e.to_string())
+    }
+}
+
+impl From<LabelError> for FstnError {
+    fn from(e: LabelError) -> Self {
+        FstnError::InvalidLabel(e.0)
+    }
+}
+
+impl From<PathError> for FstnError {
+    fn from(e: PathError) -> Self {
+        FstnError::InvalidPath(e.0)
+    }
+}
@@ -0,0 +1,485 @@
+//! The request/response protocol spoken by `fsutil` gates.
+//!
+//! A request is a JSON object `{"op": <name>, "args": {...}}`. Every reply is
+//! a JSON object with a `success` flag and, depending on the op, a `value`,
+//! base64 `data` or an `error`. [`FsRequest::decode`] turns a reply into an
+//! [`FsResponse`] whose shape is determined by the op that was sent.
+//!
+//! Paths are sent as a list of components, each written as in
+//! [`crate::path`] but without backslash escapes, so `~:a\:b` goes out as
+//! `["~", "a:b"]`. Names of new entries are sent the same way.
+//!
+//! Large files move in chunks. A `write` with an `offset` keeps the first
+//! `offset` bytes of the file and replaces the rest with `data`, and the gate
+//! replies with `{"size": <new size>}`; a gate that replies to an empty one
+//! at offset 0 without a size does not support offsets. A `read` with an `offset` and
+//! `length` asks for at most `length` bytes from `offset`, and the gate
+//! replies with `{"data": <base64>, "size": <file size>}`; gates that do not
+//! know about ranges send the whole file as usual.
+//!
+//! Large blobs are uploaded in pieces with `upload`, each carrying the
+//! blob's `sha256` and `size` and the `offset` of the piece, which is sent as
+//! the request's only `blob` part. The gate keeps the bytes it has received
+//! for each `base`, `name` and `sha256` across requests, appends a piece only
+//! if it starts where they end, and replies with `{"received": <bytes>}`; an
+//! `upload` without a part just asks for that count. Once it has all `size`
+//! bytes and they hash to `sha256`, the gate creates the blob.
+
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use serde_with::base64::Base64;
+use serde_with::{serde_as, DeserializeAs, SerializeAs};
+
+use crate::label::{Component, Label};
+use crate::path::FsPath;
+
+#[derive(Serialize_repr, Deserialize_repr, PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum Verb {
+    Head = 0,
+    Get = 1,
+    Post = 2,
+    Put = 3,
+    Delete = 4,
+}
+
+/// What a gate runs. Images are paths to blobs in the file system; an image
+/// uploaded in the same multipart request is left as `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GateArgs {
+    pub memory: Option<u64>,
+    pub app_image: Option<FsPath>,
+    pub kernel: Option<FsPath>,
+    pub runtime: Option<FsPath>,
+    pub gate: Option<FsPath>,
+}
+
+impl GateArgs {
+    fn paths(&self) -> impl Iterator<Item = &FsPath> {
+        [&self.app_image, &self.kernel, &self.runtime, &self.gate].into_iter().flatten()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MkgateArgs {
+    pub label: Label,
+    pub privilege: Component,
+    pub clearance: Label,
+    pub base: FsPath,
+    pub name: String,
+    #[serde(flatten)]
+    pub function: GateArgs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpgateArgs {
+    pub privilege: Option<Component>,
+    pub clearance: Option<Label>,
+    pub path: FsPath,
+    #[serde(flatten)]
+    pub function: GateArgs,
+}
+
+/// An external service description, as read from stdin by `fs mksvc`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MkSvc {
+    pub base: Option<FsPath>,
+    pub name: Option<String>,
+    pub label: Option<Label>,
+    pub privilege: Component,
+    pub clearance: Label,
+    pub taint: Label,
+    pub url: String,
+    pub verb: Verb,
+    pub headers: HashMap<String, String>,
+}
+
+/// A request to an `fsutil` gate.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", content = "args", rename_all = "lowercase")]
+pub enum FsRequest {
+    Ping {},
+    Ls {
+        path: FsPath,
+    },
+    Unlink {
+        base: FsPath,
+        name: String,
+    },
+    Mkdir {
+        base: FsPath,
+        name: String,
+        label: Label,
+    },
+    Mkfile {
+        base: FsPath,
+        name: String,
+        label: Label,
+    },
+    Write {
+        path: FsPath,
+        #[serde_as(as = "StreamedBase64")]
+        data: Vec<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+    },
+    Read {
+        path: FsPath,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        length: Option<u64>,
+    },
+    Mkgate(MkgateArgs),
+    Upgate(UpgateArgs),
+    Mkblob {
+        label: Label,
+        base: FsPath,
+    },
+    Upload {
+        label: Label,
+        base: FsPath,
+        name: String,
+        sha256: String,
+        size: u64,
+        offset: u64,
+    },
+    Cat {
+        path: FsPath,
+    },
+    Stat {
+        path: FsPath,
+    },
+    Mkfaceted {
+        base: FsPath,
+        name: String,
+    },
+    Mksvc(MkSvc),
+    Invoke {
+        path: FsPath,
+        sync: bool,
+        #[serde_as(as = "Base64")]
+        payload: Vec<u8>,
+        params: HashMap<String, String>,
+    },
+}
+
+/// Base64 written straight into the serializer's output, rather than into a
+/// string of its own first, so a chunk being written is held only once more
+/// in encoded form.
+struct StreamedBase64;
+
+impl SerializeAs<Vec<u8>> for StreamedBase64 {
+    fn serialize_as<S: serde::Serializer>(source: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&base64::display::Base64Display::with_config(source, base64::STANDARD))
+    }
+}
+
+impl<'de> DeserializeAs<'de, Vec<u8>> for StreamedBase64 {
+    fn deserialize_as<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Base64::<serde_with::base64::Standard>::deserialize_as(deserializer)
+    }
+}
+
+/// A reply from an `fsutil` gate, as it appears on the wire.
+#[serde_as]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FsReply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub success: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    #[serde_as(as = "Option<Base64>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+/// What a directory entry is.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    #[serde(alias = "dir")]
+    Directory,
+    Faceted,
+    File,
+    Blob,
+    Gate,
+    Service,
+}
+
+impl EntryKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            EntryKind::Directory => "directory",
+            EntryKind::Faceted => "faceted",
+            EntryKind::File => "file",
+            EntryKind::Blob => "blob",
+            EntryKind::Gate => "gate",
+            EntryKind::Service => "service",
+        }
+    }
+
+    /// Whether entries of this kind have entries of their own.
+    pub fn is_directory(self) -> bool {
+        matches!(self, EntryKind::Directory | EntryKind::Faceted)
+    }
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One entry of a directory listing. Gates that list bare names leave
+/// `kind` and `label` unset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirEntry {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<EntryKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<Label>,
+}
+
+/// The decoded result of a successful request.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum FsResponse {
+    /// Reply to `ping`.
+    Pong,
+    /// Reply to ops that only change state, with whatever value the gate
+    /// chose to return.
+    Done(Option<serde_json::Value>),
+    /// Reply to `ls`.
+    Listing(Vec<DirEntry>),
+    /// Reply to `read`, `cat` and `invoke`.
+    Contents(#[serde_as(as = "Base64")] Vec<u8>),
+    /// Reply to `stat`.
+    Stat(Box<Stat>),
+    /// Reply to `upload`: how many bytes of the blob the gate holds.
+    Received(u64),
+}
+
+/// What is known about a single entry. Sizes and hashes apply to files and
+/// blobs, and the rest to gates.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stat {
+    pub kind: Option<EntryKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<Label>,
+    /// The size of the contents in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The SHA-256 of the contents, in hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privilege: Option<Component>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clearance: Option<Label>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_image: Option<FsPath>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<FsPath>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<FsPath>,
+    /// The gate a gate runs instead of an image of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gate: Option<FsPath>,
+}
+
+/// Part of a file, as read with a range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The part's contents, still in base64 so they can be decoded as they
+    /// are copied out.
+    pub data: String,
+    /// The size of the whole file, or `None` if the gate ignored the range
+    /// and sent all of it.
+    pub size: Option<u64>,
+}
+
+/// An op the `fsutil` gate reported as failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FsFailure {
+    pub op: &'static str,
+    pub error: Option<serde_json::Value>,
+}
+
+impl std::fmt::Display for FsFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            Some(serde_json::Value::String(error)) => write!(f, "{} failed: {}", self.op, error),
+            Some(error) => write!(f, "{} failed: {}", self.op, error),
+            None => write!(f, "{} failed", self.op),
+        }
+    }
+}
+
+impl std::error::Error for FsFailure {}
+
+impl FsRequest {
+    /// The op name sent on the wire.
+    pub fn op(&self) -> &'static str {
+        match self {
+            FsRequest::Ping {} => "ping",
+            FsRequest::Ls { .. } => "ls",
+            FsRequest::Unlink { .. } => "unlink",
+            FsRequest::Mkdir { .. } => "mkdir",
+            FsRequest::Mkfile { .. } => "mkfile",
+            FsRequest::Write { .. } => "write",
+            FsRequest::Read { .. } => "read",
+            FsRequest::Mkgate(_) => "mkgate",
+            FsRequest::Upgate(_) => "upgate",
+            FsRequest::Mkblob { .. } => "mkblob",
+            FsRequest::Upload { .. } => "upload",
+            FsRequest::Cat { .. } => "cat",
+            FsRequest::Stat { .. } => "stat",
+            FsRequest::Mkfaceted { .. } => "mkfaceted",
+            FsRequest::Mksvc(_) => "mksvc",
+            FsRequest::Invoke { .. } => "invoke",
+        }
+    }
+
+    /// Every path this request refers to.
+    pub fn paths(&self) -> Vec<&FsPath> {
+        match self {
+            FsRequest::Ping {} => vec![],
+            FsRequest::Ls { path }
+            | FsRequest::Write { path, .. }
+            | FsRequest::Read { path, .. }
+            | FsRequest::Cat { path }
+            | FsRequest::Stat { path }
+            | FsRequest::Invoke { path, .. } => vec![path],
+            FsRequest::Unlink { base, .. }
+            | FsRequest::Mkdir { base, .. }
+            | FsRequest::Mkfile { base, .. }
+            | FsRequest::Mkblob { base, .. }
+            | FsRequest::Upload { base, .. }
+            | FsRequest::Mkfaceted { base, .. } => vec![base],
+            FsRequest::Mkgate(args) => [&args.base].into_iter().chain(args.function.paths()).collect(),
+            FsRequest::Upgate(args) => [&args.path].into_iter().chain(args.function.paths()).collect(),
+            FsRequest::Mksvc(svc) => svc.base.iter().collect(),
+        }
+    }
+
+    /// Fails if the gate reported this request as failed.
+    fn check(&self, reply: &FsReply) -> Result<(), FsFailure> {
+        let failed = match reply.success {
+            Some(success) => !success,
+            None => reply.error.is_some() && reply.data.is_none() && reply.value.is_none(),
+        };
+        match failed {
+            true => Err(FsFailure { op: self.op(), error: reply.error.clone() }),
+            false => Ok(()),
+        }
+    }
+
+    /// Decodes the gate's reply to a `read` sent with a range.
+    pub fn decode_chunk(&self, reply: FsReply) -> Result<Chunk, FsFailure> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Contents {
+            Whole(String),
+            Chunk { data: String, size: u64 },
+        }
+
+        self.check(&reply)?;
+        let failure = |error: String| FsFailure { op: self.op(), error: Some(error.into()) };
+        let value = reply.value.ok_or_else(|| failure("missing value".into()))?;
+        match serde_json::from_value(value).map_err(|e| failure(format!("malformed contents: {}", e)))? {
+            Contents::Whole(data) => Ok(Chunk { data, size: None }),
+            Contents::Chunk { data, size } => Ok(Chunk { data, size: Some(size) }),
+        }
+    }
+
+    /// Decodes the gate's reply to this request.
+    pub fn decode(&self, reply: FsReply) -> Result<FsResponse, FsFailure> {
+        self.check(&reply)?;
+        let failure = |error| FsFailure { op: self.op(), error };
+        match self {
+            FsRequest::Ping {} => Ok(FsResponse::Pong),
+            FsRequest::Ls { .. } => {
+                let value = reply.value.unwrap_or_else(|| serde_json::json!([]));
+                let entries = serde_json::from_value::<Listing>(value)
+                    .map_err(|e| failure(Some(format!("malformed listing: {}", e).into())))?;
+                Ok(FsResponse::Listing(entries.into()))
+            }
+            FsRequest::Read { .. } | FsRequest::Cat { .. } => {
+                #[serde_as]
+                #[derive(Deserialize)]
+                struct Contents(#[serde_as(as = "Base64")] Vec<u8>);
+
+                let value = reply.value.ok_or_else(|| failure(Some("missing value".into())))?;
+                let Contents(contents) = serde_json::from_value(value)
+                    .map_err(|e| failure(Some(format!("malformed contents: {}", e).into())))?;
+                Ok(FsResponse::Contents(contents))
+            }
+            FsRequest::Stat { .. } => {
+                let value = reply.value.ok_or_else(|| failure(Some("missing value".into())))?;
+                let stat = serde_json::from_value(value)
+                    .map_err(|e| failure(Some(format!("malformed stat: {}", e).into())))?;
+                Ok(FsResponse::Stat(Box::new(stat)))
+            }
+            FsRequest::Upload { .. } => reply
+                .value
+                .as_ref()
+                .and_then(|value| value["received"].as_u64())
+                .map(FsResponse::Received)
+                .ok_or_else(|| failure(Some("missing received".into()))),
+            FsRequest::Invoke { .. } => reply
+                .data
+                .map(FsResponse::Contents)
+                .ok_or_else(|| failure(reply.error)),
+            _ => Ok(FsResponse::Done(reply.value)),
+        }
+    }
+}
+
+/// The listing formats gates are known to return: a list of names, or a map
+/// from name to either a kind or a `{kind, label}` object.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Listing {
+    Names(Vec<String>),
+    Entries(std::collections::BTreeMap<String, EntryMeta>),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EntryMeta {
+    Kind(EntryKind),
+    Meta {
+        kind: Option<EntryKind>,
+        label: Option<Label>,
+    },
+}
+
+impl From<Listing> for Vec<DirEntry> {
+    fn from(listing: Listing) -> Self {
+        match listing {
+            Listing::Names(names) => names
+                .into_iter()
+                .map(|name| DirEntry { name, kind: None, label: None })
+                .collect(),
+            Listing::Entries(entries) => entries
+                .into_iter()
+                .map(|(name, meta)| match meta {
+                    EntryMeta::Kind(kind) => DirEntry { name, kind: Some(kind), label: None },
+                    EntryMeta::Meta { kind, label } => DirEntry { name, kind, label },
+                })
+                .collect(),
+        }
+    }
+}
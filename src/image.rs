@@ -0,0 +1,44 @@
+//! Building squashfs function images from a local source directory.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use backhand::NodeHeader;
+
+use crate::{FstnError, Result};
+
+/// Packs `source_dir` into a squashfs image written to `output`.
+pub fn build(source_dir: &Path, output: &Path) -> Result<()> {
+    let mut output = std::fs::File::create(output)?;
+    let mut fswriter = backhand::FilesystemWriter::default();
+    fswriter.set_root_mode(0o555);
+    write_dir(&mut fswriter, source_dir, "/".into())?;
+    fswriter.write(&mut output).map_err(image_error)?;
+    Ok(())
+}
+
+fn write_dir(fs: &mut backhand::FilesystemWriter, path: &Path, prefix: PathBuf) -> Result<()> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let permissions = meta.permissions().mode();
+        if meta.is_file() {
+            fs.push_file(
+                std::fs::File::open(entry.path())?,
+                prefix.join(entry.file_name()),
+                NodeHeader::new(permissions as u16, 0, 0, 0),
+            )
+            .map_err(image_error)?;
+        } else if meta.is_dir() {
+            let next_prefix = prefix.join(entry.file_name());
+            fs.push_dir(next_prefix.clone(), NodeHeader::new(permissions as u16, 0, 0, 0))
+                .map_err(image_error)?;
+            write_dir(fs, &entry.path(), next_prefix)?;
+        }
+    }
+    Ok(())
+}
+
+fn image_error(e: backhand::BackhandError) -> FstnError {
+    FstnError::ImageBuild(e.to_string())
+}
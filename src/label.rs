@@ -0,0 +1,270 @@
+//! Disjunction-category (DC) labels, as used by Faasten for information flow
+//! control.
+//!
+//! A [`Label`] is a pair of [`Component`]s, written `secrecy,integrity`. A
+//! component is a formula in conjunctive normal form over principals:
+//!
+//! * `T` is the empty conjunction (true): public secrecy, no integrity;
+//! * `F` is false: the most secret, or the most trusted;
+//! * otherwise clauses are separated by `&`, the principals within a clause
+//!   by `|`, and the parts of a hierarchical principal by `/`.
+//!
+//! For example `alice|bob&carol,T` is readable only by someone speaking for
+//! both (alice or bob) and carol. A principal speaks for its sub-principals,
+//! so `alice` implies `alice/photos`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// A principal name, one string per level of the hierarchy.
+pub type Principal = Vec<String>;
+
+/// A disjunction of principals.
+pub type Clause = BTreeSet<Principal>;
+
+/// One half of a label: a conjunction of [`Clause`]s, or `F`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Component {
+    False,
+    Formula(BTreeSet<Clause>),
+}
+
+/// A malformed label or component.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelError(pub String);
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for LabelError {}
+
+fn principal_implies(p: &Principal, q: &Principal) -> bool {
+    q.starts_with(p)
+}
+
+fn clause_implies(a: &Clause, b: &Clause) -> bool {
+    a.iter().all(|p| b.iter().any(|q| principal_implies(p, q)))
+}
+
+impl Component {
+    /// `T`, the component implied by every other.
+    pub fn dc_true() -> Component {
+        Component::Formula(BTreeSet::new())
+    }
+
+    /// `F`, the component that implies every other.
+    pub fn dc_false() -> Component {
+        Component::False
+    }
+
+    /// The component consisting of the single principal `name`, which may be
+    /// hierarchical (`alice/photos`).
+    pub fn principal(name: &str) -> Result<Component, LabelError> {
+        let principal = parse_principal(name)?;
+        Ok(Component::Formula(BTreeSet::from([BTreeSet::from([principal])])))
+    }
+
+    pub fn is_true(&self) -> bool {
+        matches!(self, Component::Formula(clauses) if clauses.is_empty())
+    }
+
+    /// Whether `self` logically implies `other`.
+    pub fn implies(&self, other: &Component) -> bool {
+        match (self, other) {
+            (Component::False, _) => true,
+            (_, Component::False) => false,
+            (Component::Formula(a), Component::Formula(b)) => {
+                b.iter().all(|cb| a.iter().any(|ca| clause_implies(ca, cb)))
+            }
+        }
+    }
+
+    /// The conjunction of two components.
+    pub fn and(&self, other: &Component) -> Component {
+        match (self, other) {
+            (Component::False, _) | (_, Component::False) => Component::False,
+            (Component::Formula(a), Component::Formula(b)) => {
+                Component::Formula(a.union(b).cloned().collect()).reduce()
+            }
+        }
+    }
+
+    /// The disjunction of two components.
+    pub fn or(&self, other: &Component) -> Component {
+        match (self, other) {
+            (Component::False, c) | (c, Component::False) => c.clone(),
+            (Component::Formula(a), Component::Formula(b)) => {
+                if a.is_empty() || b.is_empty() {
+                    return Component::dc_true();
+                }
+                let clauses = a
+                    .iter()
+                    .flat_map(|ca| b.iter().map(move |cb| ca.union(cb).cloned().collect()))
+                    .collect();
+                Component::Formula(clauses).reduce()
+            }
+        }
+    }
+
+    /// Drops clauses implied by another clause of the same formula.
+    fn reduce(self) -> Component {
+        match self {
+            Component::False => Component::False,
+            Component::Formula(clauses) => {
+                let kept = clauses
+                    .iter()
+                    .filter(|c| !clauses.iter().any(|d| d != *c && clause_implies(d, c)))
+                    .cloned()
+                    .collect();
+                Component::Formula(kept)
+            }
+        }
+    }
+}
+
+fn parse_principal(s: &str) -> Result<Principal, LabelError> {
+    let s = s.trim();
+    if s == "T" || s == "F" {
+        return Err(LabelError(format!("`{}` cannot be used as a principal", s)));
+    }
+    s.split('/')
+        .map(|part| {
+            if part.is_empty() {
+                Err(LabelError(format!("empty principal name in `{}`", s)))
+            } else if let Some(c) = part
+                .chars()
+                .find(|c| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '@')))
+            {
+                Err(LabelError(format!("invalid character `{}` in principal `{}`", c, s)))
+            } else {
+                Ok(part.to_string())
+            }
+        })
+        .collect()
+}
+
+impl FromStr for Component {
+    type Err = LabelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "T" => Ok(Component::dc_true()),
+            "F" => Ok(Component::False),
+            "" => Err(LabelError("empty component; use `T` for true".into())),
+            s => {
+                let clauses = s
+                    .split('&')
+                    .map(|clause| clause.split('|').map(parse_principal).collect())
+                    .collect::<Result<_, _>>()?;
+                Ok(Component::Formula(clauses).reduce())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Component {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Component::False => f.write_str("F"),
+            Component::Formula(clauses) if clauses.is_empty() => f.write_str("T"),
+            Component::Formula(clauses) => {
+                let clauses: Vec<String> = clauses
+                    .iter()
+                    .map(|clause| clause.iter().map(|p| p.join("/")).collect::<Vec<_>>().join("|"))
+                    .collect();
+                f.write_str(&clauses.join("&"))
+            }
+        }
+    }
+}
+
+/// A secrecy/integrity pair.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Label {
+    pub secrecy: Component,
+    pub integrity: Component,
+}
+
+impl Label {
+    pub fn new(secrecy: Component, integrity: Component) -> Label {
+        Label { secrecy, integrity }
+    }
+
+    /// `T,T`: public, with no integrity.
+    pub fn public() -> Label {
+        Label::new(Component::dc_true(), Component::dc_true())
+    }
+
+    /// Whether data labeled `self` may flow to a sink labeled `other`: the
+    /// sink must be at least as secret and at most as trusted.
+    pub fn flows_to(&self, other: &Label) -> bool {
+        other.secrecy.implies(&self.secrecy) && self.integrity.implies(&other.integrity)
+    }
+
+    /// Like [`Label::flows_to`], but exercising `privilege` to declassify
+    /// secrecy and endorse integrity.
+    pub fn flows_to_with_privilege(&self, other: &Label, privilege: &Component) -> bool {
+        other.secrecy.and(privilege).implies(&self.secrecy)
+            && self.integrity.and(privilege).implies(&other.integrity)
+    }
+
+    /// The least upper bound: the least restrictive label both flow to.
+    pub fn join(&self, other: &Label) -> Label {
+        Label::new(self.secrecy.and(&other.secrecy), self.integrity.or(&other.integrity))
+    }
+
+    /// The greatest lower bound: the most restrictive label flowing to both.
+    pub fn meet(&self, other: &Label) -> Label {
+        Label::new(self.secrecy.or(&other.secrecy), self.integrity.and(&other.integrity))
+    }
+}
+
+impl Default for Label {
+    fn default() -> Self {
+        Label::public()
+    }
+}
+
+impl FromStr for Label {
+    type Err = LabelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (secrecy, integrity) = s
+            .split_once(',')
+            .ok_or_else(|| LabelError(format!("`{}` is not of the form secrecy,integrity", s)))?;
+        if integrity.contains(',') {
+            return Err(LabelError(format!("`{}` has more than two components", s)));
+        }
+        Ok(Label::new(secrecy.parse()?, integrity.parse()?))
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.secrecy, self.integrity)
+    }
+}
+
+macro_rules! serde_via_str {
+    ($ty:ty) => {
+        impl serde::Serialize for $ty {
+            fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                s.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                let s = <std::borrow::Cow<'de, str>>::deserialize(d)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+serde_via_str!(Component);
+serde_via_str!(Label);
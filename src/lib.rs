@@ -0,0 +1,31 @@
+//! A client library for Faasten.
+//!
+//! [`Client`] talks to a Faasten gateway: it invokes gates, delegates
+//! privileges and, through [`Fs`], drives the per-user `fsutil` gate that
+//! exposes the Faasten file system. The `fstn` binary is a thin command-line
+//! front-end over this crate.
+
+pub mod cache;
+pub mod client;
+pub mod config;
+pub mod credentials;
+pub mod delegation;
+pub mod error;
+pub mod fsutil;
+pub mod image;
+pub mod label;
+pub mod login;
+pub mod output;
+pub mod path;
+mod spool;
+pub mod token;
+pub mod transfer;
+
+pub use client::{Client, Fs, GateFunction, Identity, ImageSource};
+pub use error::FstnError;
+pub use fsutil::{FsRequest, FsResponse};
+pub use label::{Component, Label};
+pub use path::FsPath;
+pub use spool::spool;
+
+pub type Result<T> = std::result::Result<T, FstnError>;
@@ -0,0 +1,174 @@
+//! Logging in through a browser.
+//!
+//! [`LoopbackLogin`] listens on a loopback port and builds a login URL that
+//! asks the gateway to redirect back to it once the user has signed in:
+//!
+//! ```text
+//! {server}/login/cas?redirect_uri=http://127.0.0.1:{port}/callback&state={nonce}
+//! ```
+//!
+//! The gateway then sends the browser to
+//! `http://127.0.0.1:{port}/callback?token={token}&state={nonce}`, and the
+//! listener hands the token back once `state` matches the nonce it issued.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+
+use crate::{Client, FstnError, Result};
+
+/// A loopback listener waiting for the gateway's login redirect.
+#[derive(Debug)]
+pub struct LoopbackLogin {
+    listener: TcpListener,
+    state: String,
+}
+
+impl LoopbackLogin {
+    pub fn start() -> Result<LoopbackLogin> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        Ok(LoopbackLogin { listener, state: nonce() })
+    }
+
+    /// Where the gateway should redirect the browser.
+    pub fn redirect_uri(&self) -> Result<String> {
+        Ok(format!("http://{}/callback", self.listener.local_addr()?))
+    }
+
+    /// The URL to open in the browser.
+    pub fn login_url(&self, client: &Client) -> Result<String> {
+        let mut url = Url::parse(&client.login_url())
+            .map_err(|e| FstnError::Config(format!("invalid server URL {}: {}", client.server(), e)))?;
+        url.query_pairs_mut()
+            .append_pair("redirect_uri", &self.redirect_uri()?)
+            .append_pair("state", &self.state);
+        Ok(url.into())
+    }
+
+    /// Waits up to `timeout` for the redirect and returns the token it
+    /// carries. Unrelated requests, such as for a favicon, are answered and
+    /// ignored.
+    pub fn wait(self, timeout: Duration) -> Result<String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Some(token) = self.callback(stream)? {
+                        return Ok(token);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            format!(
+                                "no login redirect within {}s; try `fstn login --no-browser`",
+                                timeout.as_secs()
+                            ),
+                        )
+                        .into());
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Answers one request, returning the token if it was the callback.
+    fn callback(&self, mut stream: TcpStream) -> Result<Option<String>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // Read the headers too: closing a socket with unread data resets the
+        // connection, and the browser may lose the response.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let url = Url::parse(&format!("http://localhost{}", target)).ok();
+        let (status, message, token) = match url.filter(|u| u.path() == "/callback") {
+            None => ("404 Not Found", "Not found.", None),
+            Some(url) => {
+                let param = |key: &str| url.query_pairs().find(|(k, _)| k == key).map(|(_, v)| v.into_owned());
+                match (param("token"), param("state")) {
+                    (Some(token), Some(state)) if state == self.state && !token.is_empty() => {
+                        ("200 OK", "Logged in to Faasten. You can close this window.", Some(token))
+                    }
+                    _ => ("400 Bad Request", "This login link is stale or incomplete.", None),
+                }
+            }
+        };
+        let _ = write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            message.len(),
+            message
+        );
+        Ok(token)
+    }
+}
+
+/// An unguessable value tying the redirect to this login attempt.
+fn nonce() -> String {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    (0..2)
+        .map(|i| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(time + i);
+            hasher.write_u32(std::process::id());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+/// Opens `url` with `$BROWSER`, or the platform's default browser. In
+/// `$BROWSER`, `%s` stands for the URL; without it the URL is appended.
+pub fn open_browser(url: &str) -> std::io::Result<()> {
+    let mut command = match std::env::var("BROWSER").ok().filter(|b| !b.trim().is_empty()) {
+        Some(browser) => {
+            let mut words: Vec<String> = browser.split_whitespace().map(String::from).collect();
+            if words.iter().any(|w| w.contains("%s")) {
+                words.iter_mut().for_each(|w| *w = w.replace("%s", url));
+            } else {
+                words.push(url.to_string());
+            }
+            let mut command = std::process::Command::new(&words[0]);
+            command.args(&words[1..]);
+            command
+        }
+        None if cfg!(target_os = "macos") => {
+            let mut command = std::process::Command::new("open");
+            command.arg(url);
+            command
+        }
+        None if cfg!(windows) => {
+            let mut command = std::process::Command::new("cmd");
+            command.args(["/C", "start", "", url]);
+            command
+        }
+        None => {
+            let mut command = std::process::Command::new("xdg-open");
+            command.arg(url);
+            command
+        }
+    };
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map(drop)
+}
@@ -1,19 +1,23 @@
 use core::fmt;
-use std::collections::HashMap;
 use std::path::PathBuf;
-use std::time::Instant;
-use std:: io::{stdin, stdout, BufRead, Read, Write};
+use std::io::{stdin, stdout, BufRead, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use backhand::NodeHeader;
 use clap::{Parser, Subcommand};
-use reqwest::Url;
-use reqwest::blocking::Response;
-use serde_with::serde_as;
-use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
-use toml::Value;
-use serde_repr::*;
-use serde_derive::{Deserialize, Serialize};
-use serde_with::base64::Base64;
+use fstn::client::{DEFAULT_CHUNK_SIZE, DEFAULT_SERVER, DEFAULT_USER};
+use fstn::cache::BlobCache;
+use fstn::config::{Config, Profile, ProfileKey};
+use fstn::delegation::{Delegation, DelegationRecord};
+use fstn::fsutil::{EntryKind, MkSvc};
+use fstn::output::{self, OutputMode, Report};
+use fstn::login::{self, LoopbackLogin};
+use fstn::path;
+use fstn::token::TokenInfo;
+use fstn::transfer::{self, PutOptions, Summary, SyncOptions};
+use fstn::credentials::{self, CredentialsFile};
+use fstn::{Client, Component, Fs, FsPath, FsResponse, FstnError, GateFunction, ImageSource, Label, Result};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 #[derive(Parser, Debug)]
 #[clap(about = "A CLI client for interacting with Faasten")]
@@ -24,6 +28,26 @@ struct Cli {
     server: Option<String>,
     #[clap(short, long, value_parser)]
     user: Option<String>,
+    /// Profile from config.toml to use [env: FSTN_PROFILE]
+    #[clap(long, global = true, value_parser)]
+    profile: Option<String>,
+    /// Output format
+    #[clap(long, global = true, value_enum, default_value_t = OutputMode::Text)]
+    output: OutputMode,
+}
+
+#[derive(Parser, Debug)]
+struct Login {
+    /// Print the login URL and paste the token instead of using a browser
+    #[clap(long, conflicts_with = "with_token")]
+    no_browser: bool,
+    /// Read the token from FILE, or from standard input if FILE is `-` or
+    /// omitted, without prompting
+    #[clap(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "-")]
+    with_token: Option<PathBuf>,
+    /// Seconds to wait for the browser to complete the login
+    #[clap(long, default_value_t = 300)]
+    timeout: u64,
 }
 
 #[derive(Parser, Debug)]
@@ -32,62 +56,297 @@ struct Invoke {
     payload: Option<String>,
 }
 
-
 #[derive(Parser, Debug)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Delegate {
-    #[clap(value_parser)]
-    privilege: String,
+    #[clap(subcommand)]
+    op: Option<DelegateOp>,
+    #[clap(value_parser, required = true)]
+    privilege: Option<Component>,
     #[clap(short, long, value_parser)]
     save: bool,
     #[clap(short, long, value_parser)]
     bootstrap: bool,
     #[clap(short, long, value_parser)]
-    #[arg(requires="bootstrap")]
-    clearance: Option<String>,
+    clearance: Option<Label>,
+    /// How long the token stays valid, e.g. `90s`, `30m`, `12h` or `7d`
+    #[clap(long, value_parser = duration)]
+    ttl: Option<Duration>,
+    /// Derive from the credential saved for USER, such as a delegated one,
+    /// instead of the current user's
+    #[clap(long, value_name = "USER")]
+    from: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum DelegateOp {
+    /// Show which delegated tokens were derived from which
+    Tree {
+        /// Forget delegation ID and everything derived from it, removing
+        /// their saved credentials
+        #[clap(long, value_name = "ID")]
+        discard: Option<u64>,
+    },
+}
+
+fn duration(s: &str) -> std::result::Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err("expected a number of seconds, or a number followed by s, m, h or d".into()),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("`{}` is not a duration", s))?;
+    Ok(Duration::from_secs(number * scale))
 }
 
 #[derive(Parser, Debug)]
 struct OneArg {
     #[clap(value_parser)]
-    arg: String
+    arg: FsPath,
 }
 
 #[derive(Parser, Debug)]
-struct TwoArgs {
+struct ChunkedArgs {
+    /// Move at most this many bytes per request
+    #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_CHUNK_SIZE,
+           value_parser = clap::value_parser!(u64).range(1..))]
+    chunk_size: u64,
+    /// Enter the facet labeled LABEL of each faceted directory on the path
+    /// that names none of its facets
+    #[clap(long, value_name = "LABEL", value_parser)]
+    facet: Option<Label>,
+    #[clap(value_parser)]
+    path: FsPath,
+}
+
+#[derive(Parser, Debug)]
+struct LsArgs {
+    #[clap(value_parser)]
+    path: FsPath,
+    /// List subdirectories recursively
+    #[clap(short = 'R', long)]
+    recursive: bool,
+    /// List at most N levels, counting PATH's entries as the first
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    depth: Option<u64>,
+    /// Show each entry's kind and label
+    #[clap(short, long)]
+    long: bool,
+    /// Only show entries of these kinds
+    #[clap(short, long, value_enum, value_delimiter = ',')]
+    kind: Vec<EntryKind>,
+    /// Order entries by name, or by kind and then name
+    #[clap(long, value_enum, default_value_t = LsSort::Name)]
+    sort: LsSort,
+    /// Enter the facet labeled LABEL of each faceted directory on the path
+    /// that names none of its facets
+    #[clap(long, value_name = "LABEL", value_parser)]
+    facet: Option<Label>,
+}
+
+#[derive(Parser, Debug)]
+struct PutArgs {
+    /// Upload directories and everything in them
+    #[clap(short, long)]
+    recursive: bool,
+    /// The label of new entries not covered by a .fstnlabels file
+    #[clap(short, long, value_parser)]
+    label: Option<Label>,
+    /// Upload files larger than this many bytes as blobs
+    #[clap(long, value_name = "BYTES", default_value_t = transfer::DEFAULT_BLOB_THRESHOLD)]
+    blob_threshold: u64,
+    #[clap(value_parser)]
+    local: PathBuf,
+    #[clap(value_parser)]
+    base: FsPath,
+}
+
+#[derive(Parser, Debug)]
+struct GetArgs {
+    /// Download directories and everything in them
+    #[clap(short, long)]
+    recursive: bool,
+    #[clap(value_parser)]
+    path: FsPath,
+    #[clap(value_parser)]
+    local: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct FetchArgs {
+    /// Fail unless the download matches the SHA-256 the gate reports
+    #[clap(long)]
+    verify: bool,
+    #[clap(value_parser)]
+    path: FsPath,
     #[clap(value_parser)]
-    base: String,
+    local_file: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct SyncArgs {
+    /// Copy from REMOTE to LOCAL instead
+    #[clap(long)]
+    download: bool,
+    /// Remove files and directories that only the destination has
+    #[clap(long)]
+    delete: bool,
+    /// Show what would change without changing anything
+    #[clap(short = 'n', long)]
+    dry_run: bool,
+    /// Compare with remote contents even where the local index knows them
+    #[clap(short, long)]
+    checksum: bool,
+    /// The label of new remote entries not covered by a .fstnlabels file
+    #[clap(short, long, value_parser)]
+    label: Option<Label>,
+    /// Upload files larger than this many bytes as blobs
+    #[clap(long, value_name = "BYTES", default_value_t = transfer::DEFAULT_BLOB_THRESHOLD)]
+    blob_threshold: u64,
+    #[clap(value_parser)]
+    local: PathBuf,
+    #[clap(value_parser)]
+    remote: FsPath,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LsSort {
+    Name,
+    Kind,
+}
+
+#[derive(Parser, Debug)]
+struct TwoArgs {
     #[clap(value_parser)]
+    base: FsPath,
+    #[clap(value_parser = path::name)]
     name: String,
 }
 
 #[derive(Parser, Debug)]
 struct TwoArgsLabel {
     #[clap(short, long, value_parser)]
-    label: Option<String>,
+    label: Option<Label>,
+    #[clap(value_parser)]
+    base: FsPath,
+    #[clap(value_parser = path::name)]
+    name: String,
+}
+
+#[derive(Parser, Debug)]
+struct MkfileArgs {
+    #[clap(short, long, value_parser)]
+    label: Option<Label>,
+    /// Enter the facet labeled LABEL of each faceted directory on the path
+    /// that names none of its facets
+    #[clap(long, value_name = "LABEL", value_parser)]
+    facet: Option<Label>,
     #[clap(value_parser)]
-    base: String,
+    base: FsPath,
+    #[clap(value_parser = path::name)]
+    name: String,
+}
+
+#[derive(Parser, Debug)]
+struct MkdirArgs {
+    /// Also create missing directories above BASE, with the same label, and
+    /// succeed if NAME already exists
+    #[clap(short, long)]
+    parents: bool,
+    #[clap(short, long, value_parser)]
+    label: Option<Label>,
     #[clap(value_parser)]
+    base: FsPath,
+    #[clap(value_parser = path::name)]
+    name: String,
+}
+
+#[derive(Parser, Debug)]
+struct RmArgs {
+    /// Remove directories and everything in them
+    #[clap(short, long)]
+    recursive: bool,
+    /// Do not ask before removing a directory
+    #[clap(short, long)]
+    force: bool,
+    #[clap(value_parser)]
+    path: FsPath,
+}
+
+#[derive(Parser, Debug)]
+struct CpArgs {
+    /// Copy directories and everything in them
+    #[clap(short, long)]
+    recursive: bool,
+    /// Give the copies this label instead of their own; it may not be less
+    /// secret than theirs
+    #[clap(short, long, value_parser)]
+    label: Option<Label>,
+    #[clap(value_parser)]
+    src: FsPath,
+    #[clap(value_parser)]
+    base: FsPath,
+    #[clap(value_parser = path::name)]
+    name: String,
+}
+
+#[derive(Parser, Debug)]
+struct MvArgs {
+    #[clap(value_parser)]
+    src: FsPath,
+    #[clap(value_parser)]
+    base: FsPath,
+    #[clap(value_parser = path::name)]
+    name: String,
+}
+
+#[derive(Parser, Debug)]
+struct LnArgs {
+    /// The label of the new gate; defaults to the target's
+    #[clap(short, long, value_parser)]
+    label: Option<Label>,
+    #[clap(short, long, value_parser)]
+    privilege: Component,
+    #[clap(short, long, value_parser)]
+    clearance: Label,
+    /// The gate to run
+    #[clap(value_parser)]
+    target: FsPath,
+    #[clap(value_parser)]
+    base: FsPath,
+    #[clap(value_parser = path::name)]
     name: String,
 }
 
 #[derive(Parser, Debug)]
 struct MkBlobArgs {
     #[clap(short, long, value_parser)]
-    label: Option<String>,
+    label: Option<Label>,
+    /// Upload larger files in pieces of this many bytes, resuming where an
+    /// interrupted upload of the same contents stopped
+    #[clap(long, value_name = "BYTES", default_value_t = DEFAULT_CHUNK_SIZE,
+           value_parser = clap::value_parser!(u64).range(1..))]
+    chunk_size: u64,
     #[clap(value_parser)]
-    base: String,
+    base: FsPath,
     #[clap(value_parser)]
-    files: Vec<String>,
+    files: Vec<PathBuf>,
 }
 
 #[derive(Parser, Debug)]
 struct MkGateArgs {
     #[clap(short, long, value_parser)]
-    label: String,
+    label: Label,
     #[clap(short, long, value_parser)]
-    privilege: String,
+    privilege: Component,
     #[clap(short, long, value_parser)]
-    clearance: String,
+    clearance: Label,
     #[clap(short, long, value_parser)]
     #[arg(requires="app_image")]
     #[arg(requires="kernel")]
@@ -95,43 +354,44 @@ struct MkGateArgs {
     #[arg(conflicts_with="gate")]
     memory: Option<u64>,
     #[clap(short, long, value_parser)]
-    app_image: Option<String>,
+    app_image: Option<ImageSource>,
     #[clap(short, long, value_parser)]
-    kernel: Option<String>,
+    kernel: Option<ImageSource>,
     #[clap(short, long, value_parser)]
-    runtime: Option<String>,
+    runtime: Option<ImageSource>,
     #[clap(short, long, value_parser)]
     #[arg(conflicts_with="memory")]
     #[arg(conflicts_with="app_image")]
     #[arg(conflicts_with="kernel")]
     #[arg(conflicts_with="runtime")]
-    gate: Option<String>,
-    base: String,
+    gate: Option<FsPath>,
+    base: FsPath,
+    #[clap(value_parser = path::name)]
     name: String,
 }
 
 #[derive(Parser, Debug)]
 struct UpgateArgs {
     #[clap(short, long, value_parser)]
-    privilege: Option<String>,
+    privilege: Option<Component>,
     #[clap(short, long, value_parser)]
-    clearance: Option<String>,
+    clearance: Option<Label>,
     #[clap(short, long, value_parser)]
     #[arg(conflicts_with="gate")]
     memory: Option<u64>,
     #[clap(short, long, value_parser)]
-    app_image: Option<String>,
+    app_image: Option<ImageSource>,
     #[clap(short, long, value_parser)]
-    kernel: Option<String>,
+    kernel: Option<ImageSource>,
     #[clap(short, long, value_parser)]
-    runtime: Option<String>,
+    runtime: Option<ImageSource>,
     #[clap(short, long, value_parser)]
     #[arg(conflicts_with="memory")]
     #[arg(conflicts_with="app_image")]
     #[arg(conflicts_with="kernel")]
     #[arg(conflicts_with="runtime")]
-    gate: Option<String>,
-    path: String,
+    gate: Option<FsPath>,
+    path: FsPath,
 }
 
 #[derive(Parser, Debug)]
@@ -149,32 +409,72 @@ struct MkGateArgsDirect {
 #[derive(Parser, Debug)]
 struct InvokeArgs {
     #[clap(value_parser)]
-    path: String,
+    path: FsPath,
     #[clap(value_parser = param_valid)]
     params: Vec<(String, String)>,
 }
 
-fn param_valid(s: &str) -> Result<(String, String), String> {
-    let (k, v) = s.split_once("=").ok_or("argument must be of the form key=value".to_string())?;
+fn param_valid(s: &str) -> std::result::Result<(String, String), String> {
+    let (k, v) = s.split_once('=').ok_or("argument must be of the form key=value".to_string())?;
     Ok((k.to_string(), v.to_string()))
 }
 
+/// `path` with the facet `facet`, if any, entered in its faceted directories.
+fn select_facet(fs: &Fs, path: FsPath, facet: Option<Label>) -> Result<FsPath> {
+    match facet {
+        Some(label) => fs.select_facet(&path, &label),
+        None => Ok(path),
+    }
+}
+
+fn gate_function(
+    memory: Option<u64>,
+    app_image: Option<ImageSource>,
+    kernel: Option<ImageSource>,
+    runtime: Option<ImageSource>,
+    gate: Option<FsPath>,
+) -> GateFunction {
+    GateFunction { memory, app_image, kernel, runtime, gate }
+}
+
 #[derive(Subcommand, Debug)]
 enum FsOp {
     Ping,
-    Ls(OneArg),
+    Ls(LsArgs),
     Unlink(TwoArgs),
-    Mkdir(TwoArgsLabel),
-    Mkfile(TwoArgsLabel),
-    Write(OneArg),
-    Read(OneArg),
+    Mkdir(MkdirArgs),
+    Mkfile(MkfileArgs),
+    /// Write stdin to a file, a chunk at a time
+    Write(ChunkedArgs),
+    /// Copy a file to stdout, a chunk at a time
+    Read(ChunkedArgs),
     Mkgate(MkGateArgs),
     Upgate(UpgateArgs),
     Mkblob(MkBlobArgs),
     Cat(OneArg),
+    /// Show an entry's kind and label, and its size and hash or gate settings
+    Stat(OneArg),
+    /// List the facets of a faceted directory and whether you can see them
+    Facets(OneArg),
     Mkfaceted(TwoArgs),
     Mksvc(TwoArgsLabel),
     Invoke(InvokeArgs),
+    /// Upload a local file, or a directory tree with -r, into BASE
+    Put(PutArgs),
+    /// Download a file, or a directory tree with -r, into LOCAL
+    Get(GetArgs),
+    /// Download a blob or file to LOCAL_FILE
+    Fetch(FetchArgs),
+    /// Copy only what changed from LOCAL to REMOTE, or back with --download
+    Sync(SyncArgs),
+    /// Remove an entry, or a directory tree with -r
+    Rm(RmArgs),
+    /// Copy an entry, or a directory tree with -r, keeping its labels
+    Cp(CpArgs),
+    /// Move an entry and everything below it to BASE:NAME
+    Mv(MvArgs),
+    /// Make BASE:NAME a gate that runs the gate TARGET
+    Ln(LnArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -183,6 +483,61 @@ struct FS {
     op: FsOp,
     #[clap(short, long, value_parser)]
     masquerade: Option<String>,
+    /// Neither record uploaded blobs nor reuse them for local gate images
+    #[clap(long)]
+    no_blob_cache: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigOp {
+    /// Print a setting of the selected profile
+    Get { key: ProfileKey },
+    /// Change a setting of the selected profile, creating the profile if
+    /// needed; an empty value clears the setting
+    Set { key: ProfileKey, value: String },
+    /// List all profiles, marking the selected one
+    List,
+    /// Make a profile the one used when --profile is not given
+    Use { profile: String },
+}
+
+#[derive(Parser, Debug)]
+struct ConfigArgs {
+    #[clap(subcommand)]
+    op: ConfigOp,
+}
+
+#[derive(Subcommand, Debug)]
+enum AuthOp {
+    /// List saved credentials, moving legacy entries under their server
+    List,
+    /// Make USER the user used for this server when --user is not given
+    Use { user: String },
+    /// Show the principal, privilege, clearance and expiry of a token
+    Inspect {
+        /// The token to inspect, or `-` to read it from standard input;
+        /// defaults to the saved token
+        token: Option<String>,
+    },
+    /// Remove delegated credentials whose parent credential is gone
+    Prune {
+        /// Also remove credentials saved more than DAYS days ago
+        #[clap(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+    },
+}
+
+#[derive(Parser, Debug)]
+struct AuthArgs {
+    #[clap(subcommand)]
+    op: AuthOp,
+}
+
+#[derive(Parser, Debug)]
+struct Logout {
+    /// Remove every credential saved for the server
+    #[clap(long)]
+    all: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -194,14 +549,19 @@ struct PingScheduler {}
 #[derive(Parser, Debug)]
 struct Build {
     source_dir: PathBuf,
-    #[clap(short, long, value_parser)]
-    output: Option<PathBuf>,
+    /// Where to write the image [default: function.img]
+    #[clap(short = 'o', long, value_parser)]
+    image: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Action {
     /// Login to Faasten
-    Login,
+    Login(Login),
+    /// Remove the saved credential for the user
+    Logout(Logout),
+    /// Manage saved credentials
+    Auth(AuthArgs),
     // Who am I?
     Whoami,
     /// Delegate a privilege
@@ -210,649 +570,563 @@ enum Action {
     Invoke(Invoke),
     /// upload local image to a faasten
     /// File system operaions
-    FS(FS),
+    FS(Box<FS>),
     /// ping gateway
     Ping(Ping),
     /// ping scheduler via gateway
     PingScheduler(PingScheduler),
     /// Build Faasten image from a source directory
     Build(Build),
+    /// Manage named profiles
+    Config(ConfigArgs),
 }
 
 fn status(
     stream: &mut StandardStream,
     action: &dyn fmt::Display,
     status: &dyn fmt::Display,
-) -> Result<(), std::io::Error> {
+) -> std::io::Result<()> {
     stream.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Green)))?;
     write!(stream, "{:>12} ", action)?;
     stream.reset()?;
     writeln!(stream, "{}", status)
 }
 
-fn get_default_server() -> Option<String> {
-    let config_dir = dirs::config_dir()
-        .unwrap_or("~/.config".into())
-        .join("fstn");
-    std::fs::create_dir_all(&config_dir).ok()?;
-    let credentials_file = config_dir.join("credentials");
-    let creds: Value = toml::from_slice(&std::fs::read(credentials_file).ok()?).ok()?;
-    if let Some(server) = creds
-        .get("global")
-        .and_then(|v| v.get("server"))
-        .and_then(Value::as_str)
-    {
-        Some(String::from(server))
-    } else if let Some(server) = creds.get("server").and_then(Value::as_str) {
-        Some(String::from(server))
-    } else {
-       None
-    }
+fn error(stream: &mut StandardStream, error: &FstnError) -> std::io::Result<()> {
+    stream.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Red)))?;
+    write!(stream, "{:>12} ", "Error")?;
+    stream.reset()?;
+    output::render_error(error, OutputMode::Text, stream)
 }
 
-const DEFAULT_SERVER: &'static str = "https://faasten.princeton.systems";
-const DEFAULT_USER: &'static str = "default";
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Runs the command and exits with [`FstnError::exit_code`] on failure.
+fn main() -> ExitCode {
     let cli = Cli::parse();
-    let server = cli
-        .server
-        .or(std::env::var("FSTN_SERVER").ok())
-        .or(get_default_server())
-        .unwrap_or(String::from(DEFAULT_SERVER));
-
-    let user = cli
-        .user
-        .or(std::env::var("FSTN_USER").ok())
-        .unwrap_or(String::from(DEFAULT_USER));
-    Fstn {
-        stdout: stdout(),
-        stderr: StandardStream::stderr(termcolor::ColorChoice::Auto),
-        client: reqwest::blocking::ClientBuilder::new().timeout(None).build()?,
-        server,
-        user,
-
-    }.run(cli.command)
+    let mode = cli.output;
+    let mut stderr = StandardStream::stderr(termcolor::ColorChoice::Auto);
+    let result = Config::load().and_then(|config| {
+        let profile = config.selected(cli.profile.or(std::env::var("FSTN_PROFILE").ok()));
+        // `fstn config` may name a profile it is about to create.
+        let settings = match cli.command {
+            Action::Config(_) => config.profiles.get(&profile).cloned().unwrap_or_default(),
+            _ => config.get(&profile)?,
+        };
+        let server = server(cli.server, &settings);
+        let user = user(cli.user, &settings, &server);
+        let client = Client::from_credentials(server, user)?
+            .with_timeouts(settings.timeout(), settings.connect_timeout())?;
+        let mut fstn = Fstn {
+            stdout: stdout(),
+            stderr: StandardStream::stderr(termcolor::ColorChoice::Auto),
+            client,
+            mode,
+            config,
+            profile,
+        };
+        let report = fstn.run(cli.command)?;
+        fstn.stdout.flush()?;
+        let color = if stdout().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never };
+        let mut out = StandardStream::stdout(color);
+        report.render_color(mode, &mut out)?;
+        Ok(out.flush()?)
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let _ = match mode {
+                OutputMode::Json => output::render_error(&e, mode, &mut stdout()),
+                OutputMode::Text | OutputMode::Table => error(&mut stderr, &e),
+            };
+            ExitCode::from(e.exit_code())
+        }
+    }
 }
 
-struct Fstn<O: Write> {
-    stdout: O,
-    stderr: StandardStream,
-    client: reqwest::blocking::Client,
-    server: String,
-    user: String,
+fn server(flag: Option<String>, profile: &Profile) -> String {
+    flag.or(std::env::var("FSTN_SERVER").ok())
+        .or_else(|| profile.server.clone())
+        .or_else(credentials::default_server)
+        .unwrap_or(String::from(DEFAULT_SERVER))
 }
 
-#[derive(Debug)]
-struct EarlyExit;
+fn user(flag: Option<String>, profile: &Profile, server: &str) -> String {
+    flag.or(std::env::var("FSTN_USER").ok())
+        .or_else(|| profile.user.clone())
+        .or_else(|| credentials::default_user_for(server))
+        .unwrap_or(String::from(DEFAULT_USER))
+}
 
-impl std::fmt::Display for EarlyExit {
-    fn fmt(&self, _formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        Ok(())
+/// `n` followed by the singular or plural noun, as fits.
+fn count(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+/// Reads a token from `file`, or from stdin if it is `-`.
+fn read_token(file: &std::path::Path) -> Result<String> {
+    let token = if file == std::path::Path::new("-") {
+        String::from_utf8_lossy(&read_stdin()?).into_owned()
+    } else {
+        std::fs::read_to_string(file)?
+    };
+    match token.trim() {
+        "" => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "the token is empty").into()),
+        token => Ok(token.to_string()),
     }
 }
 
-impl std::error::Error for EarlyExit {
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    stdin().read_to_end(&mut data)?;
+    Ok(data)
+}
 
+struct Fstn<O: Write> {
+    stdout: O,
+    stderr: StandardStream,
+    client: Client,
+    mode: OutputMode,
+    config: Config,
+    /// The name of the selected profile.
+    profile: String,
 }
 
 impl<O: Write> Fstn<O> {
-    fn check_credential(&self) -> Result<String, std::io::Error> {
-        let config_dir = dirs::config_dir()
-            .unwrap_or("~/.config".into())
-            .join("fstn");
-        std::fs::create_dir_all(&config_dir)?;
-        let credentials_file = config_dir.join("credentials");
-        let creds: Value = toml::from_slice(&std::fs::read(credentials_file)?)?;
-        if let Some(token) = creds
-            .get(&self.server)
-            .and_then(|v| v.get(&self.user))
-            .and_then(Value::as_str)
-        {
-            Ok(String::from(token))
-        } else if let Some(token) = creds.get(&self.user).and_then(Value::as_str) {
-            Ok(String::from(token))
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "no token found",
-            ))
+    /// Reports progress on stderr, except in JSON mode where stdout carries
+    /// the only output.
+    fn status(&mut self, action: &dyn fmt::Display, message: &dyn fmt::Display) -> Result<()> {
+        if self.mode != OutputMode::Json {
+            status(&mut self.stderr, action, message)?;
         }
+        Ok(())
     }
 
-    fn save_credential(&self, user: String, token: String) -> Result<(), Box<dyn std::error::Error>> {
-        let config_dir = dirs::config_dir()
-            .unwrap_or("~/.config".into())
-            .join("fstn");
-        std::fs::create_dir_all(&config_dir)?;
-        let credentials_file = config_dir.join("credentials");
-        let mut credentials: Value = if credentials_file.exists() {
-            toml::from_slice(&std::fs::read(&credentials_file)?)?
-        } else {
-            Value::Table(Default::default())
+    /// Reports a step of a tree operation as progress, leaving out entries
+    /// that were already up to date.
+    fn step(&mut self, step: &transfer::Step, dry_run: bool) {
+        if step.action == transfer::Action::Unchanged {
+            return;
+        }
+        let action = match dry_run {
+            true => format!("Would {}", step.action.name()),
+            false => step.action.verb().to_string(),
         };
-        credentials.as_table_mut().and_then(|t| {
-            if let Some(server_table) = t.get_mut(&self.server) {
-                server_table.as_table_mut().and_then(|b| b.insert(user, Value::String(token)))
-            } else {
-                t.insert(
-                    self.server.clone(),
-                    Value::Table(toml::map::Map::from_iter([(
-                        user,
-                        Value::String(token),
-                    )])),
-                )
-            }
-        });
-        std::fs::write(credentials_file, toml::to_string(&credentials)?)?;
-        Ok(())
+        let mut message = step.path.to_string();
+        if let Some(bytes) = step.bytes {
+            message.push_str(&format!(" ({})", output::size_string(bytes)));
+        }
+        if let Some(note) = &step.note {
+            message.push_str(&format!(": {}", note));
+        }
+        let _ = self.status(&action, &message);
+    }
+
+    /// The settings of the selected profile.
+    fn settings(&self) -> Profile {
+        self.config.profiles.get(&self.profile).cloned().unwrap_or_default()
+    }
+
+    /// `label`, or else the selected profile's default label.
+    fn label(&self, label: Option<Label>) -> Label {
+        label.or_else(|| self.settings().label).unwrap_or_default()
+    }
+
+    /// Asks a yes-or-no question on stderr, taking anything but yes as no.
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        write!(self.stderr, "{} [y/N] ", question)?;
+        self.stderr.flush()?;
+        let mut answer = String::new();
+        stdin().lock().read_line(&mut answer)?;
+        Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
     }
 
-    fn token(&mut self, command: &str) -> Result<String, Box<dyn std::error::Error>>{
-        if let Ok(token) = self.check_credential() {
-            Ok(token)
+    /// Asks the user to log in by hand and paste the token.
+    fn paste_token(&mut self) -> Result<String> {
+        let prompt = format!(
+            "Please paste the API Token found by logging in at {} below\n> ",
+            self.client.login_url()
+        );
+        if self.mode == OutputMode::Json {
+            self.stderr.write_all(prompt.as_bytes())?;
+            self.stderr.flush()?;
         } else {
-            status(&mut self.stderr, &command, &"you must first login")?;
-            Err(EarlyExit.into())
+            self.stdout.write_all(prompt.as_bytes())?;
+            self.stdout.flush()?;
+        }
+        match stdin().lock().lines().next() {
+            Some(token) => Ok(token?.trim().to_string()),
+            None => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "no token given").into()),
         }
     }
 
-    fn invoke(&mut self, function: String, payload: String) -> Result<Response, Box<dyn std::error::Error>> {
-        let token = self.token("invoke")?;
-        let mut url = Url::parse(format!("{}/faasten/invoke", self.server).as_str())?;
-        url.path_segments_mut().map_err(|_| "cannot be base")?.push(&function);
-        let result = self.client
-            .post(url)
-            .bearer_auth(&token)
-            .header("content-type", "application/json")
-            .body(payload)
-            .send()?;
-        if result.status().is_success() {
-            status(&mut self.stderr, &"Invoke", &"OK")?;
-            Ok(result)
+    /// Sends the user through the gateway's login page in a browser and
+    /// captures the token it redirects back with.
+    fn browser_login(&mut self, timeout: Duration) -> Result<String> {
+        let login = LoopbackLogin::start()?;
+        let url = login.login_url(&self.client)?;
+        if login::open_browser(&url).is_err() {
+            self.status(&"Login", &format!("open {} in a browser to continue", url))?;
         } else {
-            status(&mut self.stderr, &"Invoke", &format!("{}", result.status()))?;
-            Ok(result)
+            self.status(&"Login", &"waiting for the browser (or use --no-browser)")?;
         }
+        login.wait(timeout)
     }
 
-    fn run(&mut self, action: Action) -> Result<(), Box<dyn std::error::Error>> {
-        match action {
-            Action::Login => {
-                write!(self.stdout,
-                    "Please paste the API Token found by logging in at {}/login/cas below\n> ",
-                    self.server
-                )?;
-                self.stdout.flush()?;
-                if let Some(Ok(token)) = stdin().lock().lines().next() {
-                    self.save_credential(self.user.clone(), token)?;
-                    status(&mut self.stderr, &"Login", &"saved")?;
-                }
+    fn config(&mut self, op: ConfigOp) -> Result<Report> {
+        let profile = self.profile.clone();
+        Ok(match op {
+            ConfigOp::Get { key } => match self.settings().get(key) {
+                Some(value) => Report::Config { profile, key, value: Some(value) },
+                None => return Err(FstnError::NotFound(format!("{} in profile {}", key.name(), profile))),
+            },
+            ConfigOp::Set { key, value } => {
+                let settings = self.config.profiles.entry(profile.clone()).or_default();
+                settings.set(key, &value)?;
+                let value = settings.get(key);
+                self.config.save()?;
+                self.status(&"Config", &format!("{}.{} saved", profile, key.name()))?;
+                Report::Config { profile, key, value }
             }
-            Action::Whoami => {
-                let token = self.token("whoami")?;
-                let url = Url::parse(format!("{}/me", self.server).as_str())?;
-                let mut result = self.client
-                    .get(url)
-                    .bearer_auth(&token)
-                    .header("content-type", "application/json")
-                    .send()?;
-                if result.status().is_success() {
-                    std::io::copy(&mut result, &mut stdout())?;
-                    status(&mut self.stderr, &"Whoami", &"OK")?;
-                } else {
-                    status(&mut self.stderr, &"Whoami", &format!("{}", result.status()))?;
-                    result.copy_to(&mut stdout())?;
+            ConfigOp::List => Report::Profiles { active: profile, profiles: self.config.profiles.clone() },
+            ConfigOp::Use { profile } => {
+                self.config.use_profile(&profile)?;
+                self.config.save()?;
+                self.status(&"Config", &format!("using profile {}", profile))?;
+                Report::UseProfile { profile }
+            }
+        })
+    }
+
+    fn auth(&mut self, op: AuthOp) -> Result<Report> {
+        let file = CredentialsFile::in_config_dir();
+        Ok(match op {
+            AuthOp::List => {
+                let migrated = file.migrate()?;
+                if !migrated.is_empty() {
+                    self.status(&"Migrated", &format!("{} legacy credential(s)", migrated.len()))?;
                 }
+                Report::Credentials { credentials: file.list()?, migrated }
             }
-            Action::Invoke(Invoke { function, payload }) => {
-                let payload = if let Some(p) = payload {
-                    p
-                } else {
-                    let mut buf = String::new();
-                    stdin().read_to_string(&mut buf)?;
-                    buf
+            AuthOp::Use { user } => {
+                let server = self.client.server().to_string();
+                file.set_default_user(&server, &user)?;
+                self.status(&"Auth", &format!("using {} on {}", user, server))?;
+                Report::AuthUse { server, user }
+            }
+            AuthOp::Inspect { token } => {
+                let token = match token {
+                    Some(token) if token == "-" => read_token(std::path::Path::new("-"))?,
+                    Some(token) => token,
+                    None => self.client.token()?.to_string(),
                 };
-                self.invoke(function, payload)?.copy_to(&mut stdout())?;
-            },
-            Action::FS(FS { op, masquerade }) => {
-                let function = if let Some(user) = masquerade {
-                    format!("home:<{},{}>:fsutil", user, user)
-                } else {
-                    "~:fsutil".into()
+                let token = match TokenInfo::decode(&token) {
+                    Some(info) => info,
+                    None => {
+                        let me = self.client.clone().with_token(token).whoami()?.bytes()?;
+                        let me = serde_json::from_slice(&me).map_err(|e| FstnError::Protocol(format!("/me: {}", e)))?;
+                        TokenInfo::from_me(&me)
+                    }
                 };
-                match op {
-                    FsOp::Ping => {
-                        let start = std::time::SystemTime::now();
-                        let payload = serde_json::json!({"op": "ping", "args": {}});
-                        self.invoke(function, serde_json::to_string(&payload)?)?;
-                        let elapsed = start.elapsed()?;
-                        write!(self.stdout, "{:?}\n", elapsed)?;
-                    },
-                    FsOp::Ls(OneArg { arg: path }) => {
-                        let payload = serde_json::json!({"op": "ls", "args": { "path": path.split(":").collect::<Vec<&str>>() }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-                    },
-                    FsOp::Unlink(TwoArgs { base, name }) => {
-                        let payload = serde_json::json!({"op": "unlink", "args": {
-                            "base": base.split(":").collect::<Vec<&str>>(),
-                            "name": name,
-                        }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-                    },
-                    FsOp::Mkdir(TwoArgsLabel { label, base, name }) => {
-                        let payload = serde_json::json!({"op": "mkdir", "args": {
-                            "base": base.split(":").collect::<Vec<&str>>(),
-                            "name": name,
-                            "label": label.unwrap_or("T,T".into()),
-                        }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-                    },
-                    FsOp::Mkfile(TwoArgsLabel { label, base, name }) => {
-                        let payload = serde_json::json!({"op": "mkfile", "args": {
-                            "base": base.split(":").collect::<Vec<&str>>(),
-                            "name": name,
-                            "label": label.unwrap_or("T,T".into()),
-                        }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-                    },
-                    FsOp::Write(OneArg { arg: path }) => {
-                        let mut data = Vec::new();
-                        stdin().read_to_end(&mut data)?;
-
-                        #[serde_as]
-                        #[derive(Serialize)]
-                        struct WriteArgs<'a> {
-                            path: Vec<&'a str>,
-                            #[serde_as(as="Base64")]
-                            data: Vec<u8>
-                        }
+                Report::Inspect { token }
+            }
+            AuthOp::Prune { older_than } => {
+                let removed = file.prune(older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60)))?;
+                self.status(&"Pruned", &format!("{} credential(s)", removed.len()))?;
+                Report::Prune { removed }
+            }
+        })
+    }
 
-                        let payload = serde_json::json!({"op": "write", "args": WriteArgs {
-                            path: path.split(":").collect::<Vec<&str>>(),
-                            data: data,
-                        }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-                    },
-                    FsOp::Read(OneArg { arg: path }) => {
-                        let payload = serde_json::json!({"op": "read", "args": {
-                            "path": path.split(":").collect::<Vec<&str>>(),
-                        }});
-
-                        #[serde_as]
-                        #[derive(Deserialize)]
-                        struct ReadResult {
-                            success: bool,
-                            #[serde_as(as="Base64")]
-                            value: Vec<u8>
-                        }
+    fn logout(&mut self, all: bool) -> Result<Report> {
+        let file = CredentialsFile::in_config_dir();
+        let server = self.client.server().to_string();
+        let users = if all {
+            // Legacy flat entries stand in for this server too.
+            let mut users: Vec<_> = file
+                .list()?
+                .into_iter()
+                .filter(|c| c.server == server || c.server == credentials::ANY_SERVER)
+                .map(|c| c.user)
+                .collect();
+            users.sort_unstable();
+            users.dedup();
+            users
+        } else {
+            vec![self.client.user().to_string()]
+        };
+        let users = file.remove(&server, &users)?;
+        if !all && users.is_empty() {
+            return Err(FstnError::NoCredentials { server, user: self.client.user().to_string() });
+        }
+        self.status(&"Logout", &format!("removed {} credential(s)", users.len()))?;
+        Ok(Report::Logout { server, users })
+    }
 
-                        let result: ReadResult = self.invoke(function, serde_json::to_string(&payload)?)?.json()?;
-                        if result.success {
-                            self.stdout.write_all(&result.value)?;
-                        } else {
-                            self.stderr.write_all(b"Not found")?;
-                            Err(EarlyExit)?;
-                        }
+    fn fs(&mut self, fs: &Fs, op: FsOp) -> Result<Report> {
+        let (op, result) = match op {
+            FsOp::Ping => {
+                let elapsed = fs.ping()?;
+                return Ok(Report::Ping { target: "fsutil", elapsed });
+            }
+            FsOp::Ls(LsArgs { path, recursive, depth, long, kind, sort, facet }) => {
+                let path = select_facet(fs, path, facet)?;
+                let depth = depth.map(|d| d as usize);
+                let depth = if recursive { depth } else { Some(depth.unwrap_or(1)) };
+                let mut entries = fs.walk(&path, depth, |a, b| match sort {
+                    LsSort::Name => a.name.cmp(&b.name),
+                    LsSort::Kind => (a.kind, &a.name).cmp(&(b.kind, &b.name)),
+                })?;
+                if !kind.is_empty() {
+                    entries.retain(|e| e.entry.kind.is_some_and(|k| kind.contains(&k)));
+                }
+                return Ok(Report::Ls { path, entries, long });
+            }
+            FsOp::Unlink(TwoArgs { base, name }) => ("unlink", fs.unlink(&base, &name)?),
+            FsOp::Mkdir(MkdirArgs { parents: false, label, base, name }) => {
+                ("mkdir", fs.mkdir(&base, &name, &self.label(label))?)
+            }
+            FsOp::Mkdir(MkdirArgs { parents: true, label, base, name }) => {
+                let created = fs.mkdir_all(&base.child(&name), &self.label(label))?;
+                self.status(&"Created", &count(created.len(), "directory", "directories"))?;
+                return Ok(Report::Changes { op: "mkdir", created, removed: Vec::new() });
+            }
+            FsOp::Mkfile(MkfileArgs { label, facet, base, name }) => {
+                let base = select_facet(fs, base, facet)?;
+                ("mkfile", fs.mkfile(&base, &name, &self.label(label))?)
+            }
+            FsOp::Write(ChunkedArgs { chunk_size, facet, path }) => {
+                let path = select_facet(fs, path, facet)?;
+                ("write", fs.write_from(&path, &mut stdin().lock(), chunk_size)?)
+            }
+            // Text output is the file itself, so it goes straight to stdout
+            // rather than through memory.
+            FsOp::Read(ChunkedArgs { chunk_size, facet, path }) if self.mode != OutputMode::Json => {
+                let path = select_facet(fs, path, facet)?;
+                fs.read_to(&path, &mut self.stdout, chunk_size)?;
+                return Ok(Report::Streamed);
+            }
+            // JSON output is only written once the whole file has arrived,
+            // so that a failed read leaves just the error on stdout.
+            FsOp::Read(ChunkedArgs { chunk_size, facet, path }) => {
+                let path = select_facet(fs, path, facet)?;
+                let mut contents = fstn::spool()?;
+                fs.read_to(&path, &mut contents, chunk_size)?;
+                contents.seek(SeekFrom::Start(0))?;
+                output::render_contents("read", &mut contents, &mut self.stdout)?;
+                return Ok(Report::Streamed);
+            }
+            FsOp::Mkgate(MkGateArgs { label, privilege, clearance, base, name, memory, kernel, runtime, gate, app_image }) => {
+                let function = gate_function(memory, app_image, kernel, runtime, gate);
+                ("mkgate", fs.mkgate(&base, &name, &label, &privilege, &clearance, function)?)
+            }
+            FsOp::Upgate(UpgateArgs { privilege, clearance, memory, app_image, kernel, runtime, gate, path }) => {
+                let function = gate_function(memory, app_image, kernel, runtime, gate);
+                ("upgate", fs.upgate(&path, privilege.as_ref(), clearance.as_ref(), function)?)
+            }
+            FsOp::Mkblob(MkBlobArgs { label, chunk_size, base, files }) => {
+                ("mkblob", fs.mkblob(&base, &self.label(label), &files, chunk_size)?)
+            }
+            FsOp::Cat(OneArg { arg: path }) => ("cat", FsResponse::Contents(fs.cat(&path)?)),
+            FsOp::Stat(OneArg { arg: path }) => {
+                let stat = fs.stat(&path)?;
+                return Ok(Report::Stat { path, stat });
+            }
+            FsOp::Facets(OneArg { arg: path }) => {
+                let facets = fs.facets(&path)?;
+                return Ok(Report::Facets { path, facets });
+            }
+            FsOp::Mkfaceted(TwoArgs { base, name }) => ("mkfaceted", fs.mkfaceted(&base, &name)?),
+            FsOp::Mksvc(TwoArgsLabel { base, name, label }) => {
+                let mksvc: MkSvc = serde_json::from_reader(stdin())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                ("mksvc", fs.mksvc(&base, &name, label.as_ref(), mksvc)?)
+            }
+            FsOp::Invoke(InvokeArgs { path, params }) => {
+                let data = read_stdin()?;
+                ("invoke", FsResponse::Contents(fs.invoke(&path, data, params.into_iter().collect())?))
+            }
+            FsOp::Put(PutArgs { recursive, label, blob_threshold, local, base }) => {
+                let options = PutOptions { label: self.label(label), blob_threshold, recursive };
+                let steps = transfer::put(fs, &local, &base, &options, &mut |step| self.step(step, false))?;
+                return Ok(Report::Transfer { op: "put", dry_run: false, summary: Summary::of(&steps), steps });
+            }
+            FsOp::Get(GetArgs { recursive, path, local }) => {
+                let steps = transfer::get(fs, &path, &local, recursive, &mut |step| self.step(step, false))?;
+                return Ok(Report::Transfer { op: "get", dry_run: false, summary: Summary::of(&steps), steps });
+            }
+            FsOp::Fetch(FetchArgs { verify, path, local_file }) => {
+                let step = transfer::fetch(fs, &path, &local_file, verify)?;
+                self.step(&step, false);
+                let steps = vec![step];
+                return Ok(Report::Transfer { op: "fetch", dry_run: false, summary: Summary::of(&steps), steps });
+            }
+            FsOp::Rm(RmArgs { recursive, force, path }) => {
+                if !recursive && fs.lookup(&path)?.kind.is_some_and(EntryKind::is_directory) {
+                    return Err(FstnError::InvalidPath(format!("{} is a directory; use -r to remove it", path)));
+                }
+                let mut confirmed = Ok(true);
+                let removed = fs.remove_all(&path, |doomed| {
+                    if !recursive || force {
+                        return true;
                     }
-                    FsOp::Mkgate(MkGateArgs { label, privilege, clearance, base, name, memory, kernel, runtime, gate, app_image }) => {
-
-                        #[derive(Debug, Serialize, Deserialize)]
-                        struct MkgateArgs {
-                            label: String,
-                            privilege: String,
-                            clearance: String,
-                            base: Vec<String>,
-                            name: String,
-                            memory: Option<u64>,
-                            app_image: Option<Vec<String>>,
-                            kernel: Option<Vec<String>>,
-                            runtime: Option<Vec<String>>,
-                            gate: Option<Vec<String>>,
-                        }
-
-                        let mut args = MkgateArgs {
-                            label,
-                            privilege,
-                            clearance,
-                            base: base.split(":").map(ToString::to_string).collect(),
-                            name,
-                            memory,
-                            app_image: None,
-                            kernel: None,
-                            runtime: None,
-                            gate: gate.map(|g| g.split(":").map(ToString::to_string).collect()),
-                        };
-
-                        let mut form = reqwest::blocking::multipart::Form::new();
-
-                        if let Some(app_image) = app_image {
-                            if let Some(local_app) = app_image.strip_prefix("@") {
-                                form = form.part("blob", reqwest::blocking::multipart::Part::file(local_app)?
-                                                    .mime_str("application/octet-stream")?
-                                                    .file_name("app_image"));
-                            } else {
-                                args.app_image = Some(app_image.split(":").map(ToString::to_string).collect());
-                            }
-                        }
-
-                        if let Some(kernel) = kernel {
-                            if let Some(local_kernel) = kernel.strip_prefix("@") {
-                                form = form.part("blob", reqwest::blocking::multipart::Part::file(local_kernel)?
-                                                    .mime_str("application/octet-stream")?
-                                                    .file_name("kernel"));
-                            } else {
-                                args.kernel = Some(kernel.split(":").map(ToString::to_string).collect());
-                            }
-                        }
-
-                        if let Some(runtime) = runtime {
-                            if let Some(local_runtime) = runtime.strip_prefix("@") {
-                                form = form.part("blob", reqwest::blocking::multipart::Part::file(local_runtime)?
-                                                    .mime_str("application/octet-stream")?
-                                                    .file_name("runtime"));
-                            } else {
-                                args.runtime = Some(runtime.split(":").map(ToString::to_string).collect());
-                            }
-                        }
-
-                        let payload = serde_json::json!({
-                            "op": "mkgate",
-                            "args": args,
-                        });
-
-                        form = form.text("payload", serde_json::to_string(&payload)?);
-                        let token = self.token("invoke")?;
-                        let mut url = Url::parse(format!("{}/faasten/invoke", self.server).as_str())?;
-                        url.path_segments_mut().map_err(|_| "cannot be base")?.push(&function);
-                        let mut result = self.client
-                            .post(url)
-                            .bearer_auth(&token)
-                            .multipart(form)
-                            .send()?;
-                        if result.status().is_success() {
-                            status(&mut self.stderr, &"Invoke", &"OK")?;
-                            result.copy_to(&mut self.stdout)?;
-                        } else {
-                            status(&mut self.stderr, &"Invoke", &format!("{}", result.status()))?;
-                            result.copy_to(&mut self.stderr)?;
-                        }
-                    },
-                    FsOp::Upgate(UpgateArgs { privilege, clearance, memory, app_image, kernel, runtime, gate, path }) => {
-                        #[derive(Debug, Serialize, Deserialize)]
-                        struct UpgatePayload {
-                            privilege: Option<String>,
-                            clearance: Option<String>,
-                            memory: Option<u64>,
-                            app_image: Option<Vec<String>>,
-                            kernel: Option<Vec<String>>,
-                            runtime: Option<Vec<String>>,
-                            gate: Option<Vec<String>>,
-                            path: Vec<String>,
-                        }
-
-                        let mut args = UpgatePayload {
-                            privilege,
-                            clearance,
-                            memory,
-                            app_image: None,
-                            kernel: None,
-                            runtime: None,
-                            gate: gate.map(|g| g.split(":").map(ToString::to_string).collect()),
-                            path: path.split(":").map(ToString::to_string).collect(),
-                        };
-
-
-                        let mut form = reqwest::blocking::multipart::Form::new();
-
-                        if let Some(app_image) = app_image {
-                            if let Some(local_app) = app_image.strip_prefix("@") {
-                                form = form.part("blob", reqwest::blocking::multipart::Part::file(local_app)?
-                                                    .mime_str("application/octet-stream")?
-                                                    .file_name("app_image"));
-                            } else {
-                                args.app_image = Some(app_image.split(":").map(ToString::to_string).collect());
-                            }
-                        }
-
-                        if let Some(kernel) = kernel {
-                            if let Some(local_kernel) = kernel.strip_prefix("@") {
-                                form = form.part("blob", reqwest::blocking::multipart::Part::file(local_kernel)?
-                                                    .mime_str("application/octet-stream")?
-                                                    .file_name("kernel"));
-                            } else {
-                                args.kernel = Some(kernel.split(":").map(ToString::to_string).collect());
-                            }
-                        }
-
-                        if let Some(runtime) = runtime {
-                            if let Some(local_runtime) = runtime.strip_prefix("@") {
-                                form = form.part("blob", reqwest::blocking::multipart::Part::file(local_runtime)?
-                                                    .mime_str("application/octet-stream")?
-                                                    .file_name("runtime"));
-                            } else {
-                                args.runtime = Some(runtime.split(":").map(ToString::to_string).collect());
-                            }
-                        }
-
-                        let payload = serde_json::json!({
-                            "op": "upgate",
-                            "args": args,
-                        });
-
-                        form = form.text("payload", serde_json::to_string(&payload)?);
-                        let token = self.token("invoke")?;
-                        let mut url = Url::parse(format!("{}/faasten/invoke", self.server).as_str())?;
-                        url.path_segments_mut().map_err(|_| "cannot be base")?.push(&function);
-                        let mut result = self.client
-                            .post(url)
-                            .bearer_auth(&token)
-                            .multipart(form)
-                            .send()?;
-                        if result.status().is_success() {
-                            status(&mut self.stderr, &"Invoke", &"OK")?;
-                            result.copy_to(&mut self.stdout)?;
-                        } else {
-                            status(&mut self.stderr, &"Invoke", &format!("{}", result.status()))?;
-                            result.copy_to(&mut self.stderr)?;
-                        }
-                    },
-                    FsOp::Mkblob(MkBlobArgs { label, base, files }) => {
-                        let payload = serde_json::json!({
-                            "op": "mkblob",
-                            "args": {
-                                "label": label.unwrap_or("T,T".into()),
-                                "base": base.split(":").collect::<Vec<&str>>(),
-                            }
-                        });
-                        let mut form = reqwest::blocking::multipart::Form::new()
-                            .text("payload", serde_json::to_string(&payload)?);
-
-                        for file in files {
-                            let file_name = std::path::Path::new(&file)
-                                .file_name()
-                                .and_then(|f| f.to_str())
-                                .map(|f| f.to_string()).expect("File name");
-                            form = form.part("blob", reqwest::blocking::multipart::Part::file(file)?
-                                             .mime_str("application/octet-stream")?
-                                             .file_name(file_name));
-                        }
-                        let token = self.token("invoke")?;
-                        let mut url = Url::parse(format!("{}/faasten/invoke", self.server).as_str())?;
-                        url.path_segments_mut().map_err(|_| "cannot be base")?.push(&function);
-                        let mut result = self.client
-                            .post(url)
-                            .bearer_auth(&token)
-                            .multipart(form)
-                            .send()?;
-                        if result.status().is_success() {
-                            status(&mut self.stderr, &"Invoke", &"OK")?;
-                            result.copy_to(&mut self.stdout)?;
-                        } else {
-                            status(&mut self.stderr, &"Invoke", &format!("{}", result.status()))?;
-                            result.copy_to(&mut self.stderr)?;
-                        }
-                    },
-                    FsOp::Cat(OneArg { arg: path }) => {
-                        let payload = serde_json::json!({"op": "cat", "args": {
-                            "path": path.split(":").collect::<Vec<&str>>(),
-                        }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
+                    let question = match doomed.len() {
+                        1 => format!("Remove {}?", path),
+                        n => format!("Remove {} and {} below it?", path, count(n - 1, "entry", "entries")),
+                    };
+                    confirmed = self.confirm(&question);
+                    matches!(confirmed, Ok(true))
+                })?;
+                if !confirmed? {
+                    return Err(FstnError::Cancelled("nothing was removed".into()));
+                }
+                self.status(&"Removed", &count(removed.len(), "entry", "entries"))?;
+                return Ok(Report::Changes { op: "rm", created: Vec::new(), removed });
+            }
+            FsOp::Cp(CpArgs { recursive, label, src, base, name }) => {
+                let created = fs.copy(&src, &base, &name, label.as_ref(), recursive)?;
+                self.status(&"Copied", &format!("{} to {}", src, base.child(&name)))?;
+                return Ok(Report::Changes { op: "cp", created, removed: Vec::new() });
+            }
+            FsOp::Mv(MvArgs { src, base, name }) => {
+                let (created, removed) = match fs.rename(&src, &base, &name)? {
+                    Some(created) => {
+                        self.status(&"Moved", &format!("{} to {}", src, base.child(&name)))?;
+                        (created, vec![src])
                     }
-                    FsOp::Mkfaceted(TwoArgs { base, name }) => {
-                        let payload = serde_json::json!({"op": "mkfaceted", "args": {
-                            "base": base.split(":").collect::<Vec<&str>>(),
-                            "name": name,
-                        }});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-                    },
-                    FsOp::Mksvc(TwoArgsLabel { base, name, label }) => {
-                        #[derive(Serialize_repr, Deserialize, PartialEq, Debug)]
-                        #[repr(u8)]
-                        enum Verb {
-                            HEAD = 0,
-                            GET = 1,
-                            POST = 2,
-                            PUT = 3,
-                            DELETE = 4,
-                        }
-
-                        #[serde_as]
-                        #[derive(Debug, Serialize, Deserialize)]
-                        struct MkSvc {
-                            base: Option<Vec<String>>,
-                            name: Option<String>,
-                            label: Option<String>,
-                            privilege: String,
-                            clearance: String,
-                            taint: String,
-                            url: String,
-                            verb: Verb,
-                            headers: std::collections::HashMap<String, String>,
-                        }
-
-                        let mut mksvc: MkSvc = serde_json::from_reader(stdin())?;
-
-                        mksvc.base = Some(base.split(":").map(ToString::to_string).collect());
-                        mksvc.name = Some(name);
-                        mksvc.label = label;
-
-                        let payload = serde_json::json!({"op": "mksvc", "args": mksvc});
-                        self.invoke(function, serde_json::to_string(&payload)?)?.copy_to(&mut self.stdout)?;
-
-                    },
-                    FsOp::Invoke(InvokeArgs { path, params }) => {
-                        let mut data = Vec::new();
-                        stdin().read_to_end(&mut data)?;
-
-                        let params = params.iter().map(Clone::clone).collect();
-
-                        #[serde_as]
-                        #[derive(Serialize)]
-                        struct InvokeArgs<'a> {
-                            path: Vec<&'a str>,
-                            sync: bool,
-                            #[serde_as(as="Base64")]
-                            payload: Vec<u8>,
-                            params: HashMap<String, String>
-                        }
-
-                        #[serde_as]
-                        #[derive(Deserialize)]
-                        struct InvokeResult {
-                            #[allow(dead_code)]
-                            success: Option<bool>,
-                            #[serde_as(as="Base64")]
-                            #[serde(default)]
-                            data: Option<Vec<u8>>,
-                            error: Option<serde_json::Value>,
-                        }
-
-                        let payload = serde_json::json!({"op": "invoke", "args": InvokeArgs {
-                            path: path.split(":").collect::<Vec<&str>>(),
-                            sync: true,
-                            payload: data,
-                            params,
-                        }});
-                        let result: InvokeResult = self.invoke(function, serde_json::to_string(&payload)?)?.json()?;
-                        if let Some(data) = result.data {
-                            self.stdout.write_all(&data)?;
-                        } else {
-                            self.stderr.write_all(&serde_json::to_vec(&result.error)?)?;
-                        }
+                    None => {
+                        self.status(&"Unchanged", &format!("{} was already moved to {}", src, base.child(&name)))?;
+                        (Vec::new(), Vec::new())
                     }
                 };
-            },
-            Action::Delegate(Delegate { save, privilege, bootstrap, clearance }) => {
-                if let Ok(token) = self.check_credential() {
-                    let url = Url::parse(format!("{}/faasten/delegate", self.server).as_str())?;
-                    let mut result = self.client
-                        .post(url)
-                        .bearer_auth(&token)
-                        .header("content-type", "application/json")
-                        .json(&serde_json::json!({
-                            "component": privilege,
-                            "bootstrap": bootstrap,
-                            "clearance": clearance,
-                        }))
-                        .send()?;
-                    if result.status().is_success() {
-                        let mut token = String::new();
-                        result.read_to_string(&mut token)?;
-                        self.stdout.write_all(token.as_bytes())?;
-                        if save {
-                            self.save_credential(privilege, token)?;
-                        }
-                        status(&mut self.stderr, &"Delegate", &"OK")?;
-                    } else {
-                        status(&mut self.stderr, &"Delegate", &format!("{}", result.status()))?;
-                        result.copy_to(&mut stdout())?;
-                    }
-                } else {
-                    status(&mut self.stderr, &"Delegate", &"you must first login")?;
+                return Ok(Report::Changes { op: "mv", created, removed });
+            }
+            FsOp::Ln(LnArgs { label, privilege, clearance, target, base, name }) => {
+                let created = fs.link(&target, &base, &name, label.as_ref(), &privilege, &clearance)?;
+                self.status(&"Linked", &format!("{} to {}", base.child(&name), target))?;
+                return Ok(Report::Changes { op: "ln", created, removed: Vec::new() });
+            }
+            FsOp::Sync(SyncArgs { download, delete, dry_run, checksum, label, blob_threshold, local, remote }) => {
+                let label = self.label(label);
+                let options = SyncOptions { label, blob_threshold, download, delete, dry_run, checksum };
+                let steps = transfer::sync(fs, &local, &remote, &options, &mut |step| self.step(step, dry_run))?;
+                return Ok(Report::Transfer { op: "sync", dry_run, summary: Summary::of(&steps), steps });
+            }
+        };
+        self.status(&"Invoke", &"OK")?;
+        Ok(Report::Fs { op, result })
+    }
+
+    fn run(&mut self, action: Action) -> Result<Report> {
+        Ok(match action {
+            Action::Login(Login { no_browser, with_token, timeout }) => {
+                let token = match with_token {
+                    Some(file) => read_token(&file)?,
+                    None if no_browser => self.paste_token()?,
+                    None => self.browser_login(Duration::from_secs(timeout))?,
+                };
+                let store = self.settings().credential_store.unwrap_or_default();
+                credentials::save(store, self.client.server(), self.client.user(), &token, None)?;
+                self.status(&"Login", &"saved")?;
+                Report::Login {
+                    server: self.client.server().to_string(),
+                    user: self.client.user().to_string(),
                 }
-            },
-            Action::Ping(Ping {}) => {
-                let now = Instant::now();
-                let url = Url::parse(format!("{}/faasten/ping", self.server).as_str())?;
-                let _ = self.client.get(url).send()?;
-                write!(self.stdout, "ping: {:?} elapsed", now.elapsed())?;
             }
-            Action::PingScheduler(PingScheduler {}) => {
-                let now = Instant::now();
-                let url = Url::parse(format!("{}/faasten/ping/scheduler", self.server).as_str())?;
-                let _ = self.client.get(url).send()?;
-                write!(self.stdout, "ping: {:?} elapsed", now.elapsed())?;
-            },
-            Action::Build(Build { source_dir, output }) => {
-                use std::os::unix::fs::PermissionsExt;
-                let mut output = std::fs::File::create(output.unwrap_or("function.img".into()))?;
-                let mut fswriter = backhand::FilesystemWriter::default();
-                fswriter.set_root_mode(0o555);
-
-                fn write_dir(fs: &mut backhand::FilesystemWriter, path: PathBuf, prefix: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-                    for entry in std::fs::read_dir(path)? {
-                        let entry = entry?;
-                        let meta = entry.metadata()?;
-                        let permissions = entry.metadata()?.permissions().mode();
-                        if meta.is_file() {
-                            fs.push_file(std::fs::File::open(entry.path()).unwrap(),
-                                            prefix.join(entry.file_name()),
-                                            NodeHeader::new(permissions as u16, 0, 0, 0)).unwrap();
-                        } else if meta.is_dir() {
-                            let next_prefix = prefix.join(entry.file_name());
-                            fs.push_dir(next_prefix.clone(), NodeHeader::new(permissions as u16, 0, 0, 0))?;
-                            write_dir(fs, entry.path(), next_prefix)?;
+            Action::Logout(Logout { all }) => self.logout(all)?,
+            Action::Auth(AuthArgs { op }) => self.auth(op)?,
+            Action::Whoami => {
+                let identity = self.client.identity()?;
+                let fsutil_exists = match self.client.fs(None)?.ls(&identity.home) {
+                    Ok(entries) => entries.iter().any(|e| e.name == "fsutil"),
+                    Err(e @ FstnError::Network(_)) => return Err(e),
+                    Err(_) => false,
+                };
+                let server = self.client.server().to_string();
+                let user = self.client.user().to_string();
+                let store = CredentialsFile::in_config_dir()
+                    .list()?
+                    .into_iter()
+                    .find(|c| c.server == server && c.user == user)
+                    .map(|c| c.credential.store());
+                self.status(&"Whoami", &"OK")?;
+                Report::Whoami { identity, server, user, store, profile: self.profile.clone(), fsutil_exists }
+            }
+            Action::Invoke(Invoke { function, payload }) => {
+                let payload = match payload {
+                    Some(p) => p.into_bytes(),
+                    None => read_stdin()?,
+                };
+                let output = self.client.invoke(&function, payload)?.bytes()?.to_vec();
+                self.status(&"Invoke", &"OK")?;
+                Report::Invoke { function, output }
+            }
+            Action::FS(fs) => {
+                let FS { op, masquerade, no_blob_cache } = *fs;
+                let masquerade = masquerade.or_else(|| self.settings().masquerade);
+                let client = self.client.clone();
+                let mut fs = client.fs(masquerade.as_deref())?;
+                if !no_blob_cache {
+                    fs = fs.with_blob_cache(BlobCache::in_config_dir());
+                }
+                self.fs(&fs, op)?
+            }
+            Action::Delegate(Delegate { op: Some(DelegateOp::Tree { discard }), .. }) => {
+                let record = DelegationRecord::in_config_dir();
+                let discarded = match discard {
+                    Some(id) => {
+                        let discarded = record.discard(id, &CredentialsFile::in_config_dir())?;
+                        if discarded.is_empty() {
+                            return Err(FstnError::NotFound(format!("delegation {}", id)));
                         }
+                        self.status(&"Discarded", &format!("{} delegation(s)", discarded.len()))?;
+                        discarded
                     }
-                    Ok(())
+                    None => Vec::new(),
+                };
+                Report::DelegationTree { delegations: record.list()?, discarded }
+            }
+            Action::Delegate(Delegate { op: None, save, privilege, bootstrap, clearance, ttl, from }) => {
+                let privilege = privilege.expect("clap requires a privilege");
+                let client = match &from {
+                    Some(user) => self.client.as_user(user)?,
+                    None => self.client.clone(),
+                };
+                let token = client.delegate(&privilege, bootstrap, clearance.as_ref(), ttl)?;
+                let issued = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                let expires = TokenInfo::decode(&token)
+                    .and_then(|t| t.expires_at)
+                    .or(ttl.map(|ttl| (issued + ttl).as_secs()));
+                let privilege = privilege.to_string();
+                if save {
+                    let store = self.settings().credential_store.unwrap_or_default();
+                    credentials::save(store, client.server(), &privilege, &token, Some(client.user()))?;
                 }
-
-                write_dir(&mut fswriter, source_dir, "/".into())?;
-
-                fswriter.write(&mut output)?;
+                let delegation = DelegationRecord::in_config_dir().add(Delegation {
+                    id: 0,
+                    parent: None,
+                    server: client.server().to_string(),
+                    from: client.user().to_string(),
+                    privilege: privilege.clone(),
+                    clearance: clearance.map(|c| c.to_string()),
+                    bootstrap,
+                    issued: issued.as_secs(),
+                    expires,
+                    saved_as: save.then(|| privilege.clone()),
+                })?;
+                self.status(&"Delegate", &"OK")?;
+                Report::Delegate { id: delegation.id, privilege, token, saved: save, expires_at: expires }
             }
-        }
-        Ok(())
+            Action::Ping(Ping {}) => Report::Ping { target: "gateway", elapsed: self.client.ping()? },
+            Action::PingScheduler(PingScheduler {}) => {
+                Report::Ping { target: "scheduler", elapsed: self.client.ping_scheduler()? }
+            }
+            Action::Build(Build { source_dir, image }) => {
+                let output = image.unwrap_or("function.img".into());
+                fstn::image::build(&source_dir, &output)?;
+                let bytes = std::fs::metadata(&output)?.len();
+                self.status(&"Build", &output.display())?;
+                Report::Build { output, bytes }
+            }
+            Action::Config(ConfigArgs { op }) => self.config(op)?,
+        })
     }
 }
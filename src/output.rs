@@ -0,0 +1,881 @@
+//! Rendering command results for humans and scripts.
+//!
+//! Every command produces a [`Report`], which can be written in one of three
+//! [`OutputMode`]s:
+//!
+//! * `text` writes invocation output and file contents byte for byte, and a
+//!   short rendering of anything else; unlike earlier versions of fstn, it
+//!   does not pass the gateway's replies through unchanged;
+//! * `json` writes a single JSON document tagged with the command name;
+//! * `table` writes aligned columns meant for interactive use.
+
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde_derive::Serialize;
+use serde_with::base64::Base64;
+use serde_with::serde_as;
+use termcolor::{Color, ColorChoice, ColorSpec, NoColor, StandardStream, WriteColor};
+
+use crate::config::{Profile, ProfileKey};
+use crate::credentials::{SavedCredential, Store, ANY_SERVER};
+use crate::delegation::Delegation;
+use crate::client::{Facet, ListedEntry};
+use crate::fsutil::{EntryKind, FsResponse, Stat};
+use crate::path::FsPath;
+use crate::token::{TokenInfo, TokenSource};
+use crate::transfer::{Step, Summary};
+use crate::{FstnError, Identity};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputMode {
+    /// Raw passthrough of the gateway's reply
+    #[default]
+    Text,
+    /// A JSON document per command
+    Json,
+    /// Aligned columns
+    Table,
+}
+
+/// The result of a single command.
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Report {
+    Login {
+        server: String,
+        user: String,
+    },
+    Whoami {
+        identity: Identity,
+        server: String,
+        /// The saved credential used, and where it is kept.
+        user: String,
+        store: Option<Store>,
+        profile: String,
+        /// Whether the home directory holds the `fsutil` gate.
+        fsutil_exists: bool,
+    },
+    Delegate {
+        /// The delegation's id in the local record.
+        id: u64,
+        privilege: String,
+        token: String,
+        saved: bool,
+        expires_at: Option<u64>,
+    },
+    Invoke {
+        function: String,
+        #[serde_as(as = "Base64")]
+        output: Vec<u8>,
+    },
+    Ping {
+        target: &'static str,
+        #[serde(rename = "elapsed_ms", serialize_with = "millis")]
+        elapsed: Duration,
+    },
+    Fs {
+        op: &'static str,
+        result: FsResponse,
+    },
+    /// An fs op whose result was written out as it arrived, by
+    /// [`render_contents`], leaving nothing more to render.
+    #[serde(skip_serializing)]
+    Streamed,
+    /// A typed listing from `fstn fs ls`, recursive with `-R`.
+    Ls {
+        path: FsPath,
+        entries: Vec<ListedEntry>,
+        /// Whether text output shows kinds and labels.
+        #[serde(skip)]
+        long: bool,
+    },
+    /// The facets of a faceted directory, from `fstn fs facets`.
+    Facets {
+        path: FsPath,
+        facets: Vec<Facet>,
+    },
+    /// What `fstn fs stat` found out about an entry.
+    Stat {
+        path: FsPath,
+        #[serde(flatten)]
+        stat: Stat,
+    },
+    /// The entries a tree operation such as `fstn fs put` created, wrote
+    /// or skipped.
+    Transfer {
+        op: &'static str,
+        /// Whether the steps were only planned, not taken.
+        dry_run: bool,
+        steps: Vec<Step>,
+        summary: Summary,
+    },
+    /// The entries an fs command such as `fstn fs rm -r` created and
+    /// removed, in the order it did so.
+    Changes {
+        op: &'static str,
+        created: Vec<FsPath>,
+        removed: Vec<FsPath>,
+    },
+    Build {
+        output: PathBuf,
+        bytes: u64,
+    },
+    /// A setting read or written by `fstn config get/set`.
+    Config {
+        profile: String,
+        key: ProfileKey,
+        value: Option<String>,
+    },
+    Profiles {
+        active: String,
+        profiles: BTreeMap<String, Profile>,
+    },
+    UseProfile {
+        profile: String,
+    },
+    /// Saved credentials, listed by `fstn auth list`.
+    Credentials {
+        credentials: Vec<SavedCredential>,
+        /// Users whose legacy flat entries were moved under their server.
+        migrated: Vec<String>,
+    },
+    AuthUse {
+        server: String,
+        user: String,
+    },
+    Prune {
+        removed: Vec<SavedCredential>,
+    },
+    Logout {
+        server: String,
+        users: Vec<String>,
+    },
+    /// The local record of delegations, after discarding any given to
+    /// `fstn delegate tree --discard`.
+    DelegationTree {
+        delegations: Vec<Delegation>,
+        discarded: Vec<Delegation>,
+    },
+    /// What a token grants, from `fstn auth inspect`.
+    Inspect {
+        #[serde(flatten)]
+        token: TokenInfo,
+    },
+}
+
+fn millis<S: serde::Serializer>(elapsed: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(elapsed.as_secs_f64() * 1000.0)
+}
+
+impl Report {
+    /// The rows a whoami report shows outside JSON mode.
+    fn whoami_fields(&self) -> Vec<(&'static str, String)> {
+        let Report::Whoami { identity, server, user, store, profile, fsutil_exists } = self else {
+            return Vec::new();
+        };
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+        let store = match store {
+            Some(Store::File) => "credentials file",
+            Some(Store::Keyring) => "keyring",
+            None => "not saved",
+        };
+        vec![
+            ("login", identity.login.clone()),
+            ("privilege", or_dash(identity.privilege.as_ref().map(ToString::to_string))),
+            ("clearance", or_dash(identity.clearance.as_ref().map(ToString::to_string))),
+            ("home", identity.home.to_string()),
+            (
+                "fsutil",
+                format!("{} ({})", identity.fsutil, if *fsutil_exists { "present" } else { "missing" }),
+            ),
+            ("server", server.clone()),
+            ("credential", format!("{} ({})", user, store)),
+            ("profile", profile.clone()),
+        ]
+    }
+
+    pub fn render(&self, mode: OutputMode, out: &mut dyn Write) -> io::Result<()> {
+        self.render_color(mode, &mut NoColor::new(out))
+    }
+
+    /// Like [`Report::render`], but colors listings where `out` supports it.
+    pub fn render_color(&self, mode: OutputMode, out: &mut dyn WriteColor) -> io::Result<()> {
+        if let Report::Streamed = self {
+            return Ok(());
+        }
+        match mode {
+            OutputMode::Text => self.text(out),
+            OutputMode::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)
+            }
+            OutputMode::Table => self.table(out),
+        }
+    }
+
+    fn text(&self, out: &mut dyn WriteColor) -> io::Result<()> {
+        match self {
+            Report::Login { .. }
+            | Report::Build { .. }
+            | Report::UseProfile { .. }
+            | Report::AuthUse { .. }
+            | Report::Logout { .. }
+            | Report::Streamed => Ok(()),
+            Report::Whoami { .. } => {
+                for (field, value) in self.whoami_fields() {
+                    writeln!(out, "{:<11} {}", format!("{}:", field), value)?;
+                }
+                Ok(())
+            }
+            Report::Delegate { token, .. } => out.write_all(token.as_bytes()),
+            Report::Invoke { output, .. } => out.write_all(output),
+            Report::Ping { target: "fsutil", elapsed } => writeln!(out, "{:?}", elapsed),
+            Report::Ping { elapsed, .. } => write!(out, "ping: {:?} elapsed", elapsed),
+            Report::Fs { result, .. } => match result {
+                FsResponse::Pong | FsResponse::Done(None) => Ok(()),
+                FsResponse::Done(Some(value)) => writeln!(out, "{}", value),
+                FsResponse::Listing(entries) => {
+                    for entry in entries {
+                        writeln!(out, "{}", entry.name)?;
+                    }
+                    Ok(())
+                }
+                FsResponse::Contents(contents) => out.write_all(contents),
+                FsResponse::Stat(stat) => text_fields(out, stat_fields(stat)),
+                FsResponse::Received(bytes) => writeln!(out, "{}", bytes),
+            },
+            Report::Ls { entries, long, .. } => listing(out, entries, false, *long),
+            Report::Facets { facets, .. } => {
+                for facet in facets {
+                    let hidden = if facet.visible == Some(false) { " (hidden)" } else { "" };
+                    writeln!(out, "<{}>{}", facet.label, hidden)?;
+                }
+                Ok(())
+            }
+            Report::Stat { path, stat } => {
+                text_fields(out, [("path", path.to_string())].into_iter().chain(stat_fields(stat)).collect())
+            }
+            Report::Transfer { summary, dry_run, .. } => {
+                writeln!(out, "{}{}", if *dry_run { "dry run: " } else { "" }, summary_string(summary))
+            }
+            Report::Changes { created, removed, .. } => {
+                for path in created {
+                    writeln!(out, "+ {}", path)?;
+                }
+                for path in removed {
+                    writeln!(out, "- {}", path)?;
+                }
+                Ok(())
+            }
+            Report::Config { value, .. } => match value {
+                Some(value) => writeln!(out, "{}", value),
+                None => Ok(()),
+            },
+            Report::Profiles { active, profiles } => {
+                for (name, profile) in profiles {
+                    writeln!(out, "{} {}", if name == active { '*' } else { ' ' }, name)?;
+                    for key in ProfileKey::ALL {
+                        if let Some(value) = profile.get(key) {
+                            writeln!(out, "    {} = {}", key.name(), value)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Report::Credentials { credentials, .. } => {
+                let mut server = None;
+                for credential in credentials {
+                    if server != Some(&credential.server) {
+                        match credential.server.as_str() {
+                            ANY_SERVER => writeln!(out, "any server (legacy)")?,
+                            server => writeln!(out, "{}", server)?,
+                        }
+                        server = Some(&credential.server);
+                    }
+                    let mut notes = Vec::new();
+                    if let Some(parent) = &credential.credential.delegated_from {
+                        notes.push(format!("delegated from {}", parent));
+                    }
+                    if credential.credential.store() == Store::Keyring {
+                        notes.push("in keyring".into());
+                    }
+                    if let Some(age) = credential.credential.age() {
+                        notes.push(format!("saved {} ago", age_string(age)));
+                    }
+                    let marker = if credential.default { '*' } else { ' ' };
+                    match notes.is_empty() {
+                        true => writeln!(out, "  {} {}", marker, credential.user)?,
+                        false => writeln!(out, "  {} {} ({})", marker, credential.user, notes.join(", "))?,
+                    }
+                }
+                Ok(())
+            }
+            Report::Prune { removed } => {
+                for credential in removed {
+                    writeln!(out, "{} {}", credential.server, credential.user)?;
+                }
+                Ok(())
+            }
+            Report::DelegationTree { delegations, .. } => {
+                let is_root = |d: &&Delegation| d.parent.is_none_or(|p| !delegations.iter().any(|o| o.id == p));
+                let mut roots: Vec<&Delegation> = delegations.iter().filter(is_root).collect();
+                roots.sort_by(|a, b| (&a.server, &a.from).cmp(&(&b.server, &b.from)));
+                let mut group = None;
+                for (i, root) in roots.iter().enumerate() {
+                    if group != Some((&root.server, &root.from)) {
+                        writeln!(out, "{} @ {}", root.from, root.server)?;
+                        group = Some((&root.server, &root.from));
+                    }
+                    let last = roots.get(i + 1).is_none_or(|next| (&next.server, &next.from) != (&root.server, &root.from));
+                    delegation_tree(out, delegations, root, "", last)?;
+                }
+                Ok(())
+            }
+            Report::Inspect { token } => {
+                for (field, value) in token_fields(token) {
+                    writeln!(out, "{:<10} {}", format!("{}:", field), value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn table(&self, out: &mut dyn WriteColor) -> io::Result<()> {
+        match self {
+            Report::Login { server, user } => {
+                table(out, &["SERVER", "USER"], [vec![server.clone(), user.clone()]])
+            }
+            Report::Whoami { .. } => table(
+                out,
+                &["FIELD", "VALUE"],
+                self.whoami_fields().into_iter().map(|(field, value)| vec![field.to_string(), value]),
+            ),
+            Report::Delegate { id, privilege, token, saved, expires_at } => table(
+                out,
+                &["ID", "PRIVILEGE", "SAVED", "EXPIRES", "TOKEN"],
+                [vec![id.to_string(), privilege.clone(), saved.to_string(), expiry(*expires_at), token.clone()]],
+            ),
+            Report::Invoke { output, .. } => out.write_all(output),
+            Report::Ping { target, elapsed } => table(
+                out,
+                &["TARGET", "ELAPSED"],
+                [vec![target.to_string(), format!("{:.3} ms", elapsed.as_secs_f64() * 1000.0)]],
+            ),
+            Report::Fs { op, result } => match result {
+                FsResponse::Pong | FsResponse::Done(None) => {
+                    table(out, &["OP", "RESULT"], [vec![op.to_string(), "ok".into()]])
+                }
+                FsResponse::Done(Some(value)) => fields(out, value),
+                FsResponse::Listing(entries) => table(
+                    out,
+                    &["NAME", "KIND", "LABEL"],
+                    entries.iter().map(|e| {
+                        vec![
+                            e.name.clone(),
+                            e.kind.map(|k| k.to_string()).unwrap_or_else(|| "-".into()),
+                            e.label.as_ref().map(ToString::to_string).unwrap_or_else(|| "-".into()),
+                        ]
+                    }),
+                ),
+                FsResponse::Contents(contents) => out.write_all(contents),
+                FsResponse::Stat(stat) => table(
+                    out,
+                    &["FIELD", "VALUE"],
+                    stat_fields(stat).into_iter().map(|(field, value)| vec![field.to_string(), value]),
+                ),
+                FsResponse::Received(bytes) => {
+                    table(out, &["OP", "RECEIVED"], [vec![op.to_string(), size_string(*bytes)]])
+                }
+            },
+            Report::Ls { entries, .. } => listing(out, entries, true, true),
+            Report::Facets { facets, .. } => table(
+                out,
+                &["FACET", "PATH", "VISIBLE"],
+                facets.iter().map(|facet| {
+                    let visible = match facet.visible {
+                        Some(true) => "yes",
+                        Some(false) => "no",
+                        None => "-",
+                    };
+                    vec![format!("<{}>", facet.label), facet.path.to_string(), visible.to_string()]
+                }),
+            ),
+            Report::Stat { path, stat } => table(
+                out,
+                &["FIELD", "VALUE"],
+                [("path", path.to_string())]
+                    .into_iter()
+                    .chain(stat_fields(stat))
+                    .map(|(field, value)| vec![field.to_string(), value]),
+            ),
+            Report::Transfer { steps, .. } => table(
+                out,
+                &["ACTION", "PATH", "LABEL", "BYTES", "NOTE"],
+                steps.iter().map(|s| {
+                    vec![
+                        s.action.name().to_string(),
+                        s.path.to_string(),
+                        s.label.as_ref().map(ToString::to_string).unwrap_or_else(|| "-".into()),
+                        s.bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".into()),
+                        s.note.clone().unwrap_or_else(|| "-".into()),
+                    ]
+                }),
+            ),
+            Report::Changes { created, removed, .. } => table(
+                out,
+                &["CHANGE", "PATH"],
+                created
+                    .iter()
+                    .map(|p| vec!["created".to_string(), p.to_string()])
+                    .chain(removed.iter().map(|p| vec!["removed".to_string(), p.to_string()])),
+            ),
+            Report::Build { output, bytes } => table(
+                out,
+                &["OUTPUT", "BYTES"],
+                [vec![output.display().to_string(), bytes.to_string()]],
+            ),
+            Report::Config { profile, key, value } => table(
+                out,
+                &["PROFILE", "KEY", "VALUE"],
+                [vec![profile.clone(), key.name().into(), value.clone().unwrap_or_else(|| "-".into())]],
+            ),
+            Report::Profiles { active, profiles } => {
+                let mut headers = vec!["", "PROFILE"];
+                headers.extend(ProfileKey::ALL.iter().map(|k| k.header()));
+                table(
+                    out,
+                    &headers,
+                    profiles.iter().map(|(name, profile)| {
+                        let mut row = vec![if name == active { "*" } else { "" }.to_string(), name.clone()];
+                        row.extend(ProfileKey::ALL.iter().map(|k| profile.get(*k).unwrap_or_else(|| "-".into())));
+                        row
+                    }),
+                )
+            }
+            Report::UseProfile { profile } => table(out, &["PROFILE"], [vec![profile.clone()]]),
+            Report::Streamed => Ok(()),
+            Report::Credentials { credentials, .. } | Report::Prune { removed: credentials } => table(
+                out,
+                &["", "SERVER", "USER", "DELEGATED-FROM", "STORE", "AGE"],
+                credentials.iter().map(|c| {
+                    vec![
+                        if c.default { "*" } else { "" }.to_string(),
+                        c.server.clone(),
+                        c.user.clone(),
+                        c.credential.delegated_from.clone().unwrap_or_else(|| "-".into()),
+                        match c.credential.store() {
+                            Store::File => "file".into(),
+                            Store::Keyring => "keyring".into(),
+                        },
+                        c.credential.age().map(age_string).unwrap_or_else(|| "-".into()),
+                    ]
+                }),
+            ),
+            Report::AuthUse { server, user } => {
+                table(out, &["SERVER", "USER"], [vec![server.clone(), user.clone()]])
+            }
+            Report::Logout { server, users } => {
+                table(out, &["SERVER", "USER"], users.iter().map(|u| vec![server.clone(), u.clone()]))
+            }
+            Report::DelegationTree { delegations, .. } => table(
+                out,
+                &["ID", "PARENT", "SERVER", "FROM", "PRIVILEGE", "SAVED-AS", "EXPIRES"],
+                delegations.iter().map(|d| {
+                    vec![
+                        d.id.to_string(),
+                        d.parent.map(|p| p.to_string()).unwrap_or_else(|| "-".into()),
+                        d.server.clone(),
+                        d.from.clone(),
+                        d.privilege.clone(),
+                        d.saved_as.clone().unwrap_or_else(|| "-".into()),
+                        expiry(d.expires),
+                    ]
+                }),
+            ),
+            Report::Inspect { token } => table(
+                out,
+                &["FIELD", "VALUE"],
+                token_fields(token).into_iter().map(|(field, value)| vec![field.to_string(), value]),
+            ),
+        }
+    }
+}
+
+/// The rows `fstn auth inspect` shows for `token`.
+fn token_fields(token: &TokenInfo) -> Vec<(&'static str, String)> {
+    let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".into());
+    let issued = token
+        .issued_at
+        .map(|iat| format!("{} ago", age_string(now().saturating_sub(iat))))
+        .unwrap_or_else(|| "-".into());
+    vec![
+        ("principal", or_dash(&token.principal)),
+        ("privilege", or_dash(&token.privilege)),
+        ("clearance", or_dash(&token.clearance)),
+        ("bootstrap", token.bootstrap.map(|b| b.to_string()).unwrap_or_else(|| "-".into())),
+        ("issued", issued),
+        ("expires", expiry(token.expires_at)),
+        (
+            "source",
+            match token.source {
+                TokenSource::Local => "token".into(),
+                TokenSource::Server => "gateway".into(),
+            },
+        ),
+    ]
+}
+
+/// The rows `fstn fs stat` shows for `stat`: its kind and label, and
+/// whichever of the rest the entry has.
+fn stat_fields(stat: &Stat) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("kind", stat.kind.map_or_else(|| "-".into(), |k| k.to_string())),
+        ("label", stat.label.as_ref().map_or_else(|| "-".into(), ToString::to_string)),
+    ];
+    let size = stat.size.map(|size| format!("{} ({})", size, size_string(size)));
+    let paths = [
+        ("app_image", &stat.app_image),
+        ("kernel", &stat.kernel),
+        ("runtime", &stat.runtime),
+        ("gate", &stat.gate),
+    ];
+    fields.extend(
+        [
+            ("size", size),
+            ("sha256", stat.sha256.clone()),
+            ("privilege", stat.privilege.as_ref().map(ToString::to_string)),
+            ("clearance", stat.clearance.as_ref().map(ToString::to_string)),
+            ("memory", stat.memory.map(|m| m.to_string())),
+        ]
+        .into_iter()
+        .chain(paths.into_iter().map(|(field, path)| (field, path.as_ref().map(ToString::to_string))))
+        .filter_map(|(field, value)| Some((field, value?))),
+    );
+    fields
+}
+
+/// Writes `fields` as aligned `field: value` lines.
+fn text_fields(out: &mut dyn Write, fields: Vec<(&'static str, String)>) -> io::Result<()> {
+    for (field, value) in fields {
+        writeln!(out, "{:<10} {}", format!("{}:", field), value)?;
+    }
+    Ok(())
+}
+
+/// The color `fstn fs ls` shows entries of `kind` in.
+fn kind_color(kind: Option<EntryKind>) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    match kind {
+        Some(EntryKind::Directory) => spec.set_fg(Some(Color::Blue)).set_bold(true),
+        Some(EntryKind::Faceted) => spec.set_fg(Some(Color::Cyan)).set_bold(true),
+        Some(EntryKind::Gate) => spec.set_fg(Some(Color::Green)).set_bold(true),
+        Some(EntryKind::Blob) => spec.set_fg(Some(Color::Magenta)),
+        Some(EntryKind::Service) => spec.set_fg(Some(Color::Yellow)),
+        Some(EntryKind::File) | None => &mut spec,
+    };
+    spec
+}
+
+/// Writes a listing with names indented by depth and colored by kind,
+/// optionally followed by kind and label columns and preceded by headers.
+fn listing(out: &mut dyn WriteColor, entries: &[ListedEntry], headers: bool, long: bool) -> io::Result<()> {
+    let rows: Vec<[String; 3]> = entries
+        .iter()
+        .map(|e| {
+            [
+                format!("{}{}", "  ".repeat(e.depth), e.entry.name),
+                e.entry.kind.map(|k| k.to_string()).unwrap_or_else(|| "-".into()),
+                e.entry.label.as_ref().map(ToString::to_string).unwrap_or_else(|| "-".into()),
+            ]
+        })
+        .collect();
+    if !long {
+        for (entry, [name, ..]) in entries.iter().zip(&rows) {
+            out.set_color(&kind_color(entry.entry.kind))?;
+            write!(out, "{}", name)?;
+            out.reset()?;
+            writeln!(out)?;
+        }
+        return Ok(());
+    }
+    let mut widths = if headers { [4, 4, 5] } else { [0; 3] };
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    if headers {
+        writeln!(out, "{:<w0$}  {:<w1$}  LABEL", "NAME", "KIND", w0 = widths[0], w1 = widths[1])?;
+    }
+    for (entry, [name, kind, label]) in entries.iter().zip(&rows) {
+        out.set_color(&kind_color(entry.entry.kind))?;
+        write!(out, "{}", name)?;
+        out.reset()?;
+        let padding = widths[0] - name.chars().count();
+        writeln!(out, "{:padding$}  {:<w1$}  {}", "", kind, label, padding = padding, w1 = widths[1])?;
+    }
+    Ok(())
+}
+
+/// Writes `node` and the delegations derived from it as a branch of a tree
+/// drawn below `prefix`.
+fn delegation_tree(
+    out: &mut dyn Write,
+    all: &[Delegation],
+    node: &Delegation,
+    prefix: &str,
+    last: bool,
+) -> io::Result<()> {
+    let mut notes = Vec::new();
+    if let Some(user) = &node.saved_as {
+        notes.push(format!("saved as {}", user));
+    }
+    if node.bootstrap {
+        notes.push("bootstrap".into());
+    }
+    if node.expires.is_some() {
+        notes.push(format!("expires {}", expiry(node.expires)));
+    }
+    let notes = if notes.is_empty() { String::new() } else { format!(" ({})", notes.join(", ")) };
+    let branch = if last { "└── " } else { "├── " };
+    writeln!(out, "{}{}#{} {}{}", prefix, branch, node.id, node.privilege, notes)?;
+    let children: Vec<&Delegation> = all.iter().filter(|d| d.parent == Some(node.id)).collect();
+    let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+    for (i, child) in children.iter().enumerate() {
+        delegation_tree(out, all, child, &prefix, i + 1 == children.len())?;
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// When `expires_at` is, relative to now.
+fn expiry(expires_at: Option<u64>) -> String {
+    match expires_at {
+        None => "never".to_string(),
+        Some(at) if at > now() => format!("in {}", age_string(at - now())),
+        Some(at) => format!("{} ago (expired)", age_string(now() - at)),
+    }
+}
+
+/// A byte count in binary units, such as `1.5 MiB`.
+pub fn size_string(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 || unit == "GiB" {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                unit => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    unreachable!()
+}
+
+/// A one-line account of a tree operation.
+fn summary_string(summary: &Summary) -> String {
+    let count = |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let mut line = format!(
+        "{}, {}, {} ({})",
+        count(summary.directories, "directory", "directories"),
+        count(summary.files, "file", "files"),
+        count(summary.blobs, "blob", "blobs"),
+        size_string(summary.bytes)
+    );
+    if summary.unchanged > 0 {
+        line.push_str(&format!(", {} unchanged", summary.unchanged));
+    }
+    if summary.removed > 0 {
+        line.push_str(&format!(", {} removed", summary.removed));
+    }
+    if summary.skipped > 0 {
+        line.push_str(&format!(", {} skipped", summary.skipped));
+    }
+    line
+}
+
+/// A number of seconds in the largest whole unit, e.g. `3d` or `5m`.
+pub fn age_string(seconds: u64) -> String {
+    match seconds {
+        s if s >= 24 * 60 * 60 => format!("{}d", s / (24 * 60 * 60)),
+        s if s >= 60 * 60 => format!("{}h", s / (60 * 60)),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Prints a warning on stderr, styled like the CLI's progress lines.
+pub fn warn(message: &dyn std::fmt::Display) {
+    let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+    let _ = stderr.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Yellow)));
+    let _ = write!(stderr, "{:>12} ", "Warning");
+    let _ = stderr.reset();
+    let _ = writeln!(stderr, "{}", message);
+}
+
+/// A progress bar for one transfer, drawn on stderr while it is a terminal
+/// and erased when dropped.
+#[derive(Debug)]
+pub struct Progress {
+    name: String,
+    total: u64,
+    done: u64,
+    drawn: Option<Instant>,
+    enabled: bool,
+}
+
+impl Progress {
+    /// How often the bar is redrawn at most.
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(name: impl Into<String>, total: u64) -> Progress {
+        Progress { name: name.into(), total, done: 0, drawn: None, enabled: io::stderr().is_terminal() }
+    }
+
+    /// Records that `bytes` more bytes were transferred.
+    pub fn advance(&mut self, bytes: u64) {
+        self.set(self.done + bytes);
+    }
+
+    /// Records that `done` bytes in all were transferred, as when resuming.
+    pub fn set(&mut self, done: u64) {
+        self.done = done.min(self.total);
+        if !self.enabled || self.drawn.is_some_and(|at| at.elapsed() < Self::INTERVAL && self.done < self.total) {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+        let fraction = if self.total == 0 { 1.0 } else { self.done as f64 / self.total as f64 };
+        let filled = (fraction * 30.0) as usize;
+        let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+        let _ = stderr.set_color(ColorSpec::new().set_bold(true).set_fg(Some(Color::Cyan)));
+        let _ = write!(stderr, "\r{:>12} ", "Uploading");
+        let _ = stderr.reset();
+        let _ = write!(
+            stderr,
+            "[{:<30}] {:>3}% {} / {} {}\x1b[K",
+            "=".repeat(filled),
+            (fraction * 100.0) as u64,
+            size_string(self.done),
+            size_string(self.total),
+            self.name
+        );
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.drawn.is_some() {
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+/// Reads from `inner`, advancing a [`Progress`] as it goes.
+#[derive(Debug)]
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: io::Read> ProgressReader<R> {
+    pub fn new(inner: R, progress: Progress) -> ProgressReader<R> {
+        ProgressReader { inner, progress }
+    }
+}
+
+impl<R: io::Read> io::Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.advance(n as u64);
+        Ok(n)
+    }
+}
+
+/// Writes the JSON document of a [`Report::Fs`] whose result is the
+/// contents `contents` yields, base64-encoding them as they are read rather
+/// than holding them in memory.
+pub fn render_contents(op: &str, contents: &mut dyn io::Read, out: &mut dyn Write) -> io::Result<()> {
+    write!(out, "{{\n  \"command\": \"fs\",\n  \"op\": {},\n", serde_json::to_string(op)?)?;
+    write!(out, "  \"result\": {{\n    \"kind\": \"contents\",\n    \"value\": \"")?;
+    let mut encoder = base64::write::EncoderWriter::new(&mut *out, base64::STANDARD);
+    io::copy(contents, &mut encoder)?;
+    encoder.finish()?;
+    drop(encoder);
+    writeln!(out, "\"\n  }}\n}}")
+}
+
+/// Renders `error` as the document a failed command produces.
+pub fn render_error(error: &FstnError, mode: OutputMode, out: &mut dyn Write) -> io::Result<()> {
+    match mode {
+        OutputMode::Json => {
+            let document = serde_json::json!({
+                "error": {
+                    "kind": error.kind(),
+                    "message": error.to_string(),
+                    "exit_code": error.exit_code(),
+                }
+            });
+            serde_json::to_writer_pretty(&mut *out, &document)?;
+            writeln!(out)
+        }
+        OutputMode::Text | OutputMode::Table => writeln!(out, "{}", error),
+    }
+}
+
+/// Writes `rows` as left-aligned columns under `headers`.
+pub fn table<R>(out: &mut dyn Write, headers: &[&str], rows: R) -> io::Result<()>
+where
+    R: IntoIterator<Item = Vec<String>>,
+{
+    let rows: Vec<Vec<String>> = rows.into_iter().collect();
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers = headers.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&headers).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Writes a JSON object as a two-column key/value table, or any other value
+/// on its own line.
+fn fields(out: &mut dyn Write, value: &serde_json::Value) -> io::Result<()> {
+    match value {
+        serde_json::Value::Object(map) => table(
+            out,
+            &["FIELD", "VALUE"],
+            map.iter().map(|(k, v)| {
+                let v = match v {
+                    serde_json::Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                vec![k.clone(), v]
+            }),
+        ),
+        serde_json::Value::String(s) => writeln!(out, "{}", s),
+        value => writeln!(out, "{}", value),
+    }
+}
@@ -0,0 +1,422 @@
+//! Paths in the Faasten file system.
+//!
+//! A path is written as components separated by `:`, for example
+//! `home:<alice,alice>:photos:cat.jpg`. Besides plain names a component may
+//! be:
+//!
+//! * `~`, the caller's home directory, only as the first component;
+//! * `<secrecy,integrity>`, the facet of a faceted directory with that label;
+//! * `@name`, a name that must refer to a gate;
+//! * `#name`, a name that must refer to a blob.
+//!
+//! A backslash escapes the character after it, so `a\:b` names the single
+//! entry `a:b`. Escapes are part of the syntax only: gates are sent the
+//! plain name, which is why new entries cannot be named `~` or have names
+//! starting with `<`, `@` or `#`. Paths are normalized as they are parsed:
+//! empty and `.` components are dropped and `..` removes the component
+//! before it.
+//!
+//! A path may also be given as a URL naming the gateway it lives on,
+//! `fstn://server/home/<alice,alice>/photos`, with components separated by
+//! `/` and percent-encoded as needed.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::label::Label;
+
+/// One component of an [`FsPath`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathComponent {
+    /// `~`, expanded by the gateway to the caller's home directory.
+    Home,
+    /// An entry of a directory.
+    Name(String),
+    /// A facet of a faceted directory.
+    Facet(Label),
+    /// An entry that must be a gate.
+    Gate(String),
+    /// An entry that must be a blob.
+    Blob(String),
+}
+
+/// A malformed path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathError(pub String);
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// A normalized path, optionally qualified with the gateway it lives on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FsPath {
+    server: Option<String>,
+    components: Vec<PathComponent>,
+}
+
+impl FsPath {
+    /// The root directory.
+    pub fn root() -> FsPath {
+        FsPath::default()
+    }
+
+    /// `~`, the caller's home directory.
+    pub fn home() -> FsPath {
+        FsPath { server: None, components: vec![PathComponent::Home] }
+    }
+
+    /// `home:<user,user>`, the home directory of `user`.
+    pub fn home_of(user: &str) -> Result<FsPath, PathError> {
+        let label = format!("{},{}", user, user)
+            .parse()
+            .map_err(|e| PathError(format!("`{}` is not a valid user: {}", user, e)))?;
+        Ok(FsPath::root()
+            .join(PathComponent::Name("home".into()))
+            .join(PathComponent::Facet(label)))
+    }
+
+    /// The gateway named in a `fstn://` path, if any.
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_deref()
+    }
+
+    pub fn components(&self) -> &[PathComponent] {
+        &self.components
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// This path with `component` appended.
+    pub fn join(mut self, component: PathComponent) -> FsPath {
+        self.components.push(component);
+        self
+    }
+
+    /// This path with the entry `name` appended.
+    pub fn child(&self, name: &str) -> FsPath {
+        self.clone().join(PathComponent::Name(name.to_string()))
+    }
+
+    /// The path without its last component, or `None` for the root.
+    pub fn parent(&self) -> Option<FsPath> {
+        let (_, parent) = self.components.split_last()?;
+        Some(FsPath { server: self.server.clone(), components: parent.to_vec() })
+    }
+
+    pub fn last(&self) -> Option<&PathComponent> {
+        self.components.last()
+    }
+
+    /// This path as a `fstn://` URL on `server`.
+    pub fn to_url(&self, server: &str) -> String {
+        let mut url = format!("fstn://{}", server);
+        if self.components.is_empty() {
+            url.push('/');
+        }
+        for component in &self.components {
+            url.push('/');
+            url.push_str(&percent_encode(&component.to_string()));
+        }
+        url
+    }
+
+    fn parse_url(rest: &str) -> Result<FsPath, PathError> {
+        let (server, path) = rest.split_once('/').unwrap_or((rest, ""));
+        if server.is_empty() {
+            return Err(PathError("`fstn://` paths must name a server".into()));
+        }
+        let mut segments = Vec::new();
+        for raw in split_outside_facets(path, '/') {
+            let decoded = percent_decode(raw)?;
+            segments.push(unescape(&decoded, None)?.pop().unwrap_or_default());
+        }
+        let components = normalize(segments)?;
+        Ok(FsPath { server: Some(server.to_string()), components })
+    }
+}
+
+/// Validates the name of a new entry, which is taken literally. Names that
+/// gates would read as `~` or a facet, gate or blob component are refused.
+pub fn name(s: &str) -> Result<String, PathError> {
+    match s {
+        "" => Err(PathError("names cannot be empty".into())),
+        "." | ".." | "~" => Err(PathError(format!("`{}` cannot be used as a name", s))),
+        s if s.starts_with(['<', '@', '#']) => {
+            Err(PathError(format!("`{}`: names cannot start with `{}`", s, &s[..1])))
+        }
+        s => Ok(s.to_string()),
+    }
+}
+
+/// A character and whether it was escaped.
+type Char = (char, bool);
+
+/// Splits `s` on unescaped `separator`s (or not at all), resolving escapes.
+fn unescape(s: &str, separator: Option<char>) -> Result<Vec<Vec<Char>>, PathError> {
+    let mut segments = vec![Vec::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let segment = segments.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some(c) => segment.push((c, true)),
+                None => return Err(PathError(format!("`{}` ends with a lone backslash", s))),
+            },
+            c if Some(c) == separator => segments.push(Vec::new()),
+            c => segment.push((c, false)),
+        }
+    }
+    Ok(segments)
+}
+
+/// Splits `s` on `separator`, except inside a `<...>` facet or after a
+/// backslash. Used for URLs, where labels may contain `/`.
+fn split_outside_facets(s: &str, separator: char) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut in_facet = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '<' if i == start => in_facet = true,
+            '>' if in_facet => in_facet = false,
+            c if c == separator && !in_facet => {
+                segments.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    segments.push(&s[start..]);
+    segments
+}
+
+enum Segment {
+    Current,
+    Parent,
+    Component(PathComponent),
+}
+
+fn segment(chars: &[Char]) -> Result<Option<Segment>, PathError> {
+    let text: String = chars.iter().map(|(c, _)| c).collect();
+    let escaped = chars.iter().any(|(_, e)| *e);
+    let nonempty = |kind: &str, rest: &[Char]| {
+        if rest.is_empty() {
+            Err(PathError(format!("empty {} name", kind)))
+        } else {
+            Ok(rest.iter().map(|(c, _)| c).collect::<String>())
+        }
+    };
+    let component = match chars {
+        [] => return Ok(None),
+        _ if !escaped && text == "." => return Ok(Some(Segment::Current)),
+        _ if !escaped && text == ".." => return Ok(Some(Segment::Parent)),
+        [('~', false)] => PathComponent::Home,
+        [('~', false), ..] => {
+            return Err(PathError(format!("`{}`: write `\\~` for a name starting with `~`", text)))
+        }
+        [('<', false), inner @ .., ('>', false)] => {
+            let label: String = inner.iter().map(|(c, _)| c).collect();
+            let label = label
+                .parse()
+                .map_err(|e| PathError(format!("invalid facet `{}`: {}", text, e)))?;
+            PathComponent::Facet(label)
+        }
+        [('<', false), ..] => return Err(PathError(format!("unterminated facet `{}`", text))),
+        [('@', false), rest @ ..] => PathComponent::Gate(nonempty("gate", rest)?),
+        [('#', false), rest @ ..] => PathComponent::Blob(nonempty("blob", rest)?),
+        _ => PathComponent::Name(text),
+    };
+    Ok(Some(Segment::Component(component)))
+}
+
+fn normalize(segments: Vec<Vec<Char>>) -> Result<Vec<PathComponent>, PathError> {
+    let mut components = Vec::new();
+    for chars in segments {
+        match segment(&chars)? {
+            None | Some(Segment::Current) => {}
+            Some(Segment::Parent) => match components.pop() {
+                Some(PathComponent::Home) => {
+                    return Err(PathError("`..` cannot leave `~`; name the home directory instead".into()))
+                }
+                Some(_) => {}
+                None => return Err(PathError("`..` goes above the root".into())),
+            },
+            Some(Segment::Component(PathComponent::Home)) if !components.is_empty() => {
+                return Err(PathError("`~` is only allowed as the first component".into()))
+            }
+            Some(Segment::Component(component)) => components.push(component),
+        }
+    }
+    Ok(components)
+}
+
+impl FromStr for FsPath {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = s.strip_prefix("fstn://") {
+            return FsPath::parse_url(rest);
+        }
+        let components = normalize(unescape(s, Some(':'))?)?;
+        Ok(FsPath { server: None, components })
+    }
+}
+
+impl fmt::Display for FsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(server) = &self.server {
+            return f.write_str(&self.to_url(server));
+        }
+        for (i, component) in self.components.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{}", component)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `name` so that it parses back as a plain name.
+fn escape_name(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    for (i, c) in name.chars().enumerate() {
+        let special = match c {
+            '\\' | ':' => true,
+            '~' | '<' | '@' | '#' => i == 0,
+            '.' => i == 0 && (name == "." || name == ".."),
+            _ => false,
+        };
+        if special {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for PathComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathComponent::Home => f.write_str("~"),
+            PathComponent::Name(name) => escape_name(f, name),
+            PathComponent::Facet(label) => write!(f, "<{}>", label),
+            PathComponent::Gate(name) => {
+                f.write_str("@")?;
+                escape_name(f, name)
+            }
+            PathComponent::Blob(name) => {
+                f.write_str("#")?;
+                escape_name(f, name)
+            }
+        }
+    }
+}
+
+impl FromStr for PathComponent {
+    type Err = PathError;
+
+    /// Parses a single component; `:` needs no escaping here.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars = unescape(s, None)?.pop().unwrap_or_default();
+        match segment(&chars)? {
+            Some(Segment::Component(component)) => Ok(component),
+            _ => Err(PathError(format!("`{}` is not a path component", s))),
+        }
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_graphic() && !matches!(b, b'%' | b'/' | b'?' | b'#') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+fn percent_decode(s: &str) -> Result<String, PathError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = s
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| PathError(format!("bad percent-encoding in `{}`", s)))?;
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| PathError(format!("`{}` is not UTF-8", s)))
+}
+
+impl PathComponent {
+    /// The component as sent to a gate: like `Display`, but without
+    /// backslash escapes, which belong to the command-line syntax only.
+    fn to_wire(&self) -> String {
+        match self {
+            PathComponent::Home => "~".into(),
+            PathComponent::Name(name) => name.clone(),
+            PathComponent::Facet(label) => format!("<{}>", label),
+            PathComponent::Gate(name) => format!("@{}", name),
+            PathComponent::Blob(name) => format!("#{}", name),
+        }
+    }
+
+    fn from_wire(s: &str) -> Result<PathComponent, PathError> {
+        let nonempty = |kind: &str, rest: &str| match rest {
+            "" => Err(PathError(format!("empty {} name", kind))),
+            rest => Ok(rest.to_string()),
+        };
+        Ok(match s {
+            "" => return Err(PathError("empty path component".into())),
+            "~" => PathComponent::Home,
+            _ if s.starts_with('<') => match s[1..].strip_suffix('>') {
+                Some(label) => PathComponent::Facet(
+                    label.parse().map_err(|e| PathError(format!("invalid facet `{}`: {}", s, e)))?,
+                ),
+                None => return Err(PathError(format!("unterminated facet `{}`", s))),
+            },
+            _ if s.starts_with('@') => PathComponent::Gate(nonempty("gate", &s[1..])?),
+            _ if s.starts_with('#') => PathComponent::Blob(nonempty("blob", &s[1..])?),
+            _ => PathComponent::Name(s.to_string()),
+        })
+    }
+}
+
+/// On the wire a path is a list of components, written as in the path
+/// syntax but without escapes: `a:b` is sent as `"a:b"`, and a name is
+/// taken literally unless it is `~` or starts with `<`, `@` or `#`.
+impl serde::Serialize for FsPath {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(self.components.iter().map(PathComponent::to_wire))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for FsPath {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let components = <Vec<String>>::deserialize(d)?
+            .iter()
+            .map(|c| PathComponent::from_wire(c))
+            .collect::<Result<_, PathError>>()
+            .map_err(serde::de::Error::custom)?;
+        Ok(FsPath { server: None, components })
+    }
+}
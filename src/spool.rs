@@ -0,0 +1,23 @@
+//! Anonymous temporary files for contents too large to keep in memory on
+//! their way from one request to another.
+
+use std::fs::{File, OpenOptions};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::Result;
+
+/// A new, empty temporary file that only this process can open. It is
+/// created in [`std::env::temp_dir`] readable by its owner alone, and
+/// unlinked at once where the platform allows, so nothing is left behind.
+pub fn spool() -> Result<File> {
+    static SPOOLED: AtomicUsize = AtomicUsize::new(0);
+    let n = SPOOLED.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!(".fstn-{}-{}.spool", std::process::id(), n));
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(&path)?;
+    let _ = std::fs::remove_file(&path);
+    Ok(file)
+}
@@ -0,0 +1,105 @@
+//! What a token grants, as far as the CLI can tell.
+//!
+//! Gateway tokens are JSON Web Tokens whose claims name the principal, the
+//! privilege it holds, its clearance, whether it may bootstrap, and when it
+//! expires. [`TokenInfo::decode`] reads those claims without checking the
+//! signature, which only the gateway can do; tokens that are not JWTs are
+//! described by the gateway's `/me` reply instead, via [`TokenInfo::from_me`].
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Where a [`TokenInfo`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenSource {
+    /// Decoded from the token itself.
+    Local,
+    /// Reported by the gateway's `/me`.
+    Server,
+}
+
+/// The principal, privilege and lifetime of a token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenInfo {
+    pub source: TokenSource,
+    pub principal: Option<String>,
+    pub privilege: Option<String>,
+    pub clearance: Option<String>,
+    pub bootstrap: Option<bool>,
+    /// When the token was issued, in seconds since the Unix epoch.
+    pub issued_at: Option<u64>,
+    /// When the token expires, in seconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+}
+
+/// The claims fstn understands, under the names the gateway uses.
+#[derive(Debug, Default, Deserialize)]
+struct Claims {
+    #[serde(default, alias = "login")]
+    sub: Option<serde_json::Value>,
+    #[serde(default)]
+    privilege: Option<serde_json::Value>,
+    #[serde(default)]
+    clearance: Option<serde_json::Value>,
+    #[serde(default)]
+    bootstrap: Option<bool>,
+    #[serde(default)]
+    iat: Option<u64>,
+    #[serde(default)]
+    exp: Option<u64>,
+}
+
+/// A claim as text; structured values are shown as JSON.
+fn text(value: Option<serde_json::Value>) -> Option<String> {
+    match value? {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s),
+        other => Some(other.to_string()),
+    }
+}
+
+impl TokenInfo {
+    fn from_claims(source: TokenSource, claims: Claims) -> TokenInfo {
+        TokenInfo {
+            source,
+            principal: text(claims.sub),
+            privilege: text(claims.privilege),
+            clearance: text(claims.clearance),
+            bootstrap: claims.bootstrap,
+            issued_at: claims.iat,
+            expires_at: claims.exp,
+        }
+    }
+
+    /// Reads the claims of a JWT, or `None` if `token` is not one.
+    pub fn decode(token: &str) -> Option<TokenInfo> {
+        let mut parts = token.trim().split('.');
+        let (Some(_header), Some(payload), Some(_signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        let json = base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()?;
+        let claims = serde_json::from_slice(&json).ok()?;
+        Some(TokenInfo::from_claims(TokenSource::Local, claims))
+    }
+
+    /// Reads the identity the gateway reports for a token.
+    pub fn from_me(me: &serde_json::Value) -> TokenInfo {
+        let claims = serde_json::from_value(me.clone()).unwrap_or_default();
+        TokenInfo::from_claims(TokenSource::Server, claims)
+    }
+
+    /// Seconds until the token expires, negative once it has, if it expires
+    /// at all.
+    pub fn expires_in(&self) -> Option<i64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.expires_at.map(|exp| exp as i64 - now as i64)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_in().is_some_and(|left| left <= 0)
+    }
+}
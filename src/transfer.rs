@@ -0,0 +1,960 @@
+//! Copying trees of files between the local disk and the Faasten file system.
+//!
+//! [`put`] uploads a local file or directory tree into a directory of the
+//! Faasten file system. Entries get the label [`PutOptions::label`] unless a
+//! `.fstnlabels` file at the root of the uploaded directory says otherwise.
+//! It maps paths relative to that root, separated by `/`, to labels; the
+//! longest path that is a prefix of an entry's path decides its label:
+//!
+//! ```toml
+//! "." = "alice,alice"
+//! "public" = "T,alice"
+//! "public/drafts" = "alice,alice"
+//! ```
+//!
+//! [`get`] downloads an entry or a whole subtree, and records the kind and
+//! label of every entry in a [`Manifest`] saved as `.fstnmanifest` at the
+//! root of the copy:
+//!
+//! ```toml
+//! [entries."."]
+//! kind = "directory"
+//! label = "alice,T"
+//!
+//! [entries."bin/resize"]
+//! kind = "gate"
+//! label = "alice,alice"
+//! skipped = true
+//! ```
+//!
+//! Where a tree has no labels file, [`put`] takes its labels from the
+//! manifest, so that a downloaded tree uploads with the labels it had.
+//!
+//! [`sync`] brings a remote directory up to date with a local one, or the
+//! other way around, transferring only files whose contents differ. It
+//! remembers the SHA-256 of every remote file as of the last sync in a
+//! [`SyncIndex`] saved as `.fstnsync` in the local directory. Uploads trust
+//! that index; downloads, which are after remote changes, do not. Both
+//! otherwise use the SHA-256 the gate reports, and only read a remote file
+//! to compare it on gates that report none. Removing local files on
+//! download leaves alone whatever is below a remote directory that could
+//! not be listed.
+//!
+//! [`fetch`] downloads a single blob or file to a local file, optionally
+//! checking it against the SHA-256 the gate reports.
+//!
+//! None of these files is itself uploaded. Each operation reports its
+//! [`Step`]s as it takes them, so that callers can show progress.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::client::{file_hash, Fs, Hashing, ListedEntry, DEFAULT_CHUNK_SIZE};
+use crate::fsutil::{DirEntry, EntryKind};
+use crate::label::Label;
+use crate::path::{self, FsPath, PathComponent};
+use crate::{FstnError, Result};
+
+/// Files larger than this many bytes are uploaded as blobs by default.
+pub const DEFAULT_BLOB_THRESHOLD: u64 = 1 << 20;
+
+/// The name of the per-path labels file.
+pub const LABELS_FILE: &str = ".fstnlabels";
+
+/// The name of the manifest [`get`] writes.
+pub const MANIFEST_FILE: &str = ".fstnmanifest";
+
+/// The name of the index [`sync`] keeps.
+pub const INDEX_FILE: &str = ".fstnsync";
+
+/// Labels for the paths of an uploaded tree, read from [`LABELS_FILE`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelRules {
+    rules: Vec<(Vec<String>, Label)>,
+}
+
+impl LabelRules {
+    /// Reads the labels file in `dir`, or else the labels recorded in its
+    /// manifest, if it has either.
+    pub fn load(dir: &Path) -> Result<LabelRules> {
+        let file = dir.join(LABELS_FILE);
+        let text = match std::fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let manifest = Manifest::load(dir)?.unwrap_or_default();
+                let rules = manifest
+                    .entries
+                    .into_iter()
+                    .filter_map(|(path, entry)| Some((split(&path), entry.label?)))
+                    .collect();
+                return Ok(LabelRules { rules });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = |e: String| FstnError::Config(format!("{}: {}", file.display(), e));
+        let table: BTreeMap<String, String> = toml::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        let mut rules = Vec::new();
+        for (key, label) in table {
+            let prefix = split(&key);
+            if prefix.iter().any(|n| n == "..") {
+                return Err(invalid(format!("`{}` leaves the uploaded directory", key)));
+            }
+            let label = label.parse().map_err(|e| invalid(format!("{}: {}", key, e)))?;
+            rules.push((prefix, label));
+        }
+        Ok(LabelRules { rules })
+    }
+
+    /// The label for the entry at `relative`, from the longest rule that
+    /// covers it.
+    pub fn label_for(&self, relative: &[String]) -> Option<&Label> {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| relative.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, label)| label)
+    }
+}
+
+/// The names along a `/`-separated relative path.
+fn split(path: &str) -> Vec<String> {
+    path.split('/').filter(|n| !n.is_empty() && *n != ".").map(String::from).collect()
+}
+
+/// The kind and label of each entry of a downloaded tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Entries by their `/`-separated path relative to the root of the
+    /// tree, which is `.`.
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<EntryKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<Label>,
+    /// Whether the entry was left out of the copy.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+}
+
+impl Manifest {
+    /// Reads the manifest in `dir`, if it has one.
+    pub fn load(dir: &Path) -> Result<Option<Manifest>> {
+        let file = dir.join(MANIFEST_FILE);
+        match std::fs::read_to_string(&file) {
+            Ok(text) => toml::from_str(&text)
+                .map(Some)
+                .map_err(|e| FstnError::Config(format!("{}: {}", file.display(), e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        Ok(std::fs::write(dir.join(MANIFEST_FILE), toml::to_string(self)?)?)
+    }
+}
+
+/// What a [`Step`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Created a directory.
+    Mkdir,
+    /// Wrote a file, creating it first if needed.
+    Write,
+    /// Uploaded a blob, replacing any blob of the same name.
+    Mkblob,
+    /// Downloaded a file.
+    Read,
+    /// Downloaded a blob.
+    Cat,
+    /// Found a file or blob already up to date.
+    Unchanged,
+    /// Removed an entry the source of a sync does not have.
+    Remove,
+    /// Left an entry alone; the step's note says why.
+    Skip,
+}
+
+impl Action {
+    /// How progress lines describe the action.
+    pub fn verb(self) -> &'static str {
+        match self {
+            Action::Mkdir => "Created",
+            Action::Write => "Wrote",
+            Action::Mkblob => "Uploaded",
+            Action::Read | Action::Cat => "Downloaded",
+            Action::Unchanged => "Unchanged",
+            Action::Remove => "Removed",
+            Action::Skip => "Skipped",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Mkdir => "mkdir",
+            Action::Write => "write",
+            Action::Mkblob => "mkblob",
+            Action::Read => "read",
+            Action::Cat => "cat",
+            Action::Unchanged => "unchanged",
+            Action::Remove => "remove",
+            Action::Skip => "skip",
+        }
+    }
+}
+
+/// One entry a tree operation created, transferred or skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Step {
+    pub action: Action,
+    pub path: FsPath,
+    pub local: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<Label>,
+    /// How many bytes were transferred.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// The SHA-256 of what was transferred, in hex, where it was computed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Totals over the steps of a tree operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub directories: usize,
+    pub files: usize,
+    pub blobs: usize,
+    pub bytes: u64,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub skipped: usize,
+}
+
+impl Summary {
+    pub fn of(steps: &[Step]) -> Summary {
+        let mut summary = Summary::default();
+        for step in steps {
+            match step.action {
+                Action::Mkdir => summary.directories += 1,
+                Action::Write | Action::Read => summary.files += 1,
+                Action::Mkblob | Action::Cat => summary.blobs += 1,
+                Action::Unchanged => summary.unchanged += 1,
+                Action::Remove => summary.removed += 1,
+                Action::Skip => summary.skipped += 1,
+            }
+            summary.bytes += step.bytes.unwrap_or(0);
+        }
+        summary
+    }
+}
+
+/// How [`put`] uploads.
+#[derive(Debug, Clone)]
+pub struct PutOptions {
+    /// The label of entries no labels file rule covers.
+    pub label: Label,
+    /// Files larger than this many bytes become blobs.
+    pub blob_threshold: u64,
+    /// Whether directories may be uploaded.
+    pub recursive: bool,
+}
+
+/// Uploads the file or directory `local` into the directory `base`, under
+/// its own name, reporting each step to `progress` as it is taken. The name
+/// is the last component of `local` as given, so a symbolic link is
+/// uploaded under its own name; only `.` and `..` are resolved.
+///
+/// Existing entries are reused: directories are filled in, files are
+/// overwritten and blobs replaced, all keeping their kind and label. An
+/// existing entry of another kind is skipped.
+pub fn put(
+    fs: &Fs,
+    local: &Path,
+    base: &FsPath,
+    options: &PutOptions,
+    progress: &mut dyn FnMut(&Step),
+) -> Result<Vec<Step>> {
+    let metadata = std::fs::metadata(local)?;
+    let resolved;
+    let name = match local.file_name() {
+        Some(name) => Some(name),
+        None => {
+            resolved = std::fs::canonicalize(local)?;
+            resolved.file_name()
+        }
+    };
+    let name = name
+        .and_then(|n| n.to_str())
+        .map(path::name)
+        .transpose()?
+        .ok_or_else(|| FstnError::InvalidPath(format!("{}: not a file name", local.display())))?;
+    let existing = existing(fs, base)?;
+    let steps = Steps { progress, steps: Vec::new() };
+    let mut put = Put { fs, options, rules: LabelRules::default(), steps, sync: None };
+    if metadata.is_dir() {
+        if !options.recursive {
+            return Err(FstnError::InvalidPath(format!("{} is a directory; use -r to upload it", local.display())));
+        }
+        put.rules = LabelRules::load(local)?;
+        put.dir(local, base, &name, Vec::new(), &existing)?;
+    } else {
+        put.file(local, base, &name, Vec::new(), metadata.len(), &existing)?;
+    }
+    Ok(put.steps.steps)
+}
+
+/// How [`sync`] compares and copies.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// The label of new remote entries no labels file rule covers.
+    pub label: Label,
+    /// Files larger than this many bytes are uploaded as blobs.
+    pub blob_threshold: u64,
+    /// Copy from the remote directory to the local one.
+    pub download: bool,
+    /// Remove entries that only the destination has.
+    pub delete: bool,
+    /// Report what would change without changing anything.
+    pub dry_run: bool,
+    /// Read remote files to compare them even if the index knows them.
+    pub checksum: bool,
+}
+
+/// The SHA-256 of each remote file and blob, by path relative to the
+/// synced directory, as of the last sync with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncIndex {
+    pub server: String,
+    pub remote: String,
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+}
+
+impl SyncIndex {
+    /// The index in `dir` if it is one for `remote` on `server`, or else an
+    /// empty one.
+    pub fn load(dir: &Path, server: &str, remote: &FsPath) -> Result<SyncIndex> {
+        let file = dir.join(INDEX_FILE);
+        let empty = SyncIndex { server: server.to_string(), remote: remote.to_string(), hashes: BTreeMap::new() };
+        let index: SyncIndex = match std::fs::read_to_string(&file) {
+            Ok(text) => toml::from_str(&text).map_err(|e| FstnError::Config(format!("{}: {}", file.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(empty),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(if index.server == empty.server && index.remote == empty.remote { index } else { empty })
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        Ok(std::fs::write(dir.join(INDEX_FILE), toml::to_string(self)?)?)
+    }
+}
+
+/// The SHA-256 of `contents`, in hex.
+pub fn content_hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Makes the remote directory `remote` hold what the local directory
+/// `local` does, or with [`SyncOptions::download`] the other way around,
+/// reporting each step to `progress` as it is taken.
+///
+/// Files and blobs are only copied where their contents differ. Uploads
+/// otherwise work like [`put`] and downloads like [`get`], but copy the
+/// contents of one directory into the other rather than the directory
+/// itself. With [`SyncOptions::delete`], files, blobs and directories only
+/// the destination has are removed; remote gates, services and faceted
+/// directories are always left in place.
+pub fn sync(
+    fs: &Fs,
+    local: &Path,
+    remote: &FsPath,
+    options: &SyncOptions,
+    progress: &mut dyn FnMut(&Step),
+) -> Result<Vec<Step>> {
+    let index = SyncIndex::load(local, fs.server(), remote)?;
+    let sync = Sync {
+        delete: options.delete,
+        dry_run: options.dry_run,
+        checksum: options.checksum,
+        previous: index.hashes,
+        hashes: BTreeMap::new(),
+    };
+    let steps = Steps { progress, steps: Vec::new() };
+    let (steps, sync) = if options.download {
+        let entry = fs.lookup(remote)?;
+        if !entry.kind.is_some_and(EntryKind::is_directory) {
+            return Err(FstnError::InvalidPath(format!("{} is not a directory", remote)));
+        }
+        if !options.dry_run {
+            std::fs::create_dir_all(local)?;
+        }
+        let mut get = Get { fs, steps, sync: Some(sync), unlisted: HashSet::new() };
+        let manifest = get.tree(remote, local, entry)?;
+        if options.delete && local.is_dir() {
+            get.prune(local, remote, "", &manifest)?;
+        }
+        if !options.dry_run {
+            manifest.save(local)?;
+        }
+        (get.steps.steps, get.sync)
+    } else {
+        if !local.is_dir() {
+            return Err(FstnError::InvalidPath(format!("{} is not a directory", local.display())));
+        }
+        let existing = existing(fs, remote)?;
+        let options = PutOptions {
+            label: options.label.clone(),
+            blob_threshold: options.blob_threshold,
+            recursive: true,
+        };
+        let rules = LabelRules::load(local)?;
+        let mut put = Put { fs, options: &options, rules, steps, sync: Some(sync) };
+        put.contents(local, remote, &[], &existing)?;
+        (put.steps.steps, put.sync)
+    };
+    if !options.dry_run {
+        let hashes = sync.map(|s| s.hashes).unwrap_or_default();
+        SyncIndex { server: fs.server().to_string(), remote: remote.to_string(), hashes }.save(local)?;
+    }
+    Ok(steps)
+}
+
+/// The entries of the directory at `path`, by name.
+fn existing(fs: &Fs, path: &FsPath) -> Result<HashMap<String, DirEntry>> {
+    Ok(fs.ls(path)?.into_iter().map(|e| (e.name.clone(), e)).collect())
+}
+
+/// The steps an operation has taken, each passed on to a progress callback
+/// as it is taken.
+struct Steps<'a> {
+    progress: &'a mut dyn FnMut(&Step),
+    steps: Vec<Step>,
+}
+
+impl Steps<'_> {
+    fn push(&mut self, action: Action, path: &FsPath, local: &Path, label: Option<Label>, bytes: Option<u64>) {
+        let local = local.to_path_buf();
+        self.record(Step { action, path: path.clone(), local, label, bytes, sha256: None, note: None });
+    }
+
+    fn skip(&mut self, path: &FsPath, local: &Path, note: impl Into<String>) {
+        self.record(Step {
+            action: Action::Skip,
+            path: path.clone(),
+            local: local.to_path_buf(),
+            label: None,
+            bytes: None,
+            sha256: None,
+            note: Some(note.into()),
+        });
+    }
+
+    fn record(&mut self, step: Step) {
+        (self.progress)(&step);
+        self.steps.push(step);
+    }
+}
+
+/// The state of a [`sync`] in progress.
+#[derive(Debug)]
+struct Sync {
+    delete: bool,
+    dry_run: bool,
+    checksum: bool,
+    /// Remote content hashes as of the last sync.
+    previous: BTreeMap<String, String>,
+    /// Remote content hashes as of this one.
+    hashes: BTreeMap<String, String>,
+}
+
+/// Whether `name`, at the root of a local tree, is one of the files fstn
+/// keeps there.
+fn is_reserved(name: &str) -> bool {
+    matches!(name, LABELS_FILE | MANIFEST_FILE | INDEX_FILE)
+}
+
+struct Put<'a, 'b> {
+    fs: &'a Fs<'b>,
+    options: &'a PutOptions,
+    rules: LabelRules,
+    steps: Steps<'a>,
+    sync: Option<Sync>,
+}
+
+impl Put<'_, '_> {
+    fn label(&self, relative: &[String]) -> Label {
+        self.rules.label_for(relative).unwrap_or(&self.options.label).clone()
+    }
+
+    fn dry_run(&self) -> bool {
+        self.sync.as_ref().is_some_and(|s| s.dry_run)
+    }
+
+    fn dir(
+        &mut self,
+        local: &Path,
+        base: &FsPath,
+        name: &str,
+        relative: Vec<String>,
+        existing: &HashMap<String, DirEntry>,
+    ) -> Result<()> {
+        let path = base.child(name);
+        let children = match existing.get(name).map(|e| e.kind) {
+            None => {
+                let label = self.label(&relative);
+                if !self.dry_run() {
+                    self.fs.mkdir(base, name, &label)?;
+                }
+                self.steps.push(Action::Mkdir, &path, local, Some(label), None);
+                HashMap::new()
+            }
+            Some(Some(EntryKind::Directory)) => self::existing(self.fs, &path)?,
+            Some(kind) => {
+                self.steps.skip(&path, local, format!("a {} is in the way", kind_name(kind)));
+                return Ok(());
+            }
+        };
+        self.contents(local, &path, &relative, &children)
+    }
+
+    /// Uploads what is in the local directory `local` into the directory
+    /// `path`, whose entries are `children`.
+    fn contents(
+        &mut self,
+        local: &Path,
+        path: &FsPath,
+        relative: &[String],
+        children: &HashMap<String, DirEntry>,
+    ) -> Result<()> {
+        let mut entries = std::fs::read_dir(local)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        let mut seen = HashSet::new();
+        for entry in entries {
+            let file_name = entry.file_name();
+            let local = entry.path();
+            let Some(child) = file_name.to_str().filter(|n| path::name(n).is_ok()) else {
+                self.steps.skip(&path.child(&file_name.to_string_lossy()), &local, "not a valid entry name");
+                continue;
+            };
+            if relative.is_empty() && is_reserved(child) {
+                continue;
+            }
+            seen.insert(child.to_string());
+            let mut relative = relative.to_vec();
+            relative.push(child.to_string());
+            let metadata = std::fs::metadata(&local)?;
+            if metadata.is_dir() && entry.file_type()?.is_symlink() {
+                self.steps.skip(&path.child(child), &local, "a symbolic link to a directory");
+            } else if metadata.is_dir() {
+                self.dir(&local, path, child, relative, children)?;
+            } else if metadata.is_file() {
+                self.file(&local, path, child, relative, metadata.len(), children)?;
+            } else {
+                self.steps.skip(&path.child(child), &local, "not a regular file");
+            }
+        }
+        if self.sync.as_ref().is_some_and(|s| s.delete) {
+            let mut extraneous: Vec<&DirEntry> = children.values().filter(|e| !seen.contains(&e.name)).collect();
+            extraneous.sort_by(|a, b| a.name.cmp(&b.name));
+            for entry in extraneous {
+                let child = path.child(&entry.name);
+                let local = local.join(&entry.name);
+                match entry.kind {
+                    Some(EntryKind::Directory | EntryKind::File | EntryKind::Blob) => {
+                        if !self.dry_run() {
+                            self.fs.unlink(path, &entry.name)?;
+                        }
+                        self.steps.push(Action::Remove, &child, &local, None, None);
+                    }
+                    kind => self.steps.skip(&child, &local, format!("{} entries are left in place", kind_name(kind))),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        local: &Path,
+        base: &FsPath,
+        name: &str,
+        relative: Vec<String>,
+        size: u64,
+        existing: &HashMap<String, DirEntry>,
+    ) -> Result<()> {
+        let file = base.child(name);
+        let blob = base.clone().join(PathComponent::Blob(name.to_string()));
+        let entry = existing.get(name);
+        let key = relative.join("/");
+        let hash = match &self.sync {
+            Some(_) => Some(file_hash(local)?.1),
+            None => None,
+        };
+        if let (Some(hash), Some(entry)) = (&hash, entry) {
+            if self.remote_hash(&file, &key, entry.kind)?.as_ref() == Some(hash) {
+                let path = if entry.kind == Some(EntryKind::Blob) { &blob } else { &file };
+                self.steps.push(Action::Unchanged, path, local, None, None);
+                self.record_hash(key, hash.clone());
+                return Ok(());
+            }
+        }
+        let dry_run = self.dry_run();
+        match entry.map(|e| e.kind) {
+            None if size > self.options.blob_threshold => {
+                let label = self.label(&relative);
+                if !dry_run {
+                    self.fs.mkblob(base, &label, &[local.to_path_buf()], DEFAULT_CHUNK_SIZE)?;
+                }
+                self.steps.push(Action::Mkblob, &blob, local, Some(label), Some(size));
+            }
+            Some(Some(EntryKind::Blob)) => {
+                let label = entry.and_then(|e| e.label.clone()).unwrap_or_else(|| self.label(&relative));
+                if !dry_run {
+                    self.fs.unlink(base, name)?;
+                    self.fs.mkblob(base, &label, &[local.to_path_buf()], DEFAULT_CHUNK_SIZE)?;
+                }
+                self.steps.push(Action::Mkblob, &blob, local, None, Some(size));
+            }
+            None => {
+                let label = self.label(&relative);
+                if !dry_run {
+                    self.fs.mkfile(base, name, &label)?;
+                    self.fs.write_from(&file, &mut std::fs::File::open(local)?, DEFAULT_CHUNK_SIZE)?;
+                }
+                self.steps.push(Action::Write, &file, local, Some(label), Some(size));
+            }
+            Some(Some(EntryKind::File)) => {
+                if !dry_run {
+                    self.fs.write_from(&file, &mut std::fs::File::open(local)?, DEFAULT_CHUNK_SIZE)?;
+                }
+                self.steps.push(Action::Write, &file, local, None, Some(size));
+            }
+            Some(kind) => {
+                self.steps.skip(&file, local, format!("a {} is in the way", kind_name(kind)));
+                return Ok(());
+            }
+        }
+        if let Some(hash) = hash {
+            self.record_hash(key, hash);
+        }
+        Ok(())
+    }
+
+    /// The content hash of the file or blob at `path`, from the index unless
+    /// checksums were asked for, or else as the gate reports it or by
+    /// reading it. `None` if it cannot be read.
+    fn remote_hash(&self, path: &FsPath, key: &str, kind: Option<EntryKind>) -> Result<Option<String>> {
+        let Some(sync) = &self.sync else { return Ok(None) };
+        if !sync.checksum {
+            if let Some(hash) = sync.previous.get(key) {
+                return Ok(Some(hash.clone()));
+            }
+        }
+        let blob = match kind {
+            Some(EntryKind::File) => false,
+            Some(EntryKind::Blob) => true,
+            _ => return Ok(None),
+        };
+        if let Some(hash) = reported_hash(self.fs, path)? {
+            return Ok(Some(hash.to_ascii_lowercase()));
+        }
+        let mut hashing = Hashing::new(std::io::sink());
+        match contents_to(self.fs, path, blob, &mut hashing) {
+            Ok(_) => Ok(Some(hashing.finish().2)),
+            Err(e @ FstnError::Network(_)) => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn record_hash(&mut self, key: String, hash: String) {
+        if let Some(sync) = &mut self.sync {
+            sync.hashes.insert(key, hash);
+        }
+    }
+}
+
+fn kind_name(kind: Option<EntryKind>) -> &'static str {
+    kind.map_or("entry", EntryKind::name)
+}
+
+/// Downloads the entry at `path` into the directory `local`, under its own
+/// name, reporting each step to `progress` as it is taken. The contents of
+/// `~` and of the root go into `local` itself.
+///
+/// Directories and faceted directories, whose facets become subdirectories
+/// named like `<alice,T>`, are only downloaded with `recursive`, and then
+/// with a [`Manifest`]. Gates and services have no contents to download and
+/// are skipped, as are entries that cannot be read and entries whose names
+/// cannot be used locally. Existing local files are overwritten.
+pub fn get(
+    fs: &Fs,
+    path: &FsPath,
+    local: &Path,
+    recursive: bool,
+    progress: &mut dyn FnMut(&Step),
+) -> Result<Vec<Step>> {
+    let entry = fs.lookup(path)?;
+    let target = match path.last() {
+        None | Some(PathComponent::Home) => local.to_path_buf(),
+        Some(component) => local.join(local_name(component).ok_or_else(|| {
+            FstnError::InvalidPath(format!("{} cannot be saved under its name", path))
+        })?),
+    };
+    let directory = entry.kind.is_some_and(EntryKind::is_directory);
+    if directory && !recursive {
+        return Err(FstnError::InvalidPath(format!("{} is a directory; use -r to download it", path)));
+    }
+    let mut get = Get { fs, steps: Steps { progress, steps: Vec::new() }, sync: None, unlisted: HashSet::new() };
+    std::fs::create_dir_all(local)?;
+    if directory {
+        get.tree(path, &target, entry)?.save(&target)?;
+    } else {
+        get.entry(path, &target, "", &entry)?;
+    }
+    Ok(get.steps.steps)
+}
+
+/// Downloads the blob or file at `path` to the local file `local`. The
+/// contents go to a temporary file beside `local`, which replaces it only
+/// once the download is complete and, with `verify`, once its SHA-256
+/// matches the one the gate reports.
+pub fn fetch(fs: &Fs, path: &FsPath, local: &Path, verify: bool) -> Result<Step> {
+    let entry = fs.lookup(path)?;
+    let action = match entry.kind {
+        Some(EntryKind::Blob) => Action::Cat,
+        Some(EntryKind::File) => Action::Read,
+        kind => return Err(FstnError::InvalidPath(format!("{} is a {}; only blobs and files can be fetched", path, kind_name(kind)))),
+    };
+    let (partial, size, sha256) = download(fs, path, action == Action::Cat, local)?;
+    let verified = match verify {
+        true => fs.verify(path, &sha256).and_then(|reported| match reported {
+            true => Ok(()),
+            false => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{} does not report hashes, so {} cannot be verified", fs.gate(), path),
+            )
+            .into()),
+        }),
+        false => Ok(()),
+    };
+    verified.and_then(|()| Ok(std::fs::rename(&partial, local)?)).inspect_err(|_| {
+        let _ = std::fs::remove_file(&partial);
+    })?;
+    Ok(Step {
+        action,
+        path: path.clone(),
+        local: local.to_path_buf(),
+        label: entry.label,
+        bytes: Some(size),
+        sha256: Some(sha256),
+        note: verify.then(|| "verified".to_string()),
+    })
+}
+
+/// The SHA-256 the gate reports for the contents at `path`, if it reports
+/// one.
+fn reported_hash(fs: &Fs, path: &FsPath) -> Result<Option<String>> {
+    match fs.reported_stat(path) {
+        Ok(stat) => Ok(stat.and_then(|s| s.sha256)),
+        Err(e @ FstnError::Network(_)) => Err(e),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Copies the contents of the blob or file at `path` into `out`, returning
+/// how many bytes there were. Files are read a chunk at a time; blobs can
+/// only be read whole.
+fn contents_to(fs: &Fs, path: &FsPath, blob: bool, out: &mut dyn Write) -> Result<u64> {
+    match blob {
+        true => {
+            let contents = fs.cat(path)?;
+            out.write_all(&contents)?;
+            Ok(contents.len() as u64)
+        }
+        false => fs.read_to(path, out, DEFAULT_CHUNK_SIZE),
+    }
+}
+
+/// Downloads the blob or file at `path` to a temporary file beside `local`,
+/// returning it with the size and SHA-256 of the contents. The caller moves
+/// it into place; it is removed if the download fails.
+fn download(fs: &Fs, path: &FsPath, blob: bool, local: &Path) -> Result<(PathBuf, u64, String)> {
+    let name = local.file_name().ok_or_else(|| FstnError::InvalidPath(format!("{}: not a file name", local.display())))?;
+    let partial = local.with_file_name(format!(".{}.fstn-part", name.to_string_lossy()));
+    let copy = || -> Result<(u64, String)> {
+        let mut out = Hashing::new(std::io::BufWriter::new(std::fs::File::create(&partial)?));
+        contents_to(fs, path, blob, &mut out)?;
+        let (file, size, sha256) = out.finish();
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok((size, sha256))
+    };
+    let (size, sha256) = copy().inspect_err(|_| {
+        let _ = std::fs::remove_file(&partial);
+    })?;
+    Ok((partial, size, sha256))
+}
+
+/// The local file name for a path component, unless it cannot be one.
+fn local_name(component: &PathComponent) -> Option<String> {
+    let name = match component {
+        PathComponent::Name(name) | PathComponent::Gate(name) | PathComponent::Blob(name) => name.clone(),
+        PathComponent::Facet(label) => format!("<{}>", label),
+        PathComponent::Home => return None,
+    };
+    let usable = !matches!(name.as_str(), "" | "." | "..") && !name.contains(['/', '\\', '\0']);
+    usable.then_some(name)
+}
+
+struct Get<'a, 'b> {
+    fs: &'a Fs<'b>,
+    steps: Steps<'a>,
+    sync: Option<Sync>,
+    /// The directories, by relative path, that could not be listed and so
+    /// must not be pruned.
+    unlisted: HashSet<String>,
+}
+
+impl Get<'_, '_> {
+    fn dry_run(&self) -> bool {
+        self.sync.as_ref().is_some_and(|s| s.dry_run)
+    }
+
+    /// Downloads the directory `root`, listed as `entry`, and everything in
+    /// it to `target`, returning the manifest of what was found.
+    fn tree(&mut self, root: &FsPath, target: &Path, entry: DirEntry) -> Result<Manifest> {
+        self.entry(root, target, ".", &entry)?;
+        let mut manifest = Manifest::default();
+        manifest.entries.insert(".".into(), ManifestEntry { kind: entry.kind, label: entry.label, skipped: false });
+        let depth = root.components().len();
+        for ListedEntry { path, entry, unlisted, .. } in self.fs.walk(root, None, |a, b| a.name.cmp(&b.name))? {
+            let relative: Option<Vec<String>> = path.components()[depth..].iter().map(local_name).collect();
+            let Some(relative) = relative else {
+                self.steps.skip(&path, target, "the name cannot be used locally");
+                continue;
+            };
+            let local = relative.iter().fold(target.to_path_buf(), |local, name| local.join(name));
+            let key = relative.join("/");
+            if unlisted {
+                self.unlisted.insert(key.clone());
+            }
+            let skipped = !self.entry(&path, &local, &key, &entry)?;
+            manifest.entries.insert(key, ManifestEntry { kind: entry.kind, label: entry.label, skipped });
+        }
+        Ok(manifest)
+    }
+
+    /// Downloads one entry to `local`, returning whether it was.
+    fn entry(&mut self, path: &FsPath, local: &Path, key: &str, entry: &DirEntry) -> Result<bool> {
+        let action = match entry.kind {
+            Some(kind) if kind.is_directory() => {
+                if !local.is_dir() {
+                    if !self.dry_run() {
+                        std::fs::create_dir(local)?;
+                    }
+                    self.steps.push(Action::Mkdir, path, local, entry.label.clone(), None);
+                }
+                return Ok(true);
+            }
+            Some(EntryKind::File) => Action::Read,
+            Some(EntryKind::Blob) => Action::Cat,
+            Some(kind) => {
+                self.steps.skip(path, local, format!("{}s have no contents to download", kind.name()));
+                return Ok(false);
+            }
+            None => {
+                self.steps.skip(path, local, "the entry is of an unknown kind");
+                return Ok(false);
+            }
+        };
+        let local_hash = match &self.sync {
+            Some(_) if local.is_file() => Some(file_hash(local)?.1),
+            _ => None,
+        };
+        if let Some(local_hash) = &local_hash {
+            if reported_hash(self.fs, path)?.is_some_and(|remote| remote.eq_ignore_ascii_case(local_hash)) {
+                if let Some(sync) = &mut self.sync {
+                    sync.hashes.insert(key.to_string(), local_hash.clone());
+                }
+                self.steps.push(Action::Unchanged, path, local, None, None);
+                return Ok(true);
+            }
+        }
+        let blob = action == Action::Cat;
+        let downloaded = match self.dry_run() {
+            true => {
+                let mut hashing = Hashing::new(std::io::sink());
+                contents_to(self.fs, path, blob, &mut hashing).map(|_| {
+                    let (_, size, hash) = hashing.finish();
+                    (None, size, hash)
+                })
+            }
+            false => download(self.fs, path, blob, local).map(|(partial, size, hash)| (Some(partial), size, hash)),
+        };
+        let (partial, size, hash) = match downloaded {
+            Ok(downloaded) => downloaded,
+            Err(e @ (FstnError::Network(_) | FstnError::Io(_))) => return Err(e),
+            Err(e) => {
+                self.steps.skip(path, local, format!("cannot be read: {}", e));
+                return Ok(false);
+            }
+        };
+        if let Some(sync) = &mut self.sync {
+            sync.hashes.insert(key.to_string(), hash.clone());
+        }
+        if local_hash == Some(hash) {
+            if let Some(partial) = partial {
+                std::fs::remove_file(partial)?;
+            }
+            self.steps.push(Action::Unchanged, path, local, None, None);
+            return Ok(true);
+        }
+        if let Some(partial) = partial {
+            std::fs::rename(partial, local)?;
+        }
+        self.steps.push(action, path, local, entry.label.clone(), Some(size));
+        Ok(true)
+    }
+
+    /// Removes what is in the local directory `local` but not in the
+    /// manifest of the remote directory `path` it was downloaded from,
+    /// leaving alone what is below directories that could not be listed.
+    fn prune(&mut self, local: &Path, path: &FsPath, relative: &str, manifest: &Manifest) -> Result<()> {
+        let mut entries = std::fs::read_dir(local)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if relative.is_empty() && is_reserved(&name) {
+                continue;
+            }
+            let key = if relative.is_empty() { name.clone() } else { format!("{}/{}", relative, name) };
+            let (child, local) = (path.child(&name), entry.path());
+            let is_dir = entry.file_type()?.is_dir();
+            if !manifest.entries.contains_key(&key) {
+                if !self.dry_run() {
+                    match is_dir {
+                        true => std::fs::remove_dir_all(&local)?,
+                        false => std::fs::remove_file(&local)?,
+                    }
+                }
+                self.steps.push(Action::Remove, &child, &local, None, None);
+            } else if is_dir && self.unlisted.contains(&key) {
+                self.steps.skip(&child, &local, "its contents could not be listed, so nothing in it is removed");
+            } else if is_dir {
+                self.prune(&local, &child, &key, manifest)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1073 @@
+mod support;
+
+use std::path::Path;
+
+use serde_json::json;
+use support::{local_file, Entry, Fstn};
+
+#[test]
+fn login_saves_token() {
+    let fstn = Fstn::new();
+    let token = fstn.gateway.user("alice");
+    let output = fstn.ok_with_stdin(&["login", "--no-browser"], format!("{}\n", token).as_bytes());
+    assert!(String::from_utf8_lossy(&output).contains("/login/cas"));
+    let credentials = std::fs::read_to_string(fstn.config_dir().join("credentials")).unwrap();
+    assert!(credentials.contains(&token));
+    assert_eq!(fstn.json(&["whoami"])["identity"]["login"], "alice");
+}
+
+#[test]
+fn commands_require_login() {
+    let fstn = Fstn::new();
+    let output = fstn.run(&["whoami"]);
+    assert_eq!(output.status.code(), Some(3));
+    let output = fstn.run(&["fs", "ls", "~"]);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn rejected_token_is_an_http_error() {
+    let fstn = Fstn::new();
+    fstn.ok_with_stdin(&["login", "--with-token"], b"bogus");
+    let output = fstn.run(&["whoami"]);
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn whoami() {
+    let fstn = Fstn::logged_in();
+    let text = String::from_utf8(fstn.ok(&["whoami"])).unwrap();
+    assert!(text.contains("login:      alice\n"), "{}", text);
+    assert!(text.contains("clearance:  alice,T\n"), "{}", text);
+    assert!(text.contains("fsutil:     home:<alice,alice>:fsutil (present)\n"), "{}", text);
+    assert!(text.contains("credential: default (credentials file)\n"), "{}", text);
+
+    let me = fstn.json(&["whoami"]);
+    assert_eq!(me["identity"]["privilege"], "alice");
+    assert_eq!(me["identity"]["home"], serde_json::json!(["home", "<alice,alice>"]));
+    assert_eq!(me["user"], "default");
+    assert_eq!(me["store"], "file");
+    assert_eq!(me["profile"], "default");
+    assert_eq!(me["fsutil_exists"], true);
+
+    if let Entry::Dir { entries, .. } = fstn.gateway.state().get("alice", "~") {
+        entries.remove("fsutil");
+    }
+    assert_eq!(fstn.json(&["whoami"])["fsutil_exists"], false);
+}
+
+#[test]
+fn delegate_and_save() {
+    let fstn = Fstn::logged_in();
+    let doc = fstn.json(&["delegate", "alice", "--save"]);
+    assert_eq!(doc["privilege"], "alice");
+    assert_eq!(doc["saved"], true);
+    let token = doc["token"].as_str().unwrap().to_string();
+    assert_eq!(fstn.gateway.state().delegations[0].2, token);
+
+    let whoami = fstn.json(&["--user", "alice", "whoami"]);
+    assert_eq!(whoami["identity"]["login"], "alice");
+}
+
+#[test]
+fn delegation_tree() {
+    let fstn = Fstn::logged_in();
+    let first = fstn.json(&["delegate", "alice", "--save", "--ttl", "1h"]);
+    assert_eq!(first["id"], 1);
+    assert!(first["expires_at"].as_u64().is_some());
+    let second = fstn.json(&["delegate", "alice/photos", "--from", "alice", "--save", "--clearance", "alice,T"]);
+    assert_eq!(second["expires_at"], serde_json::Value::Null);
+    fstn.ok(&["delegate", "alice/ci"]);
+    assert_eq!(fstn.gateway.state().delegations[1].0, first["token"].as_str().unwrap());
+
+    let tree = fstn.json(&["delegate", "tree"]);
+    let delegations = tree["delegations"].as_array().unwrap();
+    assert_eq!(delegations.len(), 3);
+    assert_eq!(delegations[1]["parent"], 1);
+    assert_eq!(delegations[1]["from"], "alice");
+    assert_eq!(delegations[2]["parent"], serde_json::Value::Null);
+    let text = String::from_utf8(fstn.ok(&["delegate", "tree"])).unwrap();
+    assert!(text.contains("├── #1 alice (saved as alice, expires in"), "{}", text);
+    assert!(text.contains("│   └── #2 alice/photos (saved as alice/photos)"), "{}", text);
+    assert!(text.contains("└── #3 alice/ci"), "{}", text);
+
+    let discard = fstn.json(&["delegate", "tree", "--discard", "1"]);
+    assert_eq!(discard["discarded"].as_array().unwrap().len(), 2);
+    assert_eq!(discard["delegations"].as_array().unwrap().len(), 1);
+    let users: Vec<_> = fstn.json(&["auth", "list"])["credentials"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["user"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(users, ["default"]);
+    assert_eq!(fstn.run(&["delegate", "tree", "--discard", "1"]).status.code(), Some(7));
+    assert_eq!(fstn.run(&["delegate"]).status.code(), Some(2));
+    assert_eq!(fstn.run(&["delegate", "x", "--ttl", "soon"]).status.code(), Some(2));
+    assert_eq!(fstn.run(&["delegate", "x", "--from", "nobody"]).status.code(), Some(3));
+}
+
+#[test]
+fn invoke_gate() {
+    let fstn = Fstn::logged_in();
+    assert_eq!(fstn.ok(&["invoke", "echo", "{\"x\":1}"]), b"{\"x\":1}");
+    assert_eq!(fstn.ok_with_stdin(&["invoke", "echo"], b"from stdin"), b"from stdin");
+}
+
+#[test]
+fn pings() {
+    let fstn = Fstn::new();
+    assert!(fstn.ok(&["ping"]).starts_with(b"ping: "));
+    let doc = fstn.json(&["ping-scheduler"]);
+    assert_eq!(doc["target"], "scheduler");
+    assert!(doc["elapsed_ms"].as_f64().is_some());
+}
+
+#[test]
+fn build_image() {
+    let fstn = Fstn::new();
+    let source = tempfile::tempdir().unwrap();
+    local_file(source.path(), "main.py", b"print('hi')");
+    std::fs::create_dir(source.path().join("lib")).unwrap();
+    local_file(&source.path().join("lib"), "util.py", b"");
+    let image = fstn.config.path().join("function.img");
+    let doc = fstn.json(&["build", source.path().to_str().unwrap(), "-o", image.to_str().unwrap()]);
+    assert!(doc["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(std::fs::read(&image).unwrap()[..4], *b"hsqs");
+}
+
+#[test]
+fn fs_ping() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "ping"]);
+    assert_eq!(fstn.gateway.state().ops, ["ping"]);
+}
+
+#[test]
+fn fs_files() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkdir", "~", "docs"]);
+    fstn.ok(&["fs", "mkfile", "--label", "alice,alice", "~:docs", "notes"]);
+    fstn.ok_with_stdin(&["fs", "write", "~:docs:notes"], b"hello\x00world");
+    assert_eq!(fstn.ok(&["fs", "read", "~:docs:notes"]), b"hello\x00world");
+
+    let listing = fstn.json(&["fs", "ls", "~:docs"]);
+    assert_eq!(
+        listing["entries"],
+        json!([{"name": "notes", "kind": "file", "label": "alice,alice", "path": ["~", "docs", "notes"], "depth": 0}])
+    );
+    assert_eq!(fstn.ok(&["fs", "ls", "~"]), b"docs\nfsutil\n");
+
+    fstn.ok(&["fs", "unlink", "~:docs", "notes"]);
+    assert_eq!(fstn.ok(&["fs", "ls", "~:docs"]), b"");
+}
+
+#[test]
+fn fs_chunked_files() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkfile", "~", "big"]);
+    let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    fstn.ok_with_stdin(&["fs", "write", "--chunk-size", "300", "~:big"], &data);
+    let Entry::File { data: written, .. } = fstn.gateway.state().get("alice", "~:big").clone() else { panic!() };
+    assert_eq!(written, data);
+    // An empty write at offset 0 first checks that the gate supports offsets.
+    assert_eq!(fstn.gateway.state().ops.iter().filter(|op| *op == "write").count(), 5);
+
+    fstn.gateway.state().ops.clear();
+    assert_eq!(fstn.ok(&["fs", "read", "--chunk-size", "300", "~:big"]), data);
+    assert_eq!(fstn.gateway.state().ops, ["read"; 4]);
+    let report = fstn.json(&["fs", "read", "--chunk-size", "500", "~:big"]);
+    assert_eq!(report["result"]["value"], base64::encode(&data));
+
+    // Gates that know nothing of chunks still serve whole files.
+    fstn.gateway.state().unchunked = true;
+    fstn.gateway.state().ops.clear();
+    assert_eq!(fstn.ok(&["fs", "read", "--chunk-size", "300", "~:big"]), data);
+    assert_eq!(fstn.gateway.state().ops, ["read"]);
+    fstn.ok_with_stdin(&["fs", "write", "--chunk-size", "300", "~:big"], b"small");
+    // Longer input is written whole rather than cut short, and input of
+    // exactly one chunk takes a single plain write.
+    fstn.gateway.state().ops.clear();
+    fstn.ok_with_stdin(&["fs", "write", "--chunk-size", "300", "~:big"], &data);
+    let Entry::File { data: written, .. } = fstn.gateway.state().get("alice", "~:big").clone() else { panic!() };
+    assert_eq!(written, data);
+    assert_eq!(fstn.gateway.state().ops, ["write"; 2]);
+    fstn.gateway.state().ops.clear();
+    fstn.ok_with_stdin(&["fs", "write", "--chunk-size", "300", "~:big"], &data[..300]);
+    let Entry::File { data: written, .. } = fstn.gateway.state().get("alice", "~:big").clone() else { panic!() };
+    assert_eq!(written, &data[..300]);
+    assert_eq!(fstn.gateway.state().ops, ["write"]);
+}
+
+#[test]
+fn fs_stat() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkfile", "--label", "alice,T", "~", "notes"]);
+    fstn.ok_with_stdin(&["fs", "write", "~:notes"], b"hello");
+    let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    let stat = fstn.json(&["fs", "stat", "~:notes"]);
+    assert_eq!(
+        stat,
+        json!({
+            "command": "stat", "path": ["~", "notes"], "kind": "file", "label": "alice,T", "size": 5, "sha256": hello
+        })
+    );
+    assert_eq!(
+        String::from_utf8(fstn.ok(&["fs", "stat", "~:notes"])).unwrap(),
+        format!("path:      ~:notes\nkind:      file\nlabel:     alice,T\nsize:      5 (5 B)\nsha256:    {}\n", hello)
+    );
+    let stat = fstn.json(&["fs", "stat", "~:fsutil"]);
+    assert_eq!((&stat["kind"], &stat["privilege"]), (&json!("gate"), &json!("alice")));
+    assert_eq!(stat["clearance"], "alice,T");
+    assert_eq!(fstn.json(&["fs", "stat", "~"])["kind"], "directory");
+    assert_eq!(fstn.run(&["fs", "stat", "~:missing"]).status.code(), Some(7));
+
+    // Gates without stat are answered from a listing and the contents.
+    fstn.gateway.state().unknown_ops.push("stat");
+    let stat = fstn.json(&["fs", "stat", "~:notes"]);
+    assert_eq!((&stat["size"], &stat["sha256"]), (&json!(5), &json!(hello)));
+    let output = fstn.run(&["fs", "stat", "~:fsutil"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the settings of ~:fsutil are unknown"));
+    assert_eq!(fstn.run(&["fs", "stat", "~:missing"]).status.code(), Some(7));
+}
+
+#[test]
+fn fs_failures_exit_nonzero() {
+    let fstn = Fstn::logged_in();
+    let output = fstn.run(&["fs", "read", "~:missing"]);
+    assert_eq!(output.status.code(), Some(7));
+    fstn.ok(&["fs", "mkdir", "~", "docs"]);
+    let output = fstn.run(&["fs", "mkdir", "~", "docs"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("mkdir failed: exists"));
+}
+
+#[test]
+fn fs_blobs() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let a = local_file(dir.path(), "a.tgz", b"aaaa");
+    let b = local_file(dir.path(), "b.tgz", b"bbbbbb");
+    fstn.ok(&["fs", "mkblob", "~", a.to_str().unwrap(), b.to_str().unwrap()]);
+    assert_eq!(fstn.ok(&["fs", "cat", "~:a.tgz"]), b"aaaa");
+    assert_eq!(fstn.ok(&["fs", "cat", "~:b.tgz"]), b"bbbbbb");
+}
+
+#[test]
+fn fs_resumable_blob_uploads() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let image = local_file(dir.path(), "rootfs.img", b"0123456789");
+    let image = image.to_str().unwrap();
+    let uploads = |fstn: &Fstn| fstn.gateway.state().ops.drain(..).filter(|op| op == "upload").count();
+
+    // The upload stops after the first piece and resumes from there.
+    fstn.gateway.state().upload_budget = Some(1);
+    assert_eq!(fstn.run(&["fs", "mkblob", "--chunk-size", "4", "~", image]).status.code(), Some(5));
+    assert_eq!(uploads(&fstn), 3);
+    fstn.gateway.state().upload_budget = None;
+    fstn.ok(&["fs", "mkblob", "--chunk-size", "4", "~", image]);
+    assert_eq!(uploads(&fstn), 3);
+    assert_eq!(fstn.ok(&["fs", "cat", "~:rootfs.img"]), b"0123456789");
+    assert!(fstn.gateway.state().uploads.is_empty());
+
+    // A connection dropped partway through a piece is retried from as much
+    // as the gate received.
+    let piece = 64 << 10;
+    let contents: Vec<u8> = (0..3 * piece).map(|i| (i % 251) as u8).collect();
+    let large = local_file(dir.path(), "large.img", &contents);
+    fstn.ok(&["fs", "mkdir", "~", "large"]);
+    fstn.gateway.state().requests.clear();
+    fstn.gateway.state().drop_after = Some(piece + piece / 2);
+    fstn.ok(&["fs", "mkblob", "--chunk-size", &piece.to_string(), "~:large", large.to_str().unwrap()]);
+    let offsets: Vec<_> = fstn.gateway.state().requests.iter()
+        .filter(|request| request["op"] == "upload")
+        .map(|request| request["args"]["offset"].as_u64().unwrap())
+        .collect();
+    let piece = piece as u64;
+    assert_eq!(offsets, [0, 0, 0, piece, 2 * piece]);
+    assert_eq!(fstn.gateway.state().drop_after, None);
+    assert_eq!(fstn.ok(&["fs", "cat", "~:large:large.img"]), contents);
+    fstn.gateway.state().ops.clear();
+
+    // Small files and gates without upload take a single request.
+    fstn.ok(&["fs", "mkdir", "~", "small"]);
+    fstn.ok(&["fs", "mkblob", "--chunk-size", "10", "~:small", image]);
+    assert_eq!(uploads(&fstn), 0);
+    fstn.gateway.state().unknown_ops.push("upload");
+    fstn.ok(&["fs", "mkdir", "~", "old"]);
+    fstn.ok(&["fs", "mkblob", "--chunk-size", "4", "~:old", image]);
+    assert_eq!(fstn.ok(&["fs", "cat", "~:old:rootfs.img"]), b"0123456789");
+}
+
+#[test]
+fn fs_gate_images_reuse_uploaded_blobs() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let kernel = local_file(dir.path(), "vmlinux", b"kernel");
+    fstn.ok(&["fs", "mkdir", "~", "images"]);
+    fstn.ok(&["fs", "mkblob", "~:images", kernel.to_str().unwrap()]);
+    let cache = std::fs::read_to_string(fstn.config_dir().join("blobs.toml")).unwrap();
+    assert!(cache.contains("path = \"~:images:vmlinux\""), "{}", cache);
+
+    let kernel = format!("@{}", kernel.display());
+    let mkgate = |name: &str, extra: &[&str]| {
+        let mut args = vec!["fs"];
+        args.extend(extra);
+        args.extend(["mkgate", "-l", "alice,alice", "-p", "alice", "-c", "alice,T", "-m", "128"]);
+        args.extend(["-a", "apps:thumbnail", "-k", &kernel, "-r", "runtimes:python", "~", name]);
+        fstn.ok(&args);
+        let mut state = fstn.gateway.state();
+        let Entry::Gate { spec, blobs, .. } = state.get("alice", &format!("~:{}", name)).clone() else {
+            panic!("not a gate");
+        };
+        (spec["kernel"].clone(), blobs.contains_key("kernel"))
+    };
+    assert_eq!(mkgate("first", &[]), (json!(["~", "images", "vmlinux"]), false));
+
+    // Without the cache, the image is uploaded as a blob next to the gate.
+    let uploaded = json!(["~", "vmlinux-6923dd1bc046"]);
+    assert_eq!(mkgate("second", &["--no-blob-cache"]), (uploaded.clone(), false));
+    assert_eq!(fstn.ok(&["fs", "cat", "~:vmlinux-6923dd1bc046"]), b"kernel");
+
+    // A blob that no longer holds the contents is forgotten, and the one
+    // next to the gate is used and recorded instead.
+    *fstn.gateway.state().get("alice", "~:images:vmlinux") = Entry::Blob { label: "T,T".into(), data: b"patched".to_vec() };
+    fstn.gateway.state().ops.clear();
+    assert_eq!(mkgate("third", &[]), (uploaded.clone(), false));
+    assert!(!fstn.gateway.state().ops.iter().any(|op| op == "upload"));
+    let cache = std::fs::read_to_string(fstn.config_dir().join("blobs.toml")).unwrap();
+    assert!(!cache.contains("~:images:vmlinux"), "{}", cache);
+    assert!(cache.contains("path = \"~:vmlinux-6923dd1bc046\""), "{}", cache);
+
+    // Upgrading a gate uploads its new image next to it, with its label.
+    let runtime = local_file(dir.path(), "python.ext4", b"runtime");
+    fstn.ok(&["fs", "upgate", "-r", &format!("@{}", runtime.display()), "~:third"]);
+    let mut state = fstn.gateway.state();
+    let Entry::Gate { spec, .. } = state.get("alice", "~:third").clone() else { panic!("not a gate") };
+    let name = spec["runtime"][1].as_str().unwrap().to_string();
+    assert!(name.starts_with("python.ext4-"), "{}", name);
+    let Entry::Blob { label, data } = state.get("alice", &format!("~:{}", name)).clone() else { panic!("not a blob") };
+    assert_eq!((label.as_str(), data.as_slice()), ("alice,alice", &b"runtime"[..]));
+    drop(state);
+
+    // Gates without upload still take images in the request.
+    fstn.gateway.state().unknown_ops.push("upload");
+    assert_eq!(mkgate("fourth", &["--no-blob-cache"]), (uploaded, false));
+    let kernel = local_file(dir.path(), "other", b"other kernel");
+    fstn.ok(&["fs", "upgate", "-k", &format!("@{}", kernel.display()), "~:fourth"]);
+    let mut state = fstn.gateway.state();
+    let Entry::Gate { blobs, .. } = state.get("alice", "~:fourth").clone() else { panic!("not a gate") };
+    assert_eq!(blobs.get("kernel").map(Vec::as_slice), Some(&b"other kernel"[..]));
+}
+
+#[test]
+fn fs_fetch() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let kernel = local_file(dir.path(), "vmlinux", b"kernel");
+    fstn.ok(&["fs", "mkblob", "~", kernel.to_str().unwrap()]);
+    fstn.ok(&["fs", "mkfile", "~", "notes"]);
+    fstn.ok_with_stdin(&["fs", "write", "~:notes"], b"hello");
+
+    let out = dir.path().join("out");
+    let report = fstn.json(&["fs", "fetch", "--verify", "~:vmlinux", out.to_str().unwrap()]);
+    let step = &report["steps"][0];
+    assert_eq!((&step["action"], &step["bytes"], &step["note"]), (&json!("cat"), &json!(6), &json!("verified")));
+    assert_eq!(step["sha256"], "6923dd1bc0460082c5d55a831908c24a282860b7f1cd6c2b79cf1bc8857c639c");
+    assert_eq!(std::fs::read(&out).unwrap(), b"kernel");
+    fstn.ok(&["fs", "fetch", "~:notes", out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&out).unwrap(), b"hello");
+    assert_eq!(fstn.run(&["fs", "fetch", "~", out.to_str().unwrap()]).status.code(), Some(14));
+
+    // A mismatch fails the command and leaves the old file in place.
+    fstn.gateway.state().wrong_hashes = true;
+    let output = fstn.run(&["fs", "fetch", "--verify", "~:vmlinux", out.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(15));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("integrity check failed"));
+    assert_eq!(std::fs::read(&out).unwrap(), b"hello");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    fstn.ok(&["fs", "mkdir", "~", "images"]);
+    assert_eq!(fstn.run(&["fs", "mkblob", "~:images", kernel.to_str().unwrap()]).status.code(), Some(15));
+
+    // Without stat, downloads work but cannot be verified.
+    fstn.gateway.state().unknown_ops.push("stat");
+    fstn.ok(&["fs", "fetch", "~:vmlinux", out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&out).unwrap(), b"kernel");
+    let output = fstn.run(&["fs", "fetch", "--verify", "~:vmlinux", out.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be verified"));
+}
+
+#[test]
+fn fs_put_tree() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let site = dir.path().join("site");
+    std::fs::create_dir_all(site.join("public/drafts")).unwrap();
+    local_file(&site, "index.html", b"<h1>hi</h1>");
+    local_file(&site, "video.mp4", &[7; 64]);
+    local_file(&site.join("public"), "a.txt", b"a");
+    local_file(&site.join("public/drafts"), "b.txt", b"b");
+    local_file(&site, ".fstnlabels", b"\"public\" = \"T,alice\"\n\"public/drafts\" = \"alice,alice\"\n");
+    let site = site.to_str().unwrap();
+
+    let report = fstn.json(&["fs", "put", "-r", "--label", "alice,T", "--blob-threshold", "32", site, "~"]);
+    assert_eq!(report["command"], "transfer");
+    assert_eq!(report["summary"], json!({"directories": 3, "files": 3, "blobs": 1, "bytes": 77, "unchanged": 0, "removed": 0, "skipped": 0}));
+    {
+        let mut state = fstn.gateway.state();
+        assert_eq!(state.get("alice", "~:site:index.html"), &Entry::File { label: "alice,T".into(), data: b"<h1>hi</h1>".to_vec() });
+        assert_eq!(state.get("alice", "~:site:video.mp4"), &Entry::Blob { label: "alice,T".into(), data: vec![7; 64] });
+        assert_eq!(state.get("alice", "~:site:public:a.txt"), &Entry::File { label: "T,alice".into(), data: b"a".to_vec() });
+        let Entry::Dir { label, .. } = state.get("alice", "~:site:public:drafts") else { panic!("not a directory") };
+        assert_eq!(label, "alice,alice");
+        let Entry::Dir { entries, .. } = state.get("alice", "~:site") else { panic!("not a directory") };
+        assert!(!entries.contains_key(".fstnlabels"));
+    }
+
+    // Uploading again overwrites files and replaces blobs in place.
+    local_file(Path::new(site), "index.html", b"<h1>bye</h1>");
+    let output = fstn.command(&["fs", "put", "-r", "--blob-threshold", "32", site, "~"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("~:site:index.html (12 B)"), "{}", stderr);
+    assert!(stderr.contains("~:site:#video.mp4 (64 B)"), "{}", stderr);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "0 directories, 3 files, 1 blob (78 B)\n");
+    assert_eq!(fstn.ok(&["fs", "read", "~:site:index.html"]), b"<h1>bye</h1>");
+
+    let output = fstn.run(&["fs", "put", site, "~"]);
+    assert_eq!(output.status.code(), Some(14));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -r"));
+    fstn.ok(&["fs", "put", &format!("{}/public/a.txt", site), "~"]);
+    assert_eq!(fstn.ok(&["fs", "read", "~:a.txt"]), b"a");
+
+    // Links are uploaded under their own name, as `mkblob` does.
+    let link = dir.path().join("link.txt");
+    std::os::unix::fs::symlink(Path::new(site).join("public/a.txt"), &link).unwrap();
+    fstn.ok(&["fs", "put", link.to_str().unwrap(), "~"]);
+    assert_eq!(fstn.ok(&["fs", "read", "~:link.txt"]), b"a");
+    fstn.ok(&["fs", "put", "--blob-threshold", "0", link.to_str().unwrap(), "~:site"]);
+    assert_eq!(fstn.ok(&["fs", "cat", "~:site:#link.txt"]), b"a");
+}
+
+#[test]
+fn fs_tree_transfers_stream_large_files() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..(4 << 20) + 100).map(|i| (i % 251) as u8).collect();
+    let big = local_file(dir.path(), "big.bin", &data);
+    let count = |op: &str| fstn.gateway.state().ops.iter().filter(|o| *o == op).count();
+
+    fstn.ok(&["fs", "put", "--blob-threshold", "16777216", big.to_str().unwrap(), "~"]);
+    let Entry::File { data: written, .. } = fstn.gateway.state().get("alice", "~:big.bin").clone() else { panic!() };
+    assert_eq!(written, data);
+    assert_eq!(count("write"), 3);
+
+    fstn.gateway.state().ops.clear();
+    fstn.ok(&["fs", "cp", "~:big.bin", "~", "copy.bin"]);
+    assert_eq!((count("read"), count("write")), (2, 3));
+
+    fstn.gateway.state().ops.clear();
+    let out = dir.path().join("out");
+    fstn.ok(&["fs", "get", "~:copy.bin", out.to_str().unwrap()]);
+    assert_eq!(count("read"), 2);
+    assert_eq!(std::fs::read(out.join("copy.bin")).unwrap(), data);
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 1);
+
+    // Gates without offsets get the file whole rather than cut short.
+    fstn.gateway.state().unchunked = true;
+    fstn.ok(&["fs", "cp", "~:big.bin", "~", "stock.bin"]);
+    let Entry::File { data: written, .. } = fstn.gateway.state().get("alice", "~:stock.bin").clone() else { panic!() };
+    assert_eq!(written, data);
+}
+
+#[test]
+fn fs_get_tree() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkdir", "--label", "alice,T", "~", "site"]);
+    fstn.ok(&["fs", "mkfile", "~:site", "index.html"]);
+    fstn.ok_with_stdin(&["fs", "write", "~:site:index.html"], b"<h1>hi</h1>");
+    fstn.ok(&["fs", "mkgate", "-l", "T,T", "-p", "alice", "-c", "T,T", "-g", "~:fsutil", "~:site", "resize"]);
+    fstn.ok(&["fs", "mkfaceted", "~:site", "shared"]);
+    fstn.ok(&["fs", "mkfile", "--label", "alice,T", "~:site:shared:<alice,T>", "f"]);
+    let dir = tempfile::tempdir().unwrap();
+    let video = local_file(dir.path(), "video.mp4", b"frames");
+    fstn.ok(&["fs", "mkblob", "~:site", video.to_str().unwrap()]);
+
+    let out = dir.path().join("out");
+    let report = fstn.json(&["fs", "get", "-r", "~:site", out.to_str().unwrap()]);
+    assert_eq!(report["summary"], json!({"directories": 3, "files": 2, "blobs": 1, "bytes": 17, "unchanged": 0, "removed": 0, "skipped": 1}));
+    let skipped = &report["steps"].as_array().unwrap().iter().find(|s| s["action"] == "skip").unwrap();
+    assert_eq!(skipped["note"], "gates have no contents to download");
+    assert_eq!(std::fs::read(out.join("site/index.html")).unwrap(), b"<h1>hi</h1>");
+    assert_eq!(std::fs::read(out.join("site/video.mp4")).unwrap(), b"frames");
+    assert_eq!(std::fs::read(out.join("site/shared/<alice,T>/f")).unwrap(), b"");
+    assert!(!out.join("site/resize").exists());
+
+    let manifest: toml::Value = std::fs::read_to_string(out.join("site/.fstnmanifest")).unwrap().parse().unwrap();
+    let entries = &manifest["entries"];
+    assert_eq!(entries["."]["label"].as_str(), Some("alice,T"));
+    assert_eq!(entries["video.mp4"]["kind"].as_str(), Some("blob"));
+    assert_eq!(entries["resize"]["skipped"].as_bool(), Some(true));
+    assert_eq!(entries["shared/<alice,T>/f"]["label"].as_str(), Some("alice,T"));
+
+    // Uploading the copy restores labels from the manifest.
+    fstn.ok(&["fs", "mkdir", "~", "restored"]);
+    fstn.ok(&["fs", "put", "-r", out.join("site").to_str().unwrap(), "~:restored"]);
+    let Entry::Dir { label, .. } = fstn.gateway.state().get("alice", "~:restored:site").clone() else { panic!() };
+    assert_eq!(label, "alice,T");
+
+    let output = fstn.run(&["fs", "get", "~:site", out.to_str().unwrap()]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -r"));
+    fstn.ok(&["fs", "get", "~:site:index.html", dir.path().to_str().unwrap()]);
+    assert_eq!(std::fs::read(dir.path().join("index.html")).unwrap(), b"<h1>hi</h1>");
+    assert_eq!(fstn.run(&["fs", "get", "~:site:nope", dir.path().to_str().unwrap()]).status.code(), Some(7));
+}
+
+#[test]
+fn fs_sync() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("site");
+    std::fs::create_dir_all(local.join("css")).unwrap();
+    local_file(&local, "index.html", b"<h1>hi</h1>");
+    local_file(&local.join("css"), "main.css", b"h1 {}");
+    let site = local.to_str().unwrap();
+    fstn.ok(&["fs", "mkdir", "~", "site"]);
+    fstn.ok(&["fs", "mkfile", "~:site", "stale.txt"]);
+
+    let summary = |args: &[&str]| fstn.json(args)["summary"].clone();
+    assert_eq!(summary(&["fs", "sync", site, "~:site"])["files"], 2);
+    assert!(local.join(".fstnsync").exists());
+    fstn.gateway.state().ops.clear();
+    let report = summary(&["fs", "sync", site, "~:site"]);
+    assert_eq!((report["files"].clone(), report["unchanged"].clone()), (json!(0), json!(2)));
+    assert!(!fstn.gateway.state().ops.iter().any(|op| op == "write" || op == "read"));
+
+    // Only the changed file is uploaded; --dry-run changes nothing.
+    local_file(&local, "index.html", b"<h1>bye</h1>");
+    let dry = fstn.json(&["fs", "sync", "--dry-run", "--delete", site, "~:site"]);
+    assert_eq!(dry["dry_run"], true);
+    assert_eq!((dry["summary"]["files"].clone(), dry["summary"]["removed"].clone()), (json!(1), json!(1)));
+    assert_eq!(fstn.ok(&["fs", "read", "~:site:index.html"]), b"<h1>hi</h1>");
+    let output = fstn.ok(&["fs", "sync", "--delete", site, "~:site"]);
+    assert_eq!(String::from_utf8(output).unwrap(), "0 directories, 1 file, 0 blobs (12 B), 1 unchanged, 1 removed\n");
+    assert_eq!(fstn.ok(&["fs", "read", "~:site:index.html"]), b"<h1>bye</h1>");
+    assert_eq!(fstn.run(&["fs", "read", "~:site:stale.txt"]).status.code(), Some(7));
+
+    // The index is trusted unless --checksum is given.
+    fstn.ok_with_stdin(&["fs", "write", "~:site:css:main.css"], b"h2 {}");
+    assert_eq!(summary(&["fs", "sync", site, "~:site"])["files"], 0);
+    assert_eq!(summary(&["fs", "sync", "--checksum", site, "~:site"])["files"], 1);
+    assert_eq!(fstn.ok(&["fs", "read", "~:site:css:main.css"]), b"h1 {}");
+
+    // Downloading brings back remote changes and, with --delete, removes
+    // local files the remote directory does not have.
+    fstn.ok_with_stdin(&["fs", "write", "~:site:index.html"], b"<h1>remote</h1>");
+    local_file(&local, "draft.html", b"wip");
+    let report = summary(&["fs", "sync", "--download", "--delete", site, "~:site"]);
+    assert_eq!(report, json!({"directories": 0, "files": 1, "blobs": 0, "bytes": 15, "unchanged": 1, "removed": 1, "skipped": 0}));
+    assert_eq!(std::fs::read(local.join("index.html")).unwrap(), b"<h1>remote</h1>");
+    assert!(!local.join("draft.html").exists());
+    assert!(local.join(".fstnsync").exists());
+
+    // Unchanged files are compared by the hash the gate reports rather than
+    // read, and nothing is removed below a directory that cannot be listed.
+    fstn.ok(&["fs", "mkfaceted", "~:site", "shared"]);
+    if let Entry::Faceted { facets } = fstn.gateway.state().get("alice", "~:site:shared") {
+        facets.insert("<bob,T>".into(), Entry::dir("bob,T"));
+    }
+    let hidden = local.join("shared/<bob,T>");
+    std::fs::create_dir_all(&hidden).unwrap();
+    local_file(&hidden, "keep.txt", b"mine");
+    fstn.gateway.state().ops.clear();
+    let report = summary(&["fs", "sync", "--download", "--delete", site, "~:site"]);
+    assert_eq!((report["files"].clone(), report["unchanged"].clone(), report["removed"].clone()), (json!(0), json!(2), json!(0)));
+    assert_eq!(report["skipped"], 1);
+    assert!(hidden.join("keep.txt").exists());
+    assert!(!fstn.gateway.state().ops.iter().any(|op| op == "read"));
+
+    let output = fstn.run(&["fs", "sync", "--download", site, "~:site:index.html"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is not a directory"));
+}
+
+#[test]
+fn fs_mkdir_parents_and_rm_recursive() {
+    let fstn = Fstn::logged_in();
+    let report = fstn.json(&["fs", "mkdir", "-p", "--label", "alice,T", "~:a:b", "c"]);
+    assert_eq!(report["created"], json!([["~", "a"], ["~", "a", "b"], ["~", "a", "b", "c"]]));
+    let Entry::Dir { label, .. } = fstn.gateway.state().get("alice", "~:a:b").clone() else { panic!() };
+    assert_eq!(label, "alice,T");
+    assert_eq!(fstn.json(&["fs", "mkdir", "-p", "~:a", "b"])["created"], json!([]));
+    fstn.ok(&["fs", "mkfile", "~:a", "f"]);
+    let output = fstn.run(&["fs", "mkdir", "-p", "~:a:f", "x"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("~:a:f is a file, not a directory"));
+
+    let output = fstn.run(&["fs", "rm", "~:a"]);
+    assert_eq!(output.status.code(), Some(14));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -r"));
+    let output = fstn.run_with_stdin(&["fs", "rm", "-r", "~:a"], b"n\n");
+    assert_eq!(output.status.code(), Some(16));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Remove ~:a and 3 entries below it? [y/N]"));
+    assert!(matches!(fstn.gateway.state().get("alice", "~:a"), Entry::Dir { .. }));
+    let removed = fstn.ok_with_stdin(&["fs", "rm", "-r", "~:a"], b"y\n");
+    assert_eq!(String::from_utf8(removed).unwrap(), "- ~:a:f\n- ~:a:b:c\n- ~:a:b\n- ~:a\n");
+    assert_eq!(fstn.run(&["fs", "rm", "-rf", "~:a"]).status.code(), Some(7));
+
+    fstn.ok(&["fs", "mkfile", "~", "g"]);
+    assert_eq!(fstn.json(&["fs", "rm", "~:g"])["removed"], json!([["~", "g"]]));
+    assert!(String::from_utf8_lossy(&fstn.run(&["fs", "rm", "-rf", "~"]).stderr).contains("refusing to remove ~"));
+    assert!(String::from_utf8_lossy(&fstn.run(&["fs", "rm", "~:fsutil"]).stderr).contains("fsutil gate"));
+}
+
+#[test]
+fn fs_cp_mv_and_ln() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkdir", "-p", "--label", "alice,T", "~:src", "sub"]);
+    fstn.ok(&["fs", "mkfile", "--label", "alice,alice", "~:src", "notes"]);
+    fstn.ok_with_stdin(&["fs", "write", "~:src:notes"], b"hello");
+    fstn.ok(&["fs", "mkfaceted", "~:src", "shared"]);
+
+    let output = fstn.run(&["fs", "cp", "~:src", "~", "copy"]);
+    assert_eq!(output.status.code(), Some(14));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("use -r"));
+    let output = fstn.run(&["fs", "cp", "-r", "--label", "T,T", "~:src", "~", "copy"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("would downgrade its secrecy"));
+    let output = fstn.run(&["fs", "cp", "-r", "~:src", "~:src:sub", "copy"]);
+    assert_eq!(output.status.code(), Some(14));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot copy ~:src into itself"));
+
+    let report = fstn.json(&["fs", "cp", "-r", "~:src", "~", "copy"]);
+    assert_eq!(
+        report["created"],
+        json!([["~", "copy"], ["~", "copy", "notes"], ["~", "copy", "shared"], ["~", "copy", "sub"]])
+    );
+    let Entry::File { label, data } = fstn.gateway.state().get("alice", "~:copy:notes").clone() else { panic!() };
+    assert_eq!((label.as_str(), data.as_slice()), ("alice,alice", &b"hello"[..]));
+    assert_eq!(fstn.json(&["fs", "cp", "-r", "~:src", "~", "copy"])["created"], json!([]));
+
+    let report = fstn.json(&["fs", "mv", "~:copy", "~:src:sub", "moved"]);
+    assert_eq!(report["removed"], json!([["~", "copy"]]));
+    assert!(matches!(fstn.gateway.state().get("alice", "~:src:sub:moved:notes"), Entry::File { .. }));
+    assert_eq!(fstn.json(&["fs", "mv", "~:copy", "~:src:sub", "moved"])["removed"], json!([]));
+
+    // A tree with a directory that cannot be listed is neither copied nor
+    // moved, so nothing below that directory is left behind.
+    fstn.ok(&["fs", "mkdir", "-p", "~:tree", "inner"]);
+    fstn.ok(&["fs", "mkfaceted", "~:tree:inner", "shared"]);
+    if let Entry::Faceted { facets } = fstn.gateway.state().get("alice", "~:tree:inner:shared") {
+        let mut hidden = Entry::dir("bob,T");
+        if let Entry::Dir { entries, .. } = &mut hidden {
+            entries.insert("secret".into(), Entry::File { label: "bob,T".into(), data: b"bob's".to_vec() });
+        }
+        facets.insert("<bob,T>".into(), hidden);
+    }
+    for args in [&["fs", "mv", "~:tree", "~", "moved"][..], &["fs", "cp", "-r", "~:tree", "~", "moved"]] {
+        let output = fstn.run(args);
+        assert_eq!(output.status.code(), Some(5));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("~:tree:inner:shared:<bob,T> could not be listed"), "{}", stderr);
+    }
+    let mut state = fstn.gateway.state();
+    assert!(matches!(state.get("alice", "~:tree:inner:shared:<bob,T>:secret"), Entry::File { .. }));
+    assert!(state.resolve("alice", &["~".into(), "moved".into()]).is_none());
+    drop(state);
+
+    fstn.ok(&["fs", "mkgate", "-l", "alice,T", "-p", "alice", "-c", "alice,T", "-g", "~:fsutil", "~:src", "gate"]);
+    let output = fstn.run(&["fs", "mv", "~:src", "~", "elsewhere"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("~:src:gate is a gate, which cannot be copied"));
+    assert!(matches!(fstn.gateway.state().get("alice", "~:src"), Entry::Dir { .. }));
+
+    let report = fstn.json(&["fs", "ln", "-p", "alice", "-c", "alice,T", "~:src:gate", "~", "alias"]);
+    assert_eq!(report["created"], json!([["~", "alias"]]));
+    let Entry::Gate { label, spec, .. } = fstn.gateway.state().get("alice", "~:alias").clone() else { panic!() };
+    assert_eq!((label.as_str(), &spec["gate"]), ("alice,T", &json!(["~", "src", "gate"])));
+    let report = fstn.json(&["fs", "ln", "-p", "alice", "-c", "alice,T", "~:src:gate", "~", "alias"]);
+    assert_eq!(report["created"], json!([]));
+    let output = fstn.run(&["fs", "ln", "-p", "alice", "-c", "alice,T", "~:src:notes", "~", "bad"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only gates can be linked"));
+}
+
+#[test]
+fn fs_gates() {
+    let fstn = Fstn::logged_in();
+    let dir = tempfile::tempdir().unwrap();
+    let app = local_file(dir.path(), "app.img", b"app image");
+    let app = format!("@{}", app.display());
+    fstn.ok(&[
+        "fs", "mkgate", "-l", "alice,alice", "-p", "alice", "-c", "alice,T", "-m", "128",
+        "-a", &app, "-k", "kernels:vmlinux", "-r", "runtimes:python", "~", "thumbnail",
+    ]);
+    {
+        let mut state = fstn.gateway.state();
+        let Entry::Gate { spec, blobs, .. } = state.get("alice", "~:thumbnail").clone() else {
+            panic!("not a gate");
+        };
+        assert_eq!(spec["memory"], 128);
+        assert_eq!(spec["app_image"], json!(["~", "app.img-03630d7923a0"]));
+        assert_eq!(spec["kernel"], json!(["kernels", "vmlinux"]));
+        assert!(blobs.is_empty());
+        let image = Entry::Blob { label: "alice,alice".into(), data: b"app image".to_vec() };
+        assert_eq!(state.get("alice", "~:app.img-03630d7923a0"), &image);
+    }
+
+    fstn.ok(&["fs", "upgate", "-m", "256", "~:thumbnail"]);
+    {
+        let mut state = fstn.gateway.state();
+        let Entry::Gate { spec, .. } = state.get("alice", "~:thumbnail") else {
+            panic!("not a gate");
+        };
+        assert_eq!(spec["memory"], 256);
+    }
+
+    fstn.ok(&["fs", "mkgate", "-l", "T,T", "-p", "T", "-c", "T,T", "-g", "~:thumbnail", "~", "alias"]);
+    assert_eq!(fstn.ok_with_stdin(&["fs", "invoke", "~:alias", "size=64"], b"img"), b"img");
+}
+
+#[test]
+fn fs_faceted_and_services() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkfaceted", "~", "shared"]);
+    let service = json!({
+        "privilege": "alice",
+        "clearance": "alice,T",
+        "taint": "T,T",
+        "url": "https://example.com/api",
+        "verb": 1,
+        "headers": {},
+    });
+    fstn.ok_with_stdin(&["fs", "mksvc", "~", "api"], service.to_string().as_bytes());
+    let kinds: Vec<_> = fstn.json(&["fs", "ls", "~"])["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["name"].as_str().unwrap().to_string(), e["kind"].as_str().unwrap().to_string()))
+        .collect();
+    assert!(kinds.contains(&("shared".into(), "faceted".into())));
+    assert!(kinds.contains(&("api".into(), "service".into())));
+}
+
+#[test]
+fn fs_facets() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkfaceted", "~", "shared"]);
+    fstn.ok(&["fs", "mkfile", "--facet", "alice,T", "~:shared", "notes"]);
+    fstn.ok_with_stdin(&["fs", "write", "--facet", "alice,T", "~:shared:notes"], b"hello");
+    fstn.ok(&["fs", "mkdir", "~:shared:<T,T>", "public"]);
+    if let Entry::Faceted { facets } = fstn.gateway.state().get("alice", "~:shared") {
+        facets.insert("<bob,T>".into(), Entry::dir("bob,T"));
+    }
+    assert_eq!(fstn.ok(&["fs", "read", "~:shared:<alice,T>:notes"]), b"hello");
+    assert_eq!(fstn.ok(&["fs", "read", "--facet", "alice,T", "~:shared:notes"]), b"hello");
+    assert_eq!(fstn.ok(&["fs", "ls", "--facet", "T,T", "~:shared"]), b"public\n");
+
+    let facets = fstn.json(&["fs", "facets", "~:shared"]);
+    assert_eq!(
+        facets["facets"],
+        json!([
+            {"path": ["~", "shared", "<T,T>"], "label": "T,T", "visible": true},
+            {"path": ["~", "shared", "<alice,T>"], "label": "alice,T", "visible": true},
+            {"path": ["~", "shared", "<bob,T>"], "label": "bob,T", "visible": false},
+        ])
+    );
+    assert_eq!(fstn.ok(&["fs", "facets", "~:shared"]), b"<T,T>\n<alice,T>\n<bob,T> (hidden)\n");
+    assert_eq!(fstn.run(&["fs", "facets", "~"]).status.code(), Some(14));
+
+    let output = fstn.run(&["fs", "ls", "--facet", "bob,T", "~:shared"]);
+    assert_eq!(output.status.code(), Some(6));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("~:shared:<bob,T> is labeled bob,T, which your clearance alice,T cannot see"), "{}", stderr);
+    let output = fstn.run(&["fs", "mkfile", "~:shared:<bob,T>", "x"]);
+    assert_eq!(output.status.code(), Some(6));
+    // Missing entries in visible facets are still just missing.
+    assert_eq!(fstn.run(&["fs", "read", "~:shared:<alice,T>:missing"]).status.code(), Some(7));
+}
+
+#[test]
+fn fs_ls_recursive() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkdir", "~", "docs"]);
+    fstn.ok(&["fs", "mkfile", "~:docs", "notes"]);
+    fstn.ok(&["fs", "mkdir", "~:docs", "old"]);
+    fstn.ok(&["fs", "mkfile", "~:docs:old", "a"]);
+    fstn.ok(&["fs", "mkfaceted", "~", "shared"]);
+    fstn.ok(&["fs", "mkfile", "~:shared:<alice,T>", "f"]);
+
+    let paths = |args: &[&str]| -> Vec<String> {
+        fstn.json(args)["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| serde_json::from_value::<fstn::FsPath>(e["path"].clone()).unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        paths(&["fs", "ls", "-R", "~"]),
+        ["~:docs", "~:docs:notes", "~:docs:old", "~:docs:old:a", "~:fsutil", "~:shared", "~:shared:<alice,T>", "~:shared:<alice,T>:f"]
+    );
+    assert_eq!(paths(&["fs", "ls", "--depth", "2", "~:docs"]), ["~:docs:notes", "~:docs:old", "~:docs:old:a"]);
+    assert_eq!(paths(&["fs", "ls", "--depth", "2", "~"]).len(), 6);
+    assert_eq!(paths(&["fs", "ls", "-R", "--kind", "file", "~"]), ["~:docs:notes", "~:docs:old:a", "~:shared:<alice,T>:f"]);
+    assert_eq!(paths(&["fs", "ls", "--sort", "kind", "~:docs"]), ["~:docs:old", "~:docs:notes"]);
+
+    let tree = fstn.ok(&["fs", "ls", "-R", "~:docs"]);
+    assert_eq!(String::from_utf8(tree).unwrap(), "notes\nold\n  a\n");
+    let long = String::from_utf8(fstn.ok(&["fs", "ls", "-l", "~:docs"])).unwrap();
+    assert_eq!(long, "notes  file       T,T\nold    directory  T,T\n");
+    let table = String::from_utf8(fstn.ok(&["fs", "ls", "~:docs", "--output", "table"])).unwrap();
+    assert!(table.starts_with("NAME   KIND       LABEL\n"), "{}", table);
+    assert_eq!(fstn.run(&["fs", "ls", "--kind", "folder", "~"]).status.code(), Some(2));
+}
+
+#[test]
+fn fs_masquerade() {
+    let fstn = Fstn::logged_in();
+    fstn.gateway.user("bob");
+    fstn.ok(&["fs", "--masquerade", "bob", "ping"]);
+}
+
+#[test]
+fn labels_are_validated_before_sending() {
+    let fstn = Fstn::logged_in();
+    let output = fstn.run(&["fs", "mkdir", "--label", "alice|,T", "~", "docs"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("empty principal name"));
+    let output = fstn.run(&["fs", "mkgate", "-l", "T,T", "-p", "T", "-c", "alice", "-g", "~:g", "~", "x"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(fstn.gateway.state().ops.is_empty());
+}
+
+#[test]
+fn fs_paths() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["fs", "mkdir", "~", "a:b"]);
+    fstn.ok(&["fs", "mkfile", "~:a\\:b", "notes"]);
+    fstn.ok_with_stdin(&["fs", "write", "~:a\\:b:.:old:..:notes"], b"hi");
+    let requests = fstn.gateway.state().requests.clone();
+    assert_eq!(requests[0], json!({"op": "mkdir", "args": {"label": "T,T", "base": ["~"], "name": "a:b"}}));
+    assert_eq!(requests.last().unwrap()["args"]["path"], json!(["~", "a:b", "notes"]));
+    let url = format!("fstn://{}/home/<alice,alice>/a%3Ab/notes", fstn.gateway.url.trim_start_matches("http://"));
+    assert_eq!(fstn.ok(&["fs", "read", &url]), b"hi");
+
+    let output = fstn.run(&["fs", "read", "fstn://elsewhere.example/~/a"]);
+    assert_eq!(output.status.code(), Some(14));
+    let output = fstn.run(&["fs", "read", "~:.."]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = fstn.run(&["fs", "mkdir", "~", ".."]);
+    assert_eq!(output.status.code(), Some(2));
+
+    assert_eq!(fstn.run(&["fs", "cat", "~:#a\\:b"]).status.code(), Some(7));
+    fstn.ok_with_stdin(&["fs", "invoke", "~:@fsutil"], b"{\"op\":\"ping\",\"args\":{}}");
+}
+
+#[test]
+fn profiles() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["config", "set", "server", &fstn.gateway.url]);
+    fstn.ok(&["config", "set", "label", "alice,alice"]);
+    fstn.ok(&["--profile", "offline", "config", "set", "server", "http://127.0.0.1:9"]);
+    fstn.ok(&["--profile", "offline", "config", "set", "connect-timeout", "1"]);
+
+    assert!(fstn.run_bare(&["ping"]).status.success());
+    assert_eq!(fstn.run_bare(&["--profile", "offline", "ping"]).status.code(), Some(10));
+    assert_eq!(fstn.ok(&["config", "get", "label"]), b"alice,alice\n");
+    assert_eq!(fstn.run(&["config", "get", "masquerade"]).status.code(), Some(7));
+
+    // New entries get the profile's label unless one is given.
+    fstn.ok(&["fs", "mkdir", "~", "private"]);
+    fstn.ok(&["fs", "mkdir", "--label", "T,T", "~", "public"]);
+    let listing = fstn.json(&["fs", "ls", "~"]);
+    let label = |name: &str| {
+        let entries = listing["entries"].as_array().unwrap();
+        entries.iter().find(|e| e["name"] == name).unwrap()["label"].clone()
+    };
+    assert_eq!(label("private"), "alice,alice");
+    assert_eq!(label("public"), "T,T");
+
+    fstn.ok(&["config", "use", "offline"]);
+    assert_eq!(fstn.run_bare(&["ping"]).status.code(), Some(10));
+    let list = fstn.json(&["config", "list"]);
+    assert_eq!(list["active"], "offline");
+    assert_eq!(list["profiles"]["default"]["label"], "alice,alice");
+    assert_eq!(list["profiles"]["offline"]["connect-timeout"], 1);
+    let table = String::from_utf8(fstn.ok(&["config", "list", "--output", "table"])).unwrap();
+    assert!(table.lines().any(|l| l.starts_with("*  offline")), "{}", table);
+
+    assert_eq!(fstn.run(&["config", "use", "nope"]).status.code(), Some(12));
+    assert_eq!(fstn.run(&["--profile", "nope", "whoami"]).status.code(), Some(12));
+    assert_eq!(fstn.run(&["config", "set", "timeout", "soon"]).status.code(), Some(12));
+    assert_eq!(fstn.run(&["config", "get", "colour"]).status.code(), Some(2));
+}
+
+#[test]
+fn credentials_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fstn = Fstn::logged_in();
+    let path = fstn.config_dir().join("credentials");
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let output = fstn.run(&["whoami"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("accessible by other users (mode 644)"), "{}", stderr);
+
+    fstn.ok(&["config", "set", "credential-store", "keyring"]);
+    if cfg!(not(feature = "keyring")) {
+        assert_eq!(fstn.run(&["delegate", "alice", "--save"]).status.code(), Some(12));
+    }
+}
+
+#[test]
+fn credential_management() {
+    let fstn = Fstn::logged_in();
+    fstn.ok(&["delegate", "alice", "--save"]);
+    let list = fstn.json(&["auth", "list"]);
+    let credentials = list["credentials"].as_array().unwrap();
+    assert_eq!(credentials.len(), 2);
+    let alice = credentials.iter().find(|c| c["user"] == "alice").unwrap();
+    assert_eq!(alice["server"], fstn.gateway.url.as_str());
+    assert_eq!(alice["delegated-from"], "default");
+    assert!(alice.get("token").is_some());
+
+    // `auth use` picks the user when --user is omitted.
+    assert_eq!(fstn.run(&["auth", "use", "mallory"]).status.code(), Some(3));
+    fstn.ok(&["auth", "use", "alice"]);
+    assert_eq!(fstn.json(&["whoami"])["identity"]["login"], "alice");
+    let table = String::from_utf8(fstn.ok(&["auth", "list", "--output", "table"])).unwrap();
+    assert!(table.lines().any(|l| l.starts_with('*') && l.contains("alice") && l.contains("default")), "{}", table);
+
+    // Logging out the default user falls back to the `default` user.
+    assert_eq!(fstn.json(&["logout"])["users"], serde_json::json!(["alice"]));
+    assert_eq!(fstn.json(&["whoami"])["identity"]["login"], "alice");
+    fstn.ok(&["delegate", "alice", "--save"]);
+    fstn.ok(&["delegate", "bob", "--save"]);
+    assert_eq!(fstn.run(&["--user", "carol", "logout"]).status.code(), Some(3));
+    assert_eq!(fstn.json(&["--user", "alice", "logout"])["users"], serde_json::json!(["alice"]));
+    assert_eq!(fstn.json(&["logout", "--all"])["users"], serde_json::json!(["bob", "default"]));
+    assert!(fstn.json(&["auth", "list"])["credentials"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn inspect_tokens() {
+    let fstn = Fstn::logged_in();
+    let doc = fstn.json(&["auth", "inspect"]);
+    assert_eq!(doc["source"], "server");
+    assert_eq!(doc["principal"], "alice");
+    assert_eq!(doc["clearance"], "alice,T");
+
+    let part = |v: serde_json::Value| base64::encode_config(v.to_string(), base64::URL_SAFE_NO_PAD);
+    let expired = format!(
+        "{}.{}.sig",
+        part(serde_json::json!({"alg": "ES256"})),
+        part(serde_json::json!({"sub": "alice", "privilege": "alice/ci", "bootstrap": false, "exp": 1_000_000})),
+    );
+    fstn.gateway.state().tokens.insert(expired.clone(), "alice".into());
+    let doc = fstn.json(&["auth", "inspect", &expired]);
+    assert_eq!(doc["source"], "local");
+    assert_eq!(doc["privilege"], "alice/ci");
+    assert_eq!(doc["expires_at"], 1_000_000);
+    let text = String::from_utf8(fstn.ok(&["auth", "inspect", &expired])).unwrap();
+    assert!(text.contains("expired"), "{}", text);
+
+    fstn.ok_with_stdin(&["--user", "ci", "login", "--with-token"], expired.as_bytes());
+    let output = fstn.run(&["--user", "ci", "whoami"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("expired") && stderr.contains("fstn login"), "{}", stderr);
+}
+
+#[test]
+fn legacy_credentials_are_migrated() {
+    let fstn = Fstn::new();
+    let token = fstn.gateway.user("alice");
+    std::fs::create_dir_all(fstn.config_dir()).unwrap();
+    std::fs::write(
+        fstn.config_dir().join("credentials"),
+        format!("server = \"{}\"\nalice = \"{}\"\n", fstn.gateway.url, token),
+    )
+    .unwrap();
+    let list = fstn.json(&["auth", "list"]);
+    assert_eq!(list["migrated"], serde_json::json!(["alice"]));
+    assert_eq!(list["credentials"][0]["server"], fstn.gateway.url.as_str());
+    let credentials = std::fs::read_to_string(fstn.config_dir().join("credentials")).unwrap();
+    assert!(!credentials.starts_with("alice"), "{}", credentials);
+    assert_eq!(fstn.json(&["--user", "alice", "whoami"])["identity"]["login"], "alice");
+
+    // Entries saved for another server are listed as fallbacks for any
+    // server, and logging out here removes them.
+    std::fs::write(
+        fstn.config_dir().join("credentials"),
+        format!("server = \"https://elsewhere.example\"\nalice = \"{}\"\nbob = \"{}\"\n", token, token),
+    )
+    .unwrap();
+    let list = fstn.json(&["auth", "list"]);
+    let fallbacks: Vec<_> = list["credentials"].as_array().unwrap().iter().filter(|c| c["server"] == "*").collect();
+    assert_eq!(fallbacks.iter().map(|c| c["user"].as_str().unwrap()).collect::<Vec<_>>(), ["alice", "bob"]);
+    assert_eq!(fstn.json(&["--user", "bob", "logout"])["users"], serde_json::json!(["bob"]));
+    assert_eq!(fstn.json(&["logout", "--all"])["users"], serde_json::json!(["alice"]));
+    assert_eq!(fstn.run(&["--user", "alice", "whoami"]).status.code(), Some(3));
+}
+
+#[test]
+fn login_with_token_file() {
+    let fstn = Fstn::new();
+    let token = fstn.gateway.user("alice");
+    let file = local_file(fstn.config.path(), "token", format!("{}\n", token).as_bytes());
+    fstn.ok(&["login", "--with-token", file.to_str().unwrap()]);
+    assert_eq!(fstn.json(&["whoami"])["identity"]["login"], "alice");
+
+    let empty = local_file(fstn.config.path(), "empty", b"  \n");
+    assert_eq!(fstn.run(&["login", "--with-token", empty.to_str().unwrap()]).status.code(), Some(8));
+    assert_eq!(fstn.run(&["login", "--with-token", "--no-browser"]).status.code(), Some(2));
+}
+
+#[test]
+fn browser_login() {
+    use std::io::{BufRead, BufReader};
+
+    let fstn = Fstn::new();
+    fstn.gateway.user("alice");
+    fstn.gateway.state().browser_user = Some("alice".into());
+    // Without a browser to open, fstn prints the login page's address, and
+    // the test follows it and the redirect back as a browser would.
+    let mut child = fstn
+        .command(&["login", "--timeout", "20"])
+        .env("BROWSER", "/nonexistent/browser")
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    while !line.contains("open http") {
+        line.clear();
+        assert!(stderr.read_line(&mut line).unwrap() > 0, "no login page address");
+    }
+    let login_page = line.split("open ").nth(1).and_then(|rest| rest.split_whitespace().next()).unwrap();
+    let redirect = browse(login_page);
+    let location = redirect.lines().find_map(|l| l.strip_prefix("Location: ")).unwrap();
+    assert!(browse(location).starts_with("HTTP/1.1 200 OK"));
+    assert!(child.wait().unwrap().success());
+    assert_eq!(fstn.json(&["whoami"])["identity"]["login"], "alice");
+
+    // A browser that never comes back times out.
+    let output = fstn.command(&["login", "--timeout", "1"]).env("BROWSER", "true").output().unwrap();
+    assert_eq!(output.status.code(), Some(8));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--no-browser"));
+}
+
+/// Sends a GET for the `http` URL `url`, as a browser would, and returns the
+/// raw response.
+fn browse(url: &str) -> String {
+    use std::io::{Read, Write};
+
+    let rest = url.strip_prefix("http://").unwrap();
+    let (host, target) = rest.split_at(rest.find('/').unwrap());
+    let mut stream = std::net::TcpStream::connect(host).unwrap();
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", target, host).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
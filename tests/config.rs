@@ -0,0 +1,56 @@
+use fstn::config::{Config, Profile, ProfileKey, DEFAULT_PROFILE};
+use fstn::FstnError;
+
+#[test]
+fn profiles_round_trip_through_toml() {
+    let text = r#"
+        profile = "staging"
+
+        [profiles.staging]
+        server = "https://staging.example"
+        label = "alice,alice"
+        connect-timeout = 5
+
+        [profiles.local]
+        server = "http://localhost:8080"
+    "#;
+    let config: Config = toml::from_str(text).unwrap();
+    assert_eq!(config.selected(None), "staging");
+    assert_eq!(config.selected(Some("local".into())), "local");
+    let staging = config.get("staging").unwrap();
+    assert_eq!(staging.label, Some("alice,alice".parse().unwrap()));
+    assert_eq!(staging.connect_timeout(), Some(std::time::Duration::from_secs(5)));
+    assert_eq!(staging.timeout(), None);
+
+    let back: Config = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+    assert_eq!(back, config);
+}
+
+#[test]
+fn missing_profiles() {
+    let mut config = Config::default();
+    assert_eq!(config.selected(None), DEFAULT_PROFILE);
+    assert_eq!(config.get(DEFAULT_PROFILE).unwrap(), Profile::default());
+    assert!(matches!(config.get("prod"), Err(FstnError::Config(_))));
+    assert!(config.use_profile("prod").is_err());
+    config.profiles.insert("prod".into(), Profile::default());
+    config.use_profile("prod").unwrap();
+    assert_eq!(config.profile.as_deref(), Some("prod"));
+}
+
+#[test]
+fn settings_are_validated() {
+    let mut profile = Profile::default();
+    profile.set(ProfileKey::Server, "http://localhost:8080").unwrap();
+    profile.set(ProfileKey::Timeout, "30").unwrap();
+    profile.set(ProfileKey::Label, "alice,T").unwrap();
+    assert_eq!(profile.get(ProfileKey::Timeout).as_deref(), Some("30"));
+    assert_eq!(profile.get(ProfileKey::Label).as_deref(), Some("alice,T"));
+
+    assert!(matches!(profile.set(ProfileKey::Server, "not a url"), Err(FstnError::Config(_))));
+    assert!(matches!(profile.set(ProfileKey::Timeout, "soon"), Err(FstnError::Config(_))));
+    assert!(matches!(profile.set(ProfileKey::Label, "alice|"), Err(FstnError::InvalidLabel(_))));
+
+    profile.set(ProfileKey::Timeout, "").unwrap();
+    assert_eq!(profile.get(ProfileKey::Timeout), None);
+}
@@ -0,0 +1,118 @@
+use std::os::unix::fs::PermissionsExt;
+
+use fstn::client::DEFAULT_SERVER;
+use fstn::credentials::{CredentialsFile, Store, ANY_SERVER};
+use fstn::FstnError;
+
+fn mode(path: &std::path::Path) -> u32 {
+    std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn saved_tokens_are_private() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = CredentialsFile::new(dir.path().join("fstn/credentials"));
+    file.save(Store::File, "https://a.example", "alice", "token-a", None).unwrap();
+    assert_eq!(mode(file.path()), 0o600);
+    assert_eq!(file.load("https://a.example", "alice").unwrap(), "token-a");
+
+    // A rewrite tightens a file someone loosened.
+    std::fs::set_permissions(file.path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+    file.save(Store::File, "https://a.example", "bob", "token-b", None).unwrap();
+    assert_eq!(mode(file.path()), 0o600);
+    assert_eq!(file.load("https://a.example", "alice").unwrap(), "token-a");
+}
+
+#[test]
+fn lookups() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("credentials");
+    std::fs::write(
+        &path,
+        "legacy = \"old-token\"\n[\"https://a.example\"]\nalice = \"token-a\"\n[\"https://a.example\".carol]\nkeyring = true\n",
+    )
+    .unwrap();
+    let file = CredentialsFile::new(&path);
+    assert_eq!(file.load("https://a.example", "alice").unwrap(), "token-a");
+    // Legacy flat entries stand in for any server, as they always have.
+    assert_eq!(file.load(DEFAULT_SERVER, "legacy").unwrap(), "old-token");
+    assert_eq!(file.load("https://b.example", "legacy").unwrap(), "old-token");
+    assert!(matches!(file.load("https://b.example", "alice"), Err(FstnError::NoCredentials { .. })));
+    if cfg!(not(feature = "keyring")) {
+        assert!(matches!(file.load("https://a.example", "carol"), Err(FstnError::Config(_))));
+        assert!(file.save(Store::Keyring, "https://a.example", "dave", "t", None).is_err());
+    }
+}
+
+#[test]
+fn concurrent_saves_keep_every_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("credentials");
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let file = CredentialsFile::new(&path);
+            std::thread::spawn(move || {
+                for j in 0..5 {
+                    file.save(Store::File, "https://a.example", &format!("user{}-{}", i, j), "t", None).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let table = CredentialsFile::new(&path).read().unwrap();
+    assert_eq!(table["https://a.example"].as_table().unwrap().len(), 40);
+    let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[test]
+fn migration_defaults_and_pruning() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("credentials");
+    std::fs::write(
+        &path,
+        "alice = \"token-a\"\nbob = \"token-b\"\n[global]\nserver = \"https://a.example\"\n[\"https://a.example\"]\nbob = \"newer-b\"\n",
+    )
+    .unwrap();
+    let file = CredentialsFile::new(&path);
+    assert_eq!(file.migrate().unwrap(), ["alice", "bob"]);
+    assert!(file.migrate().unwrap().is_empty());
+    let table = file.read().unwrap();
+    assert!(table.get("alice").is_none());
+    // An entry already under the server wins over the legacy one, which
+    // is kept for other servers until the user is removed.
+    assert_eq!(file.load("https://a.example", "bob").unwrap(), "newer-b");
+    assert_eq!(file.load("https://b.example", "bob").unwrap(), "token-b");
+    assert_eq!(file.remove("https://b.example", &["bob".to_string()]).unwrap(), ["bob"]);
+    assert!(matches!(file.load("https://b.example", "bob"), Err(FstnError::NoCredentials { .. })));
+    assert_eq!(file.load("https://a.example", "bob").unwrap(), "newer-b");
+
+    file.save(Store::File, "https://a.example", "alice/photos", "token-p", Some("alice")).unwrap();
+    file.save(Store::File, "https://a.example", "alice/photos/2024", "token-q", Some("alice/photos")).unwrap();
+    assert!(file.set_default_user("https://a.example", "nobody").is_err());
+    file.set_default_user("https://a.example", "alice").unwrap();
+    assert_eq!(file.default_user("https://a.example").unwrap().as_deref(), Some("alice"));
+
+    let listed = file.list().unwrap();
+    assert_eq!(listed.len(), 5);
+    assert!(listed.iter().any(|c| (c.server.as_str(), c.user.as_str()) == (ANY_SERVER, "alice")));
+    let photos = listed.iter().find(|c| c.user == "alice/photos").unwrap();
+    assert_eq!(photos.credential.delegated_from.as_deref(), Some("alice"));
+    assert!(photos.credential.age().unwrap() < 60);
+    assert!(listed.iter().find(|c| c.user == "alice").unwrap().default);
+
+    assert!(file.prune(None).unwrap().is_empty());
+    assert_eq!(file.remove("https://a.example", &["alice".to_string()]).unwrap(), ["alice"]);
+    assert_eq!(file.default_user("https://a.example").unwrap(), None);
+    // Removing a parent orphans its delegations, transitively.
+    let pruned: Vec<String> = file.prune(None).unwrap().into_iter().map(|c| c.user).collect();
+    assert_eq!(pruned, ["alice/photos", "alice/photos/2024"]);
+    let left: Vec<String> = file.list().unwrap().into_iter().map(|c| c.user).collect();
+    assert_eq!(left, ["bob"]);
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use fstn::fsutil::{
+    Chunk, DirEntry, EntryKind, FsFailure, FsReply, FsRequest, FsResponse, GateArgs, MkSvc, MkgateArgs, UpgateArgs,
+    Verb,
+};
+use fstn::label::{Component, Label};
+use fstn::FsPath;
+use serde_json::json;
+
+fn path(p: &str) -> FsPath {
+    p.parse().unwrap()
+}
+
+fn round_trip(request: FsRequest, expected: serde_json::Value) {
+    let wire = serde_json::to_value(&request).unwrap();
+    assert_eq!(wire, expected);
+    let back: FsRequest = serde_json::from_value(wire).unwrap();
+    assert_eq!(back, request);
+}
+
+#[test]
+fn requests_round_trip() {
+    round_trip(FsRequest::Ping {}, json!({"op": "ping", "args": {}}));
+    round_trip(FsRequest::Ls { path: path("~:a") }, json!({"op": "ls", "args": {"path": ["~", "a"]}}));
+    round_trip(
+        FsRequest::Unlink { base: path("~"), name: "a".into() },
+        json!({"op": "unlink", "args": {"base": ["~"], "name": "a"}}),
+    );
+    round_trip(
+        FsRequest::Mkdir { base: path("~"), name: "d".into(), label: Label::public() },
+        json!({"op": "mkdir", "args": {"base": ["~"], "name": "d", "label": "T,T"}}),
+    );
+    round_trip(
+        FsRequest::Mkfile { base: path("~"), name: "f".into(), label: Label::public() },
+        json!({"op": "mkfile", "args": {"base": ["~"], "name": "f", "label": "T,T"}}),
+    );
+    round_trip(
+        FsRequest::Write { path: path("~:f"), data: b"hello".to_vec(), offset: None },
+        json!({"op": "write", "args": {"path": ["~", "f"], "data": "aGVsbG8="}}),
+    );
+    round_trip(
+        FsRequest::Write { path: path("~:f"), data: b"hi".to_vec(), offset: Some(5) },
+        json!({"op": "write", "args": {"path": ["~", "f"], "data": "aGk=", "offset": 5}}),
+    );
+    round_trip(
+        FsRequest::Read { path: path("~:f"), offset: None, length: None },
+        json!({"op": "read", "args": {"path": ["~", "f"]}}),
+    );
+    round_trip(
+        FsRequest::Read { path: path("~:f"), offset: Some(4), length: Some(2) },
+        json!({"op": "read", "args": {"path": ["~", "f"], "offset": 4, "length": 2}}),
+    );
+    round_trip(
+        FsRequest::Mkblob { label: Label::public(), base: path("~") },
+        json!({"op": "mkblob", "args": {"label": "T,T", "base": ["~"]}}),
+    );
+    round_trip(FsRequest::Cat { path: path("~:b") }, json!({"op": "cat", "args": {"path": ["~", "b"]}}));
+    round_trip(FsRequest::Stat { path: path("~:b") }, json!({"op": "stat", "args": {"path": ["~", "b"]}}));
+    round_trip(
+        FsRequest::Upload {
+            label: Label::public(),
+            base: path("~"),
+            name: "b".into(),
+            sha256: "ab".into(),
+            size: 10,
+            offset: 4,
+        },
+        json!({"op": "upload", "args": {"label": "T,T", "base": ["~"], "name": "b", "sha256": "ab", "size": 10, "offset": 4}}),
+    );
+    round_trip(
+        FsRequest::Mkfaceted { base: path("~"), name: "x".into() },
+        json!({"op": "mkfaceted", "args": {"base": ["~"], "name": "x"}}),
+    );
+    round_trip(
+        FsRequest::Invoke {
+            path: path("~:g"),
+            sync: true,
+            payload: b"{}".to_vec(),
+            params: HashMap::from([("k".to_string(), "v".to_string())]),
+        },
+        json!({"op": "invoke", "args": {"path": ["~", "g"], "sync": true, "payload": "e30=", "params": {"k": "v"}}}),
+    );
+}
+
+#[test]
+fn gate_requests_round_trip() {
+    round_trip(
+        FsRequest::Mkgate(MkgateArgs {
+            label: Label::public(),
+            privilege: "alice".parse().unwrap(),
+            clearance: "alice,T".parse().unwrap(),
+            base: path("~"),
+            name: "g".into(),
+            function: GateArgs {
+                memory: Some(128),
+                app_image: None,
+                kernel: Some(path("kernels:vmlinux")),
+                runtime: Some(path("runtimes:python")),
+                gate: None,
+            },
+        }),
+        json!({"op": "mkgate", "args": {
+            "label": "T,T",
+            "privilege": "alice",
+            "clearance": "alice,T",
+            "base": ["~"],
+            "name": "g",
+            "memory": 128,
+            "app_image": null,
+            "kernel": ["kernels", "vmlinux"],
+            "runtime": ["runtimes", "python"],
+            "gate": null,
+        }}),
+    );
+    round_trip(
+        FsRequest::Upgate(UpgateArgs {
+            privilege: None,
+            clearance: Some(Label::public()),
+            path: path("~:g"),
+            function: GateArgs { gate: Some(path("~:h")), ..Default::default() },
+        }),
+        json!({"op": "upgate", "args": {
+            "privilege": null,
+            "clearance": "T,T",
+            "path": ["~", "g"],
+            "memory": null,
+            "app_image": null,
+            "kernel": null,
+            "runtime": null,
+            "gate": ["~", "h"],
+        }}),
+    );
+    round_trip(
+        FsRequest::Mksvc(MkSvc {
+            base: Some(path("~")),
+            name: Some("svc".into()),
+            label: None,
+            privilege: Component::dc_true(),
+            clearance: Label::public(),
+            taint: Label::public(),
+            url: "https://example.com".into(),
+            verb: Verb::Post,
+            headers: HashMap::new(),
+        }),
+        json!({"op": "mksvc", "args": {
+            "base": ["~"],
+            "name": "svc",
+            "label": null,
+            "privilege": "T",
+            "clearance": "T,T",
+            "taint": "T,T",
+            "url": "https://example.com",
+            "verb": 2,
+            "headers": {},
+        }}),
+    );
+}
+
+fn reply(value: serde_json::Value) -> FsReply {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn decodes_listings() {
+    let ls = FsRequest::Ls { path: path("~") };
+    let names = ls.decode(reply(json!({"success": true, "value": ["a", "b"]}))).unwrap();
+    assert_eq!(
+        names,
+        FsResponse::Listing(vec![
+            DirEntry { name: "a".into(), kind: None, label: None },
+            DirEntry { name: "b".into(), kind: None, label: None },
+        ])
+    );
+    let entries = ls
+        .decode(reply(json!({"success": true, "value": {
+            "b": "file",
+            "a": {"kind": "directory", "label": "T,T"},
+        }})))
+        .unwrap();
+    assert_eq!(
+        entries,
+        FsResponse::Listing(vec![
+            DirEntry { name: "a".into(), kind: Some(EntryKind::Directory), label: Some(Label::public()) },
+            DirEntry { name: "b".into(), kind: Some(EntryKind::File), label: None },
+        ])
+    );
+    assert!(ls.decode(reply(json!({"success": true, "value": {"a": "folder"}}))).is_err());
+    assert!(ls.decode(reply(json!({"success": true, "value": {"a": {"kind": "file", "label": "<"}}}))).is_err());
+}
+
+#[test]
+fn decodes_contents() {
+    let read = FsRequest::Read { path: path("~:f"), offset: None, length: None };
+    assert_eq!(
+        read.decode(reply(json!({"success": true, "value": "aGVsbG8="}))).unwrap(),
+        FsResponse::Contents(b"hello".to_vec())
+    );
+    let invoke = FsRequest::Invoke { path: path("~:g"), sync: true, payload: vec![], params: HashMap::new() };
+    assert_eq!(
+        invoke.decode(reply(json!({"data": "aGk="}))).unwrap(),
+        FsResponse::Contents(b"hi".to_vec())
+    );
+}
+
+#[test]
+fn decodes_received() {
+    let upload = FsRequest::Upload {
+        label: "T,T".parse().unwrap(),
+        base: path("~"),
+        name: "b".into(),
+        sha256: "ab".into(),
+        size: 10,
+        offset: 0,
+    };
+    let received = upload.decode(reply(json!({"success": true, "value": {"received": 4}}))).unwrap();
+    assert_eq!(received, FsResponse::Received(4));
+    assert!(upload.decode(reply(json!({"success": true}))).is_err());
+}
+
+#[test]
+fn decodes_stat() {
+    let stat = FsRequest::Stat { path: path("~:g") };
+    let value = json!({"kind": "gate", "label": "T,T", "privilege": "alice", "memory": 128, "gate": ["~", "h"]});
+    let FsResponse::Stat(stat) = stat.decode(reply(json!({"success": true, "value": value}))).unwrap() else {
+        panic!("not a stat");
+    };
+    assert_eq!(stat.kind, Some(EntryKind::Gate));
+    assert_eq!((stat.memory, stat.gate), (Some(128), Some(path("~:h"))));
+    assert_eq!(stat.size, None);
+}
+
+#[test]
+fn decodes_chunks() {
+    let read = FsRequest::Read { path: path("~:f"), offset: Some(0), length: Some(2) };
+    let chunk = read.decode_chunk(reply(json!({"success": true, "value": {"data": "aGk=", "size": 5}}))).unwrap();
+    assert_eq!(chunk, Chunk { data: "aGk=".into(), size: Some(5) });
+    // A gate that ignores the range sends the whole file.
+    let chunk = read.decode_chunk(reply(json!({"success": true, "value": "aGVsbG8="}))).unwrap();
+    assert_eq!(chunk, Chunk { data: "aGVsbG8=".into(), size: None });
+    assert!(read.decode_chunk(reply(json!({"success": true, "value": {"data": "aGk="}}))).is_err());
+    assert_eq!(read.decode_chunk(reply(json!({"success": false}))).unwrap_err(), FsFailure { op: "read", error: None });
+}
+
+#[test]
+fn decodes_failures() {
+    let read = FsRequest::Read { path: path("~:f"), offset: None, length: None };
+    let failure = read.decode(reply(json!({"success": false}))).unwrap_err();
+    assert_eq!(failure, FsFailure { op: "read", error: None });
+
+    let mkdir = FsRequest::Mkdir { base: path("~"), name: "d".into(), label: Label::public() };
+    let failure = mkdir.decode(reply(json!({"success": false, "error": "exists"}))).unwrap_err();
+    assert_eq!(failure.to_string(), "mkdir failed: exists");
+
+    let invoke = FsRequest::Invoke { path: path("~:g"), sync: true, payload: vec![], params: HashMap::new() };
+    let failure = invoke.decode(reply(json!({"error": {"code": 1}}))).unwrap_err();
+    assert_eq!(failure.error, Some(json!({"code": 1})));
+}
+
+#[test]
+fn responses_round_trip() {
+    for response in [
+        FsResponse::Pong,
+        FsResponse::Done(None),
+        FsResponse::Done(Some(json!({"created": 1}))),
+        FsResponse::Listing(vec![DirEntry { name: "a".into(), kind: Some(EntryKind::File), label: None }]),
+        FsResponse::Contents(b"\x00\xffdata".to_vec()),
+    ] {
+        let wire = serde_json::to_string(&response).unwrap();
+        assert_eq!(serde_json::from_str::<FsResponse>(&wire).unwrap(), response);
+    }
+}
@@ -0,0 +1,72 @@
+use fstn::label::{Component, Label};
+
+fn label(s: &str) -> Label {
+    s.parse().unwrap()
+}
+
+fn component(s: &str) -> Component {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_and_prints() {
+    for s in ["T,T", "F,T", "alice,alice", "alice|bob,T", "alice&bob,carol", "alice/photos,T"] {
+        assert_eq!(label(s).to_string(), s);
+    }
+    // Clauses and principals are kept in canonical order, and redundant
+    // clauses are dropped.
+    assert_eq!(label(" bob | alice , T ").to_string(), "alice|bob,T");
+    assert_eq!(label("alice|bob&alice,T").to_string(), "alice,T");
+}
+
+#[test]
+fn rejects_malformed_labels() {
+    for s in ["", "T", "T,T,T", "alice,", ",T", "alice||bob,T", "al ice,T", "alice/,T", "T|alice,T", "<a>,T"] {
+        assert!(s.parse::<Label>().is_err(), "{:?} parsed", s);
+    }
+}
+
+#[test]
+fn implication() {
+    assert!(component("F").implies(&component("alice")));
+    assert!(component("alice").implies(&component("T")));
+    assert!(!component("T").implies(&component("alice")));
+    assert!(component("alice&bob").implies(&component("alice")));
+    assert!(component("alice").implies(&component("alice|bob")));
+    assert!(!component("alice|bob").implies(&component("alice")));
+    assert!(component("alice").implies(&component("alice/photos")));
+    assert!(!component("alice/photos").implies(&component("alice")));
+}
+
+#[test]
+fn flows() {
+    assert!(label("T,T").flows_to(&label("alice,T")));
+    assert!(!label("alice,T").flows_to(&label("T,T")));
+    assert!(label("alice,alice").flows_to(&label("alice,T")));
+    assert!(!label("alice,T").flows_to(&label("alice,alice")));
+    assert!(label("alice|bob,T").flows_to(&label("alice,T")));
+    assert!(!label("alice,T").flows_to(&label("bob,T")));
+    assert!(label("alice,T").flows_to_with_privilege(&label("T,T"), &component("alice")));
+    assert!(!label("alice,T").flows_to_with_privilege(&label("T,T"), &component("bob")));
+}
+
+#[test]
+fn join_and_meet() {
+    let a = label("alice,alice");
+    let b = label("bob,bob");
+    let join = a.join(&b);
+    assert_eq!(join.to_string(), "alice&bob,alice|bob");
+    assert!(a.flows_to(&join) && b.flows_to(&join));
+    let meet = a.meet(&b);
+    assert_eq!(meet.to_string(), "alice|bob,alice&bob");
+    assert!(meet.flows_to(&a) && meet.flows_to(&b));
+    assert_eq!(label("T,T").join(&label("F,F")), label("F,T"));
+}
+
+#[test]
+fn serializes_as_string() {
+    let l = label("alice|bob,T");
+    assert_eq!(serde_json::to_string(&l).unwrap(), "\"alice|bob,T\"");
+    assert_eq!(serde_json::from_str::<Label>("\"alice|bob,T\"").unwrap(), l);
+    assert!(serde_json::from_str::<Label>("\"nope\"").is_err());
+}
@@ -0,0 +1,33 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use fstn::login::LoopbackLogin;
+use fstn::Client;
+
+fn get(redirect: &str, query: &str) -> String {
+    let address = redirect.trim_start_matches("http://").trim_end_matches("/callback");
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET /callback{} HTTP/1.1\r\nHost: {}\r\n\r\n", query, address).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn loopback_checks_state() {
+    let login = LoopbackLogin::start().unwrap();
+    let client = Client::new("https://faasten.example", "alice").unwrap();
+    let url = reqwest::Url::parse(&login.login_url(&client).unwrap()).unwrap();
+    assert_eq!(url.path(), "/login/cas");
+    let param = |key: &str| url.query_pairs().find(|(k, _)| k == key).unwrap().1.into_owned();
+    let redirect = param("redirect_uri");
+    let state = param("state");
+    assert!(redirect.starts_with("http://127.0.0.1:"));
+    assert_eq!(state.len(), 32);
+
+    let waiter = std::thread::spawn(move || login.wait(Duration::from_secs(10)));
+    assert!(get(&redirect, "?token=stolen&state=guess").starts_with("HTTP/1.1 400"));
+    assert!(get(&redirect, &format!("?token=token-alice&state={}", state)).starts_with("HTTP/1.1 200"));
+    assert_eq!(waiter.join().unwrap().unwrap(), "token-alice");
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use fstn::fsutil::{DirEntry, EntryKind, FsResponse};
+use fstn::output::{OutputMode, Report};
+use fstn::credentials::Store;
+use fstn::{FsPath, FstnError, Identity};
+
+fn render_bytes(report: &Report, mode: OutputMode) -> Vec<u8> {
+    let mut out = Vec::new();
+    report.render(mode, &mut out).unwrap();
+    out
+}
+
+fn render(report: &Report, mode: OutputMode) -> String {
+    String::from_utf8(render_bytes(report, mode)).unwrap()
+}
+
+fn listing() -> Report {
+    Report::Fs {
+        op: "ls",
+        result: FsResponse::Listing(vec![
+            DirEntry { name: "fsutil".into(), kind: Some(EntryKind::Gate), label: Some("alice,alice".parse().unwrap()) },
+            DirEntry { name: "notes".into(), kind: None, label: None },
+        ]),
+    }
+}
+
+#[test]
+fn text_is_passthrough() {
+    assert_eq!(render(&listing(), OutputMode::Text), "fsutil\nnotes\n");
+    let contents = Report::Fs { op: "cat", result: FsResponse::Contents(b"raw\xff".to_vec()) };
+    assert_eq!(render_bytes(&contents, OutputMode::Text), b"raw\xff");
+}
+
+#[test]
+fn whoami_summarizes() {
+    let identity = Identity {
+        login: "alice".into(),
+        privilege: Some("alice".parse().unwrap()),
+        clearance: None,
+        home: FsPath::home_of("alice").unwrap(),
+        fsutil: FsPath::home_of("alice").unwrap().child("fsutil"),
+    };
+    let whoami = Report::Whoami {
+        identity,
+        server: "https://a.example".into(),
+        user: "alice".into(),
+        store: Some(Store::Keyring),
+        profile: "default".into(),
+        fsutil_exists: false,
+    };
+    let text = render(&whoami, OutputMode::Text);
+    assert!(text.starts_with("login:      alice\nprivilege:  alice\nclearance:  -\n"), "{}", text);
+    assert!(text.contains("fsutil:     home:<alice,alice>:fsutil (missing)\n"), "{}", text);
+    assert!(text.contains("credential: alice (keyring)\n"), "{}", text);
+}
+
+#[test]
+fn json_is_tagged_by_command() {
+    let json: serde_json::Value = serde_json::from_str(&render(&listing(), OutputMode::Json)).unwrap();
+    assert_eq!(json["command"], "fs");
+    assert_eq!(json["op"], "ls");
+    assert_eq!(json["result"]["kind"], "listing");
+    assert_eq!(json["result"]["value"][0]["name"], "fsutil");
+
+    let ping = Report::Ping { target: "gateway", elapsed: Duration::from_millis(12) };
+    let json: serde_json::Value = serde_json::from_str(&render(&ping, OutputMode::Json)).unwrap();
+    assert_eq!(json["command"], "ping");
+    assert_eq!(json["elapsed_ms"], 12.0);
+}
+
+#[test]
+fn table_aligns_columns() {
+    assert_eq!(
+        render(&listing(), OutputMode::Table),
+        "NAME    KIND  LABEL\n\
+         fsutil  gate  alice,alice\n\
+         notes   -     -\n"
+    );
+}
+
+#[test]
+fn errors_render_as_json() {
+    let mut out = Vec::new();
+    let error = FstnError::NotFound("~:missing".into());
+    fstn::output::render_error(&error, OutputMode::Json, &mut out).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json["error"]["kind"], "not-found");
+    assert_eq!(json["error"]["exit_code"], 7);
+}
+
+#[test]
+fn sizes_use_binary_units() {
+    assert_eq!(fstn::output::size_string(0), "0 B");
+    assert_eq!(fstn::output::size_string(1023), "1023 B");
+    assert_eq!(fstn::output::size_string(1536), "1.5 KiB");
+    assert_eq!(fstn::output::size_string(3 << 30), "3.0 GiB");
+    assert_eq!(fstn::output::size_string(5 << 40), "5120.0 GiB");
+}
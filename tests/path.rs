@@ -0,0 +1,85 @@
+use fstn::path::{FsPath, PathComponent};
+use fstn::Label;
+
+fn parse(s: &str) -> FsPath {
+    s.parse().unwrap_or_else(|e| panic!("{}: {}", s, e))
+}
+
+fn name(s: &str) -> PathComponent {
+    PathComponent::Name(s.into())
+}
+
+fn facet(s: &str) -> PathComponent {
+    PathComponent::Facet(s.parse::<Label>().unwrap())
+}
+
+#[test]
+fn parses_components() {
+    assert_eq!(parse("~:docs:notes").components(), [PathComponent::Home, name("docs"), name("notes")]);
+    assert_eq!(
+        parse("home:<alice,alice>:@fsutil").components(),
+        [name("home"), facet("alice,alice"), PathComponent::Gate("fsutil".into())]
+    );
+    assert_eq!(parse("~:#app.img").components(), [PathComponent::Home, PathComponent::Blob("app.img".into())]);
+    assert_eq!(parse("home:<alice/photos|bob,T>").components(), [name("home"), facet("alice/photos|bob,T")]);
+    assert!(parse("").is_root());
+    assert_eq!(FsPath::home_of("alice").unwrap(), parse("home:<alice,alice>"));
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(parse("~:a\\:b").components(), [PathComponent::Home, name("a:b")]);
+    assert_eq!(parse("\\~:\\@x:\\#y:\\<z>:\\.\\.").components(), [name("~"), name("@x"), name("#y"), name("<z>"), name("..")]);
+    assert_eq!(parse("back\\\\slash").components(), [name("back\\slash")]);
+    for s in ["~:a\\:b", "\\~:\\@x:\\#y:\\<z>", "\\..", "~:@g:#b:<a,T>", "back\\\\slash"] {
+        assert_eq!(parse(s).to_string(), s);
+        assert_eq!(parse(&parse(s).to_string()), parse(s));
+    }
+}
+
+#[test]
+fn normalizes() {
+    assert_eq!(parse("~::docs:.:notes:"), parse("~:docs:notes"));
+    assert_eq!(parse("~:docs:old:..:notes"), parse("~:docs:notes"));
+    assert_eq!(parse("home:<alice,alice>:.."), parse("home"));
+    assert_eq!(parse("~:docs:..").to_string(), "~");
+}
+
+#[test]
+fn rejects_malformed() {
+    for bad in ["..", "~:..:x", "a:~", "~x", "home:<alice", "home:<alice>", "@", "~:#", "trailing\\"] {
+        assert!(bad.parse::<FsPath>().is_err(), "{} should not parse", bad);
+    }
+    assert!(fstn::path::name("..").is_err());
+    assert!(fstn::path::name("").is_err());
+    assert_eq!(fstn::path::name("a:b").unwrap(), "a:b");
+    for reserved in ["~", "<a,T>", "@g", "#b"] {
+        assert!(fstn::path::name(reserved).is_err(), "{}", reserved);
+    }
+}
+
+#[test]
+fn urls() {
+    let path = parse("fstn://faasten.example:8080/home/<alice/photos,T>/a%3Ab/%40g");
+    assert_eq!(path.server(), Some("faasten.example:8080"));
+    assert_eq!(
+        path.components(),
+        [name("home"), facet("alice/photos,T"), name("a:b"), PathComponent::Gate("g".into())]
+    );
+    let url = path.to_string();
+    assert!(url.starts_with("fstn://faasten.example:8080/home/"));
+    assert_eq!(parse(&url), path);
+    assert_eq!(parse("fstn://host/~/docs/../x").to_url("host"), "fstn://host/~/x");
+    assert_eq!(parse("fstn://host").components(), []);
+    assert!("fstn:///x".parse::<FsPath>().is_err());
+    assert!("fstn://host/a%zz".parse::<FsPath>().is_err());
+}
+
+#[test]
+fn wire_form() {
+    let path = parse("~:a\\:b:c\\\\d:<alice,T>:@g");
+    let wire = serde_json::to_value(&path).unwrap();
+    assert_eq!(wire, serde_json::json!(["~", "a:b", "c\\d", "<alice,T>", "@g"]));
+    assert_eq!(serde_json::from_value::<FsPath>(wire).unwrap(), path);
+    assert!(serde_json::from_value::<FsPath>(serde_json::json!(["<oops"])).is_err());
+}
@@ -0,0 +1,786 @@
+//! An in-process mock of the Faasten gateway for integration tests.
+//!
+//! [`MockGateway`] serves the gateway's HTTP API on a loopback port, backed by
+//! an in-memory file system whose `fsutil` ops behave like the real gate's.
+//! Connections reach it through a relay that can drop them partway through a
+//! request, as a failing network would.
+//! [`Fstn`] runs the `fstn` binary against it with an isolated config
+//! directory.
+//!
+//! Requests are read as plain JSON, the way a gate written in another
+//! language would, rather than with fstn's own types: a path is a list of
+//! strings, `"~"`, `"<label>"`, `"@gate"`, `"#blob"` or a literal name, and a
+//! label is a string.
+
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// An entry in the mock file system.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entry {
+    Dir { label: String, entries: BTreeMap<String, Entry> },
+    File { label: String, data: Vec<u8> },
+    Faceted { facets: BTreeMap<String, Entry> },
+    Gate { label: String, spec: Value, blobs: BTreeMap<String, Vec<u8>> },
+    Blob { label: String, data: Vec<u8> },
+    Service { label: String, spec: Value },
+}
+
+impl Entry {
+    pub fn dir(label: &str) -> Entry {
+        Entry::Dir { label: label.into(), entries: BTreeMap::new() }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Entry::Dir { .. } => "directory",
+            Entry::File { .. } => "file",
+            Entry::Faceted { .. } => "faceted",
+            Entry::Gate { .. } => "gate",
+            Entry::Blob { .. } => "blob",
+            Entry::Service { .. } => "service",
+        }
+    }
+
+    fn label(&self) -> Option<&str> {
+        match self {
+            Entry::Dir { label, .. }
+            | Entry::File { label, .. }
+            | Entry::Gate { label, .. }
+            | Entry::Blob { label, .. }
+            | Entry::Service { label, .. } => Some(label),
+            Entry::Faceted { .. } => None,
+        }
+    }
+
+    /// The entry the wire component `component` names within this one.
+    /// Facets spring into existence when first looked up.
+    fn child_mut(&mut self, component: &str) -> Option<&mut Entry> {
+        match self {
+            Entry::Dir { entries, .. } => match component.split_at(component.len().min(1)) {
+                ("@", name) => entries.get_mut(name).filter(|e| matches!(e, Entry::Gate { .. })),
+                ("#", name) => entries.get_mut(name).filter(|e| matches!(e, Entry::Blob { .. })),
+                ("<", _) => None,
+                _ => entries.get_mut(component),
+            },
+            Entry::Faceted { facets } => {
+                let label = component.strip_prefix('<')?.strip_suffix('>')?;
+                Some(facets.entry(component.to_string()).or_insert_with(|| Entry::dir(label)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The gateway's mutable state, shared with the serving thread.
+#[derive(Debug)]
+pub struct State {
+    /// Maps bearer tokens to the login they were issued to.
+    pub tokens: HashMap<String, String>,
+    /// Delegations made so far: (parent token, privilege, issued token).
+    pub delegations: Vec<(String, String, String)>,
+    pub root: Entry,
+    /// The `op` of every fsutil request received, in order.
+    pub ops: Vec<String>,
+    /// Every fsutil request received, as sent.
+    pub requests: Vec<Value>,
+    /// Who is signed in to the login page, as if through CAS.
+    pub browser_user: Option<String>,
+    /// Whether fsutil ignores write offsets and read ranges, as gates from
+    /// before chunked transfers do.
+    pub unchunked: bool,
+    /// Ops fsutil answers as unknown, as older gates do.
+    pub unknown_ops: Vec<&'static str>,
+    /// Whether `stat` reports hashes that do not match the contents, as if
+    /// they were corrupted in storage.
+    pub wrong_hashes: bool,
+    /// The bytes of unfinished uploads, by login, base, name and SHA-256.
+    pub uploads: HashMap<(String, String, String, String), Vec<u8>>,
+    /// How many more pieces of uploads fsutil takes before failing, as if
+    /// the connection dropped.
+    pub upload_budget: Option<usize>,
+    /// How many more bytes of requests reach the gateway before the
+    /// connection they arrive on is dropped.
+    pub drop_after: Option<usize>,
+}
+
+impl State {
+    /// Resolves the wire components `path`, expanding a leading `~` to
+    /// `login`'s home.
+    pub fn resolve(&mut self, login: &str, path: &[String]) -> Option<&mut Entry> {
+        let mut entry = &mut self.root;
+        for component in expand(login, path) {
+            entry = entry.child_mut(&component)?;
+        }
+        Some(entry)
+    }
+
+    /// The entry at `path`, written with `:` between components and no
+    /// escapes, panicking if there is none.
+    pub fn get(&mut self, login: &str, path: &str) -> &mut Entry {
+        let components: Vec<String> = path.split(':').map(String::from).collect();
+        self.resolve(login, &components).unwrap_or_else(|| panic!("no entry at {}", path))
+    }
+
+    fn add_user(&mut self, login: &str) {
+        let home = self.resolve(login, &["~".to_string()]).unwrap();
+        if let Entry::Dir { entries, .. } = home {
+            entries.entry("fsutil".into()).or_insert_with(|| Entry::Gate {
+                label: format!("{},{}", login, login),
+                spec: json!({"privilege": login, "clearance": format!("{},T", login)}),
+                blobs: BTreeMap::new(),
+            });
+        }
+    }
+}
+
+fn expand(login: &str, path: &[String]) -> Vec<String> {
+    match path {
+        [home, rest @ ..] if home == "~" => {
+            let home = ["home".to_string(), format!("<{},{}>", login, login)];
+            home.into_iter().chain(rest.iter().cloned()).collect()
+        }
+        components => components.to_vec(),
+    }
+}
+
+pub struct MockGateway {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+    server: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+    relay: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    relay_thread: Option<JoinHandle<()>>,
+}
+
+impl MockGateway {
+    pub fn start() -> MockGateway {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let upstream = server.server_addr().to_ip().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let relay = listener.local_addr().unwrap();
+        let url = format!("http://{}", relay);
+        let mut root = Entry::dir("T,T");
+        if let Entry::Dir { entries, .. } = &mut root {
+            entries.insert("home".into(), Entry::Faceted { facets: BTreeMap::new() });
+        }
+        let state = Arc::new(Mutex::new(State {
+            tokens: HashMap::new(),
+            delegations: Vec::new(),
+            root,
+            ops: Vec::new(),
+            requests: Vec::new(),
+            browser_user: None,
+            unchunked: false,
+            unknown_ops: Vec::new(),
+            wrong_hashes: false,
+            uploads: HashMap::new(),
+            upload_budget: None,
+            drop_after: None,
+        }));
+        let thread = {
+            let server = server.clone();
+            let state = state.clone();
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            })
+        };
+        let stopped = Arc::new(AtomicBool::new(false));
+        let relay_thread = {
+            let state = state.clone();
+            let stopped = stopped.clone();
+            std::thread::spawn(move || {
+                for client in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(client) = client {
+                        let state = state.clone();
+                        std::thread::spawn(move || relay_connection(&state, client, upstream));
+                    }
+                }
+            })
+        };
+        MockGateway { url, state, server, thread: Some(thread), relay, stopped, relay_thread: Some(relay_thread) }
+    }
+
+    /// Registers `login` with a home directory and `fsutil` gate, returning
+    /// a token for it.
+    pub fn user(&self, login: &str) -> String {
+        let token = format!("token-{}", login);
+        let mut state = self.state();
+        state.tokens.insert(token.clone(), login.into());
+        state.add_user(login);
+        token
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // Wake the relay so that it sees it is stopped.
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect(self.relay);
+        if let Some(thread) = self.relay_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Passes the connection `client` on to the gateway at `upstream` and its
+/// replies back, dropping both ends once [`State::drop_after`] runs out.
+fn relay_connection(state: &Mutex<State>, mut client: TcpStream, upstream: SocketAddr) {
+    let Ok(mut server) = TcpStream::connect(upstream) else { return };
+    let (Ok(mut from_server), Ok(mut to_client)) = (server.try_clone(), client.try_clone()) else { return };
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut from_server, &mut to_client);
+        let _ = to_client.shutdown(Shutdown::Write);
+    });
+    let mut buffer = [0; 8192];
+    loop {
+        let read = match client.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        let mut state = state.lock().unwrap();
+        match state.drop_after {
+            Some(left) if left < read => {
+                state.drop_after = None;
+                drop(state);
+                let _ = server.write_all(&buffer[..left]);
+                let _ = server.shutdown(Shutdown::Both);
+                let _ = client.shutdown(Shutdown::Both);
+                return;
+            }
+            Some(left) => state.drop_after = Some(left - read),
+            None => {}
+        }
+        drop(state);
+        if server.write_all(&buffer[..read]).is_err() {
+            break;
+        }
+    }
+    let _ = server.shutdown(Shutdown::Write);
+}
+
+fn respond(request: tiny_http::Request, status: u16, body: impl Into<Vec<u8>>) {
+    let _ = request.respond(tiny_http::Response::from_data(body.into()).with_status_code(status));
+}
+
+fn header(request: &tiny_http::Request, name: &str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str().to_string())
+}
+
+fn handle(state: &Mutex<State>, mut request: tiny_http::Request) {
+    let url = request.url().to_string();
+    let mut body = Vec::new();
+    if request.as_reader().read_to_end(&mut body).is_err() {
+        return respond(request, 400, "unreadable body");
+    }
+    match url.as_str() {
+        "/faasten/ping" | "/faasten/ping/scheduler" => return respond(request, 200, "pong"),
+        _ => {}
+    }
+
+    let mut state = state.lock().unwrap();
+    if let Some(query) = url.strip_prefix("/login/cas?") {
+        let param = |key: &str| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+                .map(percent_decode)
+        };
+        let (Some(login), Some(redirect), Some(nonce)) = (state.browser_user.clone(), param("redirect_uri"), param("state"))
+        else {
+            return respond(request, 400, "bad login request");
+        };
+        let location = format!("{}?token=token-{}&state={}", redirect, login, nonce);
+        let header = tiny_http::Header::from_bytes("Location", location).unwrap();
+        let _ = request.respond(tiny_http::Response::empty(302).with_header(header));
+        return;
+    }
+    let token = header(&request, "authorization").and_then(|h| h.strip_prefix("Bearer ").map(String::from));
+    let login = match token.as_ref().and_then(|t| state.tokens.get(t)) {
+        Some(login) => login.clone(),
+        None => return respond(request, 401, "unauthorized"),
+    };
+
+    if url == "/me" {
+        let me = json!({
+            "login": login,
+            "privilege": login,
+            "clearance": format!("{},T", login),
+        });
+        return respond(request, 200, me.to_string());
+    }
+    if url == "/faasten/delegate" {
+        let args: Value = serde_json::from_slice(&body).unwrap_or_default();
+        let privilege = args["component"].as_str().unwrap_or_default().to_string();
+        let issued = format!("token-{}-{}", privilege, state.delegations.len());
+        state.tokens.insert(issued.clone(), login);
+        state.delegations.push((token.unwrap(), privilege, issued.clone()));
+        return respond(request, 200, issued);
+    }
+    if let Some(gate) = url.strip_prefix("/faasten/invoke/") {
+        let gate = percent_decode(gate);
+        let content_type = header(&request, "content-type").unwrap_or_default();
+        let (payload, blobs) = match content_type.split_once("boundary=") {
+            Some((_, boundary)) => {
+                let mut payload = Vec::new();
+                let mut blobs = Vec::new();
+                for (name, file_name, data) in multipart(&body, boundary) {
+                    match (name.as_str(), file_name) {
+                        ("payload", _) => payload = data,
+                        ("blob", Some(file_name)) => blobs.push((file_name, data)),
+                        _ => {}
+                    }
+                }
+                (payload, blobs)
+            }
+            None => (body, Vec::new()),
+        };
+        if gate.rsplit(':').next() == Some("fsutil") {
+            let request_json: Value = match serde_json::from_slice(&payload) {
+                Ok(v) => v,
+                Err(e) => return respond(request, 400, e.to_string()),
+            };
+            let reply = fsutil(&mut state, &login, &request_json, blobs);
+            return respond(request, 200, reply.to_string());
+        }
+        // Any other gate echoes its payload.
+        return respond(request, 200, payload);
+    }
+    respond(request, 404, "not found")
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(b) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).unwrap()
+}
+
+/// Splits a multipart body into (field name, file name, data) triples.
+fn multipart(body: &[u8], boundary: &str) -> Vec<(String, Option<String>, Vec<u8>)> {
+    let delimiter = format!("--{}", boundary.trim_matches('"'));
+    let mut parts = Vec::new();
+    for chunk in split_bytes(body, delimiter.as_bytes()).into_iter().skip(1) {
+        if chunk.starts_with(b"--") {
+            break;
+        }
+        let chunk = chunk.strip_prefix(b"\r\n").unwrap_or(chunk);
+        let chunk = chunk.strip_suffix(b"\r\n").unwrap_or(chunk);
+        let Some(split) = chunk.windows(4).position(|w| w == b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&chunk[..split]);
+        let data = chunk[split + 4..].to_vec();
+        let param = |key: &str| {
+            let start = headers.find(&format!("{}=\"", key))? + key.len() + 2;
+            let end = headers[start..].find('"')? + start;
+            Some(headers[start..end].to_string())
+        };
+        if let Some(name) = param(" name") {
+            parts.push((name, param("filename"), data));
+        }
+    }
+    parts
+}
+
+fn split_bytes<'a>(haystack: &'a [u8], needle: &[u8]) -> Vec<&'a [u8]> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if &haystack[i..i + needle.len()] == needle {
+            out.push(&haystack[start..i]);
+            i += needle.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    out.push(&haystack[start..]);
+    out
+}
+
+/// The path `args[key]`, a list of component strings.
+fn path_arg(args: &Value, key: &str) -> Vec<String> {
+    let components = args[key].as_array().map(Vec::as_slice).unwrap_or_default();
+    components
+        .iter()
+        .map(|c| c.as_str().unwrap_or_else(|| panic!("`{}` has a non-string component: {}", key, args[key])).to_string())
+        .collect()
+}
+
+/// Whether the facet component `<secrecy,integrity>` is above `login`'s
+/// clearance of `login,T`, which here means its secrecy is neither `T`
+/// nor `login`.
+fn above_clearance(login: &str, component: &str) -> bool {
+    let Some(label) = component.strip_prefix('<').and_then(|c| c.strip_suffix('>')) else {
+        return false;
+    };
+    let secrecy = label.split(',').next().unwrap_or_default();
+    secrecy != "T" && secrecy != login
+}
+
+fn b64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn unb64(s: &str) -> Vec<u8> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => c - b'A',
+        b'a'..=b'z' => c - b'a' + 26,
+        b'0'..=b'9' => c - b'0' + 52,
+        b'+' => 62,
+        _ => 63,
+    };
+    let mut out = Vec::new();
+    for chunk in s.trim_end_matches('=').as_bytes().chunks(4) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, c)| n | (value(*c) as u32) << (18 - 6 * i));
+        for i in 0..chunk.len().saturating_sub(1) {
+            out.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    out
+}
+
+fn failure(error: impl Into<Value>) -> Value {
+    json!({"success": false, "error": error.into()})
+}
+
+fn ok() -> Value {
+    json!({"success": true})
+}
+
+/// Inserts `entry` as `name` in the directory at `base`.
+fn create(state: &mut State, login: &str, base: &[String], name: &str, entry: Entry) -> Value {
+    match state.resolve(login, base) {
+        Some(Entry::Dir { entries, .. }) => {
+            if entries.contains_key(name) {
+                failure("exists")
+            } else {
+                entries.insert(name.into(), entry);
+                ok()
+            }
+        }
+        _ => json!({"success": false}),
+    }
+}
+
+/// Executes one fsutil request against the in-memory file system.
+fn fsutil(state: &mut State, login: &str, request: &Value, blobs: Vec<(String, Vec<u8>)>) -> Value {
+    let op = request["op"].as_str().unwrap_or_default().to_string();
+    let mut args = request["args"].clone();
+    if let (true, Some(args)) = (state.unchunked, args.as_object_mut()) {
+        args.remove("offset");
+        args.remove("length");
+    }
+    let args = &args;
+    let label = args["label"].as_str().unwrap_or("T,T").to_string();
+    let name = args["name"].as_str().unwrap_or_default().to_string();
+    state.ops.push(op.clone());
+    state.requests.push(request.clone());
+    if state.unknown_ops.contains(&op.as_str()) {
+        return failure(format!("unknown op {}", op));
+    }
+    // Facets above the caller's clearance fail without a reason.
+    if ["path", "base"].iter().flat_map(|key| path_arg(args, key)).any(|c| above_clearance(login, &c)) {
+        return json!({"success": false});
+    }
+    match op.as_str() {
+        "ping" => ok(),
+        "ls" => match state.resolve(login, &path_arg(args, "path")) {
+            Some(Entry::Dir { entries, .. }) => {
+                let listing: serde_json::Map<String, Value> = entries
+                    .iter()
+                    .map(|(name, e)| (name.clone(), json!({"kind": e.kind(), "label": e.label()})))
+                    .collect();
+                json!({"success": true, "value": listing})
+            }
+            Some(Entry::Faceted { facets }) => {
+                let listing: serde_json::Map<String, Value> = facets
+                    .iter()
+                    .map(|(name, e)| (name.clone(), json!({"kind": e.kind(), "label": e.label()})))
+                    .collect();
+                json!({"success": true, "value": listing})
+            }
+            _ => json!({"success": false}),
+        },
+        "unlink" => match state.resolve(login, &path_arg(args, "base")) {
+            Some(Entry::Dir { entries, .. }) => match entries.remove(&name) {
+                Some(_) => ok(),
+                None => json!({"success": false}),
+            },
+            _ => json!({"success": false}),
+        },
+        "mkdir" => create(state, login, &path_arg(args, "base"), &name, Entry::dir(&label)),
+        "mkfile" => {
+            let file = Entry::File { label, data: Vec::new() };
+            create(state, login, &path_arg(args, "base"), &name, file)
+        }
+        "mkfaceted" => {
+            let faceted = Entry::Faceted { facets: BTreeMap::new() };
+            create(state, login, &path_arg(args, "base"), &name, faceted)
+        }
+        "write" => match (state.resolve(login, &path_arg(args, "path")), args["offset"].as_u64()) {
+            (Some(Entry::File { data, .. }), None) => {
+                *data = unb64(args["data"].as_str().unwrap_or_default());
+                ok()
+            }
+            (Some(Entry::File { data, .. }), Some(offset)) if offset as usize <= data.len() => {
+                data.truncate(offset as usize);
+                data.extend(unb64(args["data"].as_str().unwrap_or_default()));
+                json!({"success": true, "value": {"size": data.len()}})
+            }
+            _ => json!({"success": false}),
+        },
+        "read" => match (state.resolve(login, &path_arg(args, "path")), args["offset"].as_u64()) {
+            (Some(Entry::File { data, .. }), None) => json!({"success": true, "value": b64(data)}),
+            (Some(Entry::File { data, .. }), Some(offset)) => {
+                let start = (offset as usize).min(data.len());
+                let end = args["length"].as_u64().map_or(data.len(), |l| (start + l as usize).min(data.len()));
+                json!({"success": true, "value": {"data": b64(&data[start..end]), "size": data.len()}})
+            }
+            _ => json!({"success": false}),
+        },
+        "cat" => match state.resolve(login, &path_arg(args, "path")) {
+            Some(Entry::Blob { data, .. }) => json!({"success": true, "value": b64(data)}),
+            _ => json!({"success": false}),
+        },
+        "stat" => match (state.wrong_hashes, state.resolve(login, &path_arg(args, "path"))) {
+            (wrong_hashes, Some(entry)) => {
+                let mut stat = json!({"kind": entry.kind(), "label": entry.label()});
+                match entry {
+                    Entry::File { data, .. } | Entry::Blob { data, .. } => {
+                        stat["size"] = data.len().into();
+                        let data: &[u8] = if wrong_hashes { b"corrupted" } else { data };
+                        stat["sha256"] = format!("{:x}", Sha256::digest(data)).into();
+                    }
+                    Entry::Gate { spec, .. } => {
+                        for key in ["privilege", "clearance", "memory", "app_image", "kernel", "runtime", "gate"] {
+                            if !spec[key].is_null() {
+                                stat[key] = spec[key].clone();
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                json!({"success": true, "value": stat})
+            }
+            (_, None) => json!({"success": false}),
+        },
+        "mkblob" => {
+            let base = path_arg(args, "base");
+            let mut created = Vec::new();
+            for (file_name, data) in blobs {
+                let blob = Entry::Blob { label: label.clone(), data };
+                let reply = create(state, login, &base, &file_name, blob);
+                if reply["success"] != true {
+                    return reply;
+                }
+                created.push(file_name);
+            }
+            json!({"success": true, "value": created})
+        }
+        "upload" => {
+            let base = path_arg(args, "base");
+            let sha256 = args["sha256"].as_str().unwrap_or_default().to_string();
+            let key = (login.to_string(), base.join(":"), name.clone(), sha256.clone());
+            if let Some((_, data)) = blobs.into_iter().next() {
+                if state.upload_budget == Some(0) {
+                    return failure("connection dropped");
+                }
+                state.upload_budget = state.upload_budget.map(|n| n - 1);
+                let received = state.uploads.entry(key.clone()).or_default();
+                if args["offset"].as_u64() == Some(received.len() as u64) {
+                    received.extend(data);
+                }
+            }
+            let received = state.uploads.get(&key).map_or(0, Vec::len);
+            if args["size"].as_u64() == Some(received as u64) {
+                let data = state.uploads.remove(&key).unwrap_or_default();
+                if format!("{:x}", Sha256::digest(&data)) != sha256 {
+                    return failure("hash mismatch");
+                }
+                let reply = create(state, login, &base, &name, Entry::Blob { label, data });
+                if reply["success"] != true {
+                    return reply;
+                }
+            }
+            json!({"success": true, "value": {"received": received}})
+        }
+        "mkgate" => {
+            let gate = Entry::Gate { label, spec: args.clone(), blobs: blobs.into_iter().collect() };
+            create(state, login, &path_arg(args, "base"), &name, gate)
+        }
+        "upgate" => match state.resolve(login, &path_arg(args, "path")) {
+            Some(Entry::Gate { spec, blobs: stored, .. }) => {
+                for (key, value) in args.as_object().into_iter().flatten() {
+                    if !value.is_null() {
+                        spec[key] = value.clone();
+                    }
+                }
+                stored.extend(blobs);
+                ok()
+            }
+            _ => json!({"success": false}),
+        },
+        "mksvc" => {
+            let service = Entry::Service { label, spec: args.clone() };
+            create(state, login, &path_arg(args, "base"), &name, service)
+        }
+        "invoke" => match state.resolve(login, &path_arg(args, "path")) {
+            Some(Entry::Gate { .. }) => json!({"success": true, "data": args["payload"]}),
+            _ => failure("no such gate"),
+        },
+        _ => failure(format!("unknown op {}", op)),
+    }
+}
+
+/// Runs the `fstn` binary against a [`MockGateway`] with its own config
+/// directory.
+pub struct Fstn {
+    pub gateway: MockGateway,
+    pub config: tempfile::TempDir,
+}
+
+impl Fstn {
+    pub fn new() -> Fstn {
+        Fstn { gateway: MockGateway::start(), config: tempfile::tempdir().unwrap() }
+    }
+
+    /// A gateway with `alice` already logged in.
+    pub fn logged_in() -> Fstn {
+        let fstn = Fstn::new();
+        let token = fstn.gateway.user("alice");
+        let output = fstn.run_with_stdin(&["login", "--with-token"], token.as_bytes());
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        fstn
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.config.path().join("fstn")
+    }
+
+    /// A command for `fstn args`, pointed at the mock gateway with
+    /// `--server`.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = self.bare_command(&[]);
+        command.arg("--server").arg(&self.gateway.url).args(args);
+        command
+    }
+
+    /// Like [`Fstn::command`], but leaves the server to the configuration.
+    pub fn bare_command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fstn"));
+        command
+            .env("XDG_CONFIG_HOME", self.config.path())
+            .env("HOME", self.config.path())
+            .env_remove("FSTN_SERVER")
+            .env_remove("FSTN_USER")
+            .env_remove("FSTN_PROFILE")
+            .args(args);
+        command
+    }
+
+    pub fn run_bare(&self, args: &[&str]) -> Output {
+        self.bare_command(args).stdin(Stdio::null()).output().unwrap()
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_stdin(args, b"")
+    }
+
+    pub fn run_with_stdin(&self, args: &[&str], stdin: &[u8]) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs `args` and returns stdout, panicking if the command fails.
+    pub fn ok(&self, args: &[&str]) -> Vec<u8> {
+        self.ok_with_stdin(args, b"")
+    }
+
+    pub fn ok_with_stdin(&self, args: &[&str], stdin: &[u8]) -> Vec<u8> {
+        let output = self.run_with_stdin(args, stdin);
+        assert!(
+            output.status.success(),
+            "fstn {:?} exited with {}: {}",
+            args,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+
+    /// Runs `args` with `--output json` and parses the document it prints.
+    pub fn json(&self, args: &[&str]) -> Value {
+        let mut args = args.to_vec();
+        args.extend(["--output", "json"]);
+        serde_json::from_slice(&self.ok(&args)).unwrap()
+    }
+}
+
+/// Writes `contents` to `dir/name`, returning the path.
+pub fn local_file(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
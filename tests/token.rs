@@ -0,0 +1,39 @@
+use fstn::token::{TokenInfo, TokenSource};
+
+fn jwt(claims: serde_json::Value) -> String {
+    let part = |v: &serde_json::Value| base64::encode_config(v.to_string(), base64::URL_SAFE_NO_PAD);
+    format!("{}.{}.signature", part(&serde_json::json!({"alg": "ES256"})), part(&claims))
+}
+
+#[test]
+fn decodes_jwt_claims() {
+    let token = jwt(serde_json::json!({
+        "sub": "alice",
+        "privilege": "alice/photos",
+        "clearance": "alice,T",
+        "bootstrap": true,
+        "iat": 1_000,
+        "exp": 2_000,
+    }));
+    let info = TokenInfo::decode(&token).unwrap();
+    assert_eq!(info.source, TokenSource::Local);
+    assert_eq!(info.principal.as_deref(), Some("alice"));
+    assert_eq!(info.privilege.as_deref(), Some("alice/photos"));
+    assert_eq!(info.clearance.as_deref(), Some("alice,T"));
+    assert_eq!(info.bootstrap, Some(true));
+    assert!(info.is_expired());
+
+    let fresh = TokenInfo::decode(&jwt(serde_json::json!({"sub": "bob", "exp": u32::MAX}))).unwrap();
+    assert!(!fresh.is_expired());
+    assert!(!TokenInfo::decode(&jwt(serde_json::json!({"sub": "bob"}))).unwrap().is_expired());
+}
+
+#[test]
+fn opaque_tokens_need_the_gateway() {
+    assert_eq!(TokenInfo::decode("token-alice"), None);
+    assert_eq!(TokenInfo::decode("a.!!!.c"), None);
+    let me = TokenInfo::from_me(&serde_json::json!({"login": "alice", "privilege": "alice", "clearance": "alice,T"}));
+    assert_eq!(me.source, TokenSource::Server);
+    assert_eq!(me.principal.as_deref(), Some("alice"));
+    assert_eq!(me.expires_at, None);
+}